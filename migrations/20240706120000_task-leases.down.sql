@@ -0,0 +1,3 @@
+DROP INDEX tasks_idx_leased_until;
+ALTER TABLE tasks
+DROP COLUMN leased_until;
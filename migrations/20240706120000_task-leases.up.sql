@@ -0,0 +1,3 @@
+ALTER TABLE tasks
+ADD COLUMN leased_until timestamptz;
+CREATE INDEX tasks_idx_leased_until ON tasks (leased_until);
@@ -0,0 +1,4 @@
+DROP INDEX tasks_idx_failed_at;
+ALTER TABLE tasks
+DROP COLUMN next_attempt_at,
+DROP COLUMN failed_at;
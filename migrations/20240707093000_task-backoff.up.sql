@@ -0,0 +1,9 @@
+ALTER TABLE tasks
+ADD COLUMN next_attempt_at timestamptz,
+ADD COLUMN failed_at timestamptz;
+CREATE INDEX tasks_idx_failed_at ON tasks (failed_at);
+
+-- tasks that exceeded the retry limit are considered permanently failed
+UPDATE tasks
+SET failed_at = now()
+WHERE retries >= 5;
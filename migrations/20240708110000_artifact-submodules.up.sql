@@ -0,0 +1,14 @@
+CREATE TABLE artifact_submodules (
+    chksum VARCHAR NOT NULL,
+    path VARCHAR NOT NULL,
+    url VARCHAR NOT NULL,
+    commit VARCHAR NOT NULL,
+
+    CONSTRAINT fk_chksum
+        FOREIGN KEY(chksum)
+        REFERENCES artifacts(chksum)
+        ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX artifact_submodules_idx_uniq ON artifact_submodules (chksum, path);
+CREATE INDEX artifact_submodules_idx_commit ON artifact_submodules (commit);
@@ -0,0 +1,13 @@
+CREATE TABLE git_signatures (
+    object VARCHAR NOT NULL,
+    commit VARCHAR NOT NULL,
+    kind VARCHAR NOT NULL,
+    format VARCHAR,
+    status VARCHAR NOT NULL,
+    fingerprint VARCHAR,
+    required BOOLEAN NOT NULL,
+    verified_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX git_signatures_idx_uniq ON git_signatures (object);
+CREATE INDEX git_signatures_idx_commit ON git_signatures (commit);
@@ -0,0 +1,10 @@
+CREATE TABLE git_tags (
+    remote VARCHAR NOT NULL,
+    tag VARCHAR NOT NULL,
+    commit VARCHAR NOT NULL,
+    first_seen timestamptz NOT NULL DEFAULT NOW(),
+    last_seen timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX git_tags_idx_uniq ON git_tags (remote, tag, commit);
+CREATE INDEX git_tags_idx_commit ON git_tags (commit);
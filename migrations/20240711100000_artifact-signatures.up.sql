@@ -0,0 +1,11 @@
+CREATE TABLE artifact_signatures (
+    chksum VARCHAR NOT NULL,
+    vendor VARCHAR NOT NULL,
+    url VARCHAR NOT NULL,
+    signature_url VARCHAR NOT NULL,
+    status VARCHAR NOT NULL,
+    fingerprint VARCHAR,
+    verified_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX artifact_signatures_idx_uniq ON artifact_signatures (chksum, vendor, signature_url);
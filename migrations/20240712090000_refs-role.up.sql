@@ -0,0 +1 @@
+ALTER TABLE refs ADD COLUMN role VARCHAR;
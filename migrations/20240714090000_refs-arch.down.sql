@@ -0,0 +1 @@
+ALTER TABLE refs DROP COLUMN arch;
@@ -0,0 +1,10 @@
+CREATE TABLE package_repos (
+    vendor VARCHAR NOT NULL,
+    package VARCHAR NOT NULL,
+    version VARCHAR NOT NULL,
+    repo VARCHAR NOT NULL,
+    first_seen timestamptz NOT NULL DEFAULT NOW(),
+    last_seen timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX package_repos_idx_uniq ON package_repos (vendor, package, version, repo);
@@ -0,0 +1,14 @@
+CREATE TABLE alias_hints (
+    alias_from VARCHAR NOT NULL,
+    alias_to VARCHAR NOT NULL,
+    reason VARCHAR,
+    first_seen timestamptz NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT fk_artifact
+        FOREIGN KEY(alias_to)
+        REFERENCES artifacts(chksum)
+        ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX alias_hints_idx_uniq ON alias_hints (alias_from, alias_to);
+CREATE INDEX alias_hints_idx_to ON alias_hints (alias_to);
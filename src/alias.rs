@@ -16,6 +16,9 @@ pub async fn run(args: &args::AddRef) -> Result<()> {
         package: args.package.clone(),
         version: args.version.clone(),
         filename: args.filename.clone(),
+        role: None,
+        repo: None,
+        arch: None,
     })
     .await?;
 
@@ -15,7 +15,7 @@ impl SourcesIndex {
         let mut pkgs = Vec::new();
 
         let mut package = None;
-        let mut in_checksums_sha256_section = false;
+        let mut section = None;
         for line in sources.lines() {
             if let Some(value) = line.strip_prefix("Package: ") {
                 package = Some(SourcePkg {
@@ -36,12 +36,10 @@ impl SourcesIndex {
                 if let Some(package) = package.take() {
                     pkgs.push(package);
                 }
-            } else if line.trim_end() == "Checksums-Sha256:" {
-                in_checksums_sha256_section = true;
             } else if let Some(line) = line.strip_prefix(' ') {
-                if !in_checksums_sha256_section {
+                let Some(section) = section else {
                     continue;
-                }
+                };
 
                 let Some(package) = package.as_mut() else {
                     continue;
@@ -58,13 +56,23 @@ impl SourcesIndex {
                     .parse()
                     .map_err(|_err| APTError::ParseError(ParseError))?;
 
-                package.checksums_sha256.push(ReleaseHash {
+                let entry = ReleaseHash {
                     hash: hash.to_string(),
                     size,
                     filename: filename.to_string(),
-                });
+                };
+                match section {
+                    ChecksumSection::Md5 => package.files.push(entry),
+                    ChecksumSection::Sha256 => package.checksums_sha256.push(entry),
+                    ChecksumSection::Sha512 => package.checksums_sha512.push(entry),
+                }
             } else {
-                in_checksums_sha256_section = false;
+                section = match line.trim_end() {
+                    "Files:" => Some(ChecksumSection::Md5),
+                    "Checksums-Sha256:" => Some(ChecksumSection::Sha256),
+                    "Checksums-Sha512:" => Some(ChecksumSection::Sha512),
+                    _ => None,
+                };
             }
         }
 
@@ -118,6 +126,84 @@ pub struct SourcePkg {
     pub version: Option<String>,
     pub directory: Option<String>,
     pub checksums_sha256: Vec<ReleaseHash>,
+    pub checksums_sha512: Vec<ReleaseHash>,
+    pub files: Vec<ReleaseHash>,
+}
+
+impl SourcePkg {
+    /// Additional checksums of a file, prefixed with their algorithm
+    pub fn aliases_for(&self, filename: &str) -> Vec<String> {
+        [(&self.checksums_sha512, "sha512"), (&self.files, "md5")]
+            .into_iter()
+            .filter_map(|(entries, algo)| {
+                let entry = entries.iter().find(|entry| entry.filename == filename)?;
+                Some(format!("{algo}:{}", entry.hash))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ChecksumSection {
+    Md5,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceRole {
+    /// The pristine upstream tarball (`.orig.tar.*`)
+    Upstream,
+    /// Additional upstream tarballs (`.orig-component.tar.*`)
+    Component,
+    /// Debian's patches and build instructions (`.debian.tar.*`, `.diff.gz`, `.dsc`)
+    Packaging,
+    /// Native packages have no separate upstream tarball
+    Native,
+}
+
+impl SourceRole {
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        if filename.ends_with(".dsc") || filename.ends_with(".diff.gz") {
+            return Some(SourceRole::Packaging);
+        }
+
+        let (stem, _) = split_tarball(filename)?;
+        if stem.ends_with(".debian") {
+            Some(SourceRole::Packaging)
+        } else if stem.ends_with(".orig") {
+            Some(SourceRole::Upstream)
+        } else if stem.rsplit_once(".orig-").is_some_and(|(_, component)| {
+            !component.is_empty()
+                && component
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        }) {
+            Some(SourceRole::Component)
+        } else {
+            Some(SourceRole::Native)
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceRole::Upstream => "upstream",
+            SourceRole::Component => "component",
+            SourceRole::Packaging => "packaging",
+            SourceRole::Native => "native",
+        }
+    }
+}
+
+/// Split a compressed tarball filename into its stem and compression
+fn split_tarball(filename: &str) -> Option<(&str, &str)> {
+    let (stem, compression) = filename.rsplit_once(".tar.")?;
+    matches!(compression, "gz" | "xz" | "bz2" | "zst" | "lzma").then_some((stem, compression))
+}
+
+/// Whether a source file is a tarball we're able to ingest
+pub fn is_source_tarball(filename: &str) -> bool {
+    split_tarball(filename).is_some()
 }
 
 pub struct Release {
@@ -219,8 +305,91 @@ Checksums-Sha256:
                             size: 8648
                         }
                     ],
+                    checksums_sha512: vec![],
+                    files: vec![
+                        ReleaseHash {
+                            filename: "sn0int_0.26.0-0kali3.dsc".to_string(),
+                            hash: "a2f2a9f592c506b6a746dc9debd1cacd".to_string(),
+                            size: 1807
+                        },
+                        ReleaseHash {
+                            filename: "sn0int_0.26.0.orig.tar.gz".to_string(),
+                            hash: "5c5578537a0abe07b683f8b454af025d".to_string(),
+                            size: 1798079
+                        },
+                        ReleaseHash {
+                            filename: "sn0int_0.26.0-0kali3.debian.tar.xz".to_string(),
+                            hash: "b103d74ae55843b0f87112988062be54".to_string(),
+                            size: 8648
+                        }
+                    ],
                 }]
             }
         );
+        assert_eq!(
+            index.pkgs[0].aliases_for("sn0int_0.26.0.orig.tar.gz"),
+            vec!["md5:5c5578537a0abe07b683f8b454af025d".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_parse_sha512() {
+        let data = br#"Package: hello
+Version: 2.10-3
+Directory: pool/main/h/hello
+Checksums-Sha256:
+ 5a2a1e5bfbbc4b5ec4ac9ae1a1e7a5e5e0b4d0a0a1c1c9d1c6b5f5b3b0c6a2b1 1183 hello_2.10-3.dsc
+Checksums-Sha512:
+ 3cd1b25b4d1f1f6a1e33d1b8e9c1a7b2a5e0f1e0c2b3f3e2b1a9c8d7e6f5a4b3c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1 1183 hello_2.10-3.dsc
+
+"#;
+        let index = SourcesIndex::parse(data).unwrap();
+        let pkg = &index.pkgs[0];
+        assert_eq!(pkg.checksums_sha256.len(), 1);
+        assert_eq!(pkg.checksums_sha512.len(), 1);
+        assert_eq!(
+            pkg.aliases_for("hello_2.10-3.dsc"),
+            vec!["sha512:3cd1b25b4d1f1f6a1e33d1b8e9c1a7b2a5e0f1e0c2b3f3e2b1a9c8d7e6f5a4b3c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_source_role() {
+        assert_eq!(
+            SourceRole::from_filename("sn0int_0.26.0.orig.tar.gz"),
+            Some(SourceRole::Upstream)
+        );
+        assert_eq!(
+            SourceRole::from_filename("llvm-toolchain-18_18.1.8.orig-clang-tools-extra.tar.xz"),
+            Some(SourceRole::Component)
+        );
+        assert_eq!(
+            SourceRole::from_filename("sn0int_0.26.0-0kali3.debian.tar.xz"),
+            Some(SourceRole::Packaging)
+        );
+        assert_eq!(
+            SourceRole::from_filename("zlib_1.2.3.4.dfsg-3.diff.gz"),
+            Some(SourceRole::Packaging)
+        );
+        assert_eq!(
+            SourceRole::from_filename("sn0int_0.26.0-0kali3.dsc"),
+            Some(SourceRole::Packaging)
+        );
+        assert_eq!(
+            SourceRole::from_filename("debhelper_13.15.3.tar.xz"),
+            Some(SourceRole::Native)
+        );
+        assert_eq!(
+            SourceRole::from_filename("sn0int_0.26.0.orig.tar.gz.asc"),
+            None
+        );
+        assert_eq!(
+            SourceRole::from_filename("aalib_1.4p5.orig.tar.lzma"),
+            Some(SourceRole::Upstream)
+        );
+        assert!(is_source_tarball("debhelper_13.15.3.tar.xz"));
+        assert!(is_source_tarball("aalib_1.4p5.orig.tar.lzma"));
+        assert!(!is_source_tarball("zlib_1.2.3.4.dfsg-3.diff.gz"));
+        assert!(!is_source_tarball("sn0int_0.26.0.orig.tar.gz.asc"));
     }
 }
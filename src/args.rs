@@ -1,6 +1,8 @@
 use crate::ingest;
+use crate::utils;
 use clap::{ArgAction, Parser, Subcommand};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(version)]
@@ -37,12 +39,26 @@ pub struct Worker {
     /// Path to use for temporary git clone operations
     #[arg(long, env = "WHATSRC_GIT_TMP")]
     pub git_tmp: String,
+    /// Number of tasks to process concurrently
+    #[arg(short = 'j', long, env = "WHATSRC_CONCURRENCY", default_value = "1")]
+    pub concurrency: usize,
+    #[command(flatten)]
+    pub git_cache: GitCache,
+    #[command(flatten)]
+    pub git_keyring: GitKeyring,
+    #[command(flatten)]
+    pub release_keyring: ReleaseKeyring,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Plumbing {
     IngestTar(IngestTar),
+    IngestDir(IngestDir),
     IngestGit(IngestGit),
+    IngestHg(IngestHg),
+    IngestSvn(IngestSvn),
     IngestPacmanSnapshot(IngestPacmanSnapshot),
     IngestRpm(IngestRpm),
     IngestWolfi(IngestWolfi),
@@ -51,7 +67,9 @@ pub enum Plumbing {
     SyncAlpine(SyncAlpine),
     SyncApt(SyncApt),
     SyncPacman(SyncPacman),
+    SyncAur(SyncAur),
     SyncRpm(SyncRpm),
+    SyncDistgit(SyncDistgit),
     SyncGentoo(SyncGentoo),
     SyncHomebrew(SyncHomebrew),
     SyncGuix(SyncGuix),
@@ -60,6 +78,8 @@ pub enum Plumbing {
     AddRef(AddRef),
     ReindexUrl(ReindexUrl),
     ReindexSbom(ReindexSbom),
+    #[command(subcommand)]
+    Tasks(Tasks),
 }
 
 /// Ingest a .tar into the archive
@@ -70,6 +90,27 @@ pub struct IngestTar {
     pub file: Option<String>,
 }
 
+/// Ingest all archives found in a directory
+#[derive(Debug, Parser)]
+pub struct IngestDir {
+    /// Number of archives to ingest concurrently
+    #[arg(short = 'j', long, default_value = "1")]
+    pub concurrency: usize,
+    /// Read refs from a file with one `<path> <vendor> <package> <version>` entry per line
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+    /// Regex to derive refs from filenames, with named groups `package` and `version`
+    #[arg(long, requires = "vendor")]
+    pub pattern: Option<String>,
+    /// The vendor to use for refs derived with --pattern
+    #[arg(long)]
+    pub vendor: Option<String>,
+    /// Ingest archives even if they are already known
+    #[arg(short = 'R', long)]
+    pub reindex: bool,
+    pub path: PathBuf,
+}
+
 /// Create a `git archive` of a git ref
 #[derive(Debug, Parser)]
 pub struct IngestGit {
@@ -78,6 +119,36 @@ pub struct IngestGit {
     pub tmp: String,
     /// The url to clone from, including tag information
     pub git: ingest::git::GitUrl,
+    #[command(flatten)]
+    pub cache: GitCache,
+    #[command(flatten)]
+    pub keyring: GitKeyring,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
+}
+
+/// Create a `hg archive` of a mercurial revision
+#[derive(Debug, Parser)]
+pub struct IngestHg {
+    /// The directory to clone into
+    #[arg(long)]
+    pub tmp: String,
+    /// The url to clone from, including revision information
+    pub hg: ingest::hg::HgUrl,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
+}
+
+/// Create a `svn export` of a subversion revision
+#[derive(Debug, Parser)]
+pub struct IngestSvn {
+    /// The directory to export into
+    #[arg(long)]
+    pub tmp: String,
+    /// The url to export from, including revision information
+    pub svn: ingest::svn::SvnUrl,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Ingest a pacman git .tar.gz
@@ -95,6 +166,8 @@ pub struct IngestPacmanSnapshot {
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
+    #[command(flatten)]
+    pub keyring: ReleaseKeyring,
 }
 
 /// Ingest a .src.rpm
@@ -106,6 +179,9 @@ pub struct IngestRpm {
     pub package: String,
     #[arg(long)]
     pub version: String,
+    /// The repository label to record on refs, e.g. `updates`
+    #[arg(long)]
+    pub repo: Option<String>,
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
@@ -159,6 +235,10 @@ pub struct SyncAlpine {
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (apt)
@@ -175,7 +255,14 @@ pub struct SyncApt {
     /// Queue a task even if artifact is already known
     #[arg(short = 'R', long)]
     pub reindex: bool,
+    /// Mirrors to fall back to if a download from the primary url fails
+    #[arg(long = "mirror")]
+    pub mirrors: Vec<String>,
     pub url: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (pacman)
@@ -189,6 +276,25 @@ pub struct SyncPacman {
     #[arg(short, long = "repo", required = true)]
     pub repos: Vec<String>,
     pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
+}
+
+/// Start an import of a software vendor (aur)
+#[derive(Debug, Parser)]
+pub struct SyncAur {
+    #[arg(long, default_value = "aur")]
+    pub vendor: String,
+    #[arg(long)]
+    pub fetch: bool,
+    /// A tarball of `.SRCINFO` files, a directory of checkouts or a clone of aur.git
+    pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (pacman)
@@ -196,7 +302,34 @@ pub struct SyncPacman {
 pub struct SyncRpm {
     #[arg(long)]
     pub vendor: String,
-    pub url: String,
+    /// Repository base urls, `metalink=<url>` or `mirrorlist=<url>`, optionally labeled like `updates=<url>`
+    #[arg(required = true)]
+    pub repos: Vec<String>,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
+}
+
+/// Start an import of a software vendor (dist-git `sources` files)
+#[derive(Debug, Parser)]
+pub struct SyncDistgit {
+    #[arg(long)]
+    pub vendor: String,
+    #[arg(long)]
+    pub fetch: bool,
+    /// Base url of the content-addressed lookaside cache
+    #[arg(long, default_value = "https://src.fedoraproject.org/repo/pkgs/rpms")]
+    pub lookaside: String,
+    /// Value of the `%{dist}` macro, e.g. `.fc41`, so versions match the ones of sync-rpm
+    #[arg(long)]
+    pub dist: Option<String>,
+    /// A tarball of the dist-git repositories, or a directory of clones
+    pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (gentoo)
@@ -207,6 +340,10 @@ pub struct SyncGentoo {
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (homebrew)
@@ -217,6 +354,10 @@ pub struct SyncHomebrew {
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (guix)
@@ -227,6 +368,10 @@ pub struct SyncGuix {
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (void)
@@ -237,6 +382,10 @@ pub struct SyncVoid {
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
 }
 
 /// Start an import of a software vendor (yocto)
@@ -247,6 +396,55 @@ pub struct SyncYocto {
     #[arg(long)]
     pub fetch: bool,
     pub file: String,
+    #[command(flatten)]
+    pub http_cache: HttpCache,
+    #[command(flatten)]
+    pub rewrites: UrlRewrites,
+}
+
+#[derive(Debug, Parser)]
+pub struct HttpCache {
+    /// Directory to cache downloaded indexes in, unchanged indexes are skipped
+    #[arg(long, env = "WHATSRC_HTTP_CACHE")]
+    pub http_cache: Option<PathBuf>,
+    /// Process the index even if it didn't change since the last run
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct UrlRewrites {
+    /// Replace a url prefix before downloading, e.g. `https://deb.debian.org/=/srv/mirror/debian/`
+    #[arg(
+        long = "rewrite-url",
+        env = "WHATSRC_REWRITE_URL",
+        value_delimiter = ','
+    )]
+    pub rules: Vec<utils::RewriteRule>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct GitCache {
+    /// Maximum size of the cached git repositories in MiB, least recently used ones are evicted
+    #[arg(long, env = "WHATSRC_GIT_CACHE_SIZE", default_value = "20480")]
+    pub git_cache_size: u64,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct GitKeyring {
+    /// GnuPG home directory with the OpenPGP keys to verify git signatures with
+    #[arg(long, env = "WHATSRC_GIT_GNUPGHOME")]
+    pub git_gnupghome: Option<PathBuf>,
+    /// `allowed_signers` file with the ssh keys to verify git signatures with
+    #[arg(long, env = "WHATSRC_GIT_ALLOWED_SIGNERS")]
+    pub git_allowed_signers: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ReleaseKeyring {
+    /// GnuPG home directory that vendor keys are imported into, used to verify release signatures
+    #[arg(long, env = "WHATSRC_RELEASE_GNUPGHOME")]
+    pub release_gnupghome: Option<PathBuf>,
 }
 
 /// This command should merge into Ingest eventually
@@ -286,3 +484,71 @@ pub struct ReindexSbom {
     #[arg(long)]
     pub limit: Option<usize>,
 }
+
+/// Inspect and manage the task queue
+#[derive(Debug, Subcommand)]
+pub enum Tasks {
+    List(TasksList),
+    Show(TasksShow),
+    Retry(TasksRetry),
+    Delete(TasksDelete),
+    Purge(TasksPurge),
+}
+
+#[derive(Debug, Parser)]
+pub struct TaskFilter {
+    /// Only match tasks with a key starting with this, e.g. `fetch:` or `git-clone:`
+    #[arg(long)]
+    pub prefix: Option<String>,
+    /// Only match tasks with an error message containing this string
+    #[arg(long)]
+    pub error: Option<String>,
+    /// Only match tasks that have been retried at least this many times
+    #[arg(long)]
+    pub min_retries: Option<i16>,
+    /// Only match tasks that have permanently failed
+    #[arg(long)]
+    pub failed: bool,
+}
+
+/// List tasks in the queue
+#[derive(Debug, Parser)]
+pub struct TasksList {
+    #[command(flatten)]
+    pub filter: TaskFilter,
+    /// Upper limit of tasks to list
+    #[arg(long)]
+    pub limit: Option<usize>,
+}
+
+/// Show all details of a task
+#[derive(Debug, Parser)]
+pub struct TasksShow {
+    /// The key or numeric id of the task
+    pub task: String,
+}
+
+/// Reset the retry counter of tasks so they are attempted again
+#[derive(Debug, Parser)]
+pub struct TasksRetry {
+    #[command(flatten)]
+    pub filter: TaskFilter,
+}
+
+/// Delete specific tasks
+#[derive(Debug, Parser)]
+pub struct TasksDelete {
+    /// The keys or numeric ids of the tasks
+    #[arg(required = true)]
+    pub tasks: Vec<String>,
+}
+
+/// Delete all tasks matching a filter
+#[derive(Debug, Parser)]
+pub struct TasksPurge {
+    #[command(flatten)]
+    pub filter: TaskFilter,
+    /// Allow deleting all tasks if no filter is given
+    #[arg(long)]
+    pub all: bool,
+}
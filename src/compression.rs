@@ -1,4 +1,6 @@
-use async_compression::tokio::bufread::{BzDecoder, GzipDecoder, XzDecoder};
+use async_compression::tokio::bufread::{
+    BzDecoder, GzipDecoder, LzmaDecoder, XzDecoder, ZstdDecoder,
+};
 use std::pin::Pin;
 use std::task::Poll;
 use tokio::io::{self, AsyncBufRead, AsyncRead, ReadBuf};
@@ -8,6 +10,8 @@ pub enum Decompressor<R> {
     Gz(GzipDecoder<R>),
     Xz(XzDecoder<R>),
     Bz2(BzDecoder<R>),
+    Zstd(ZstdDecoder<R>),
+    Lzma(LzmaDecoder<R>),
 }
 
 impl<R: AsyncBufRead> Decompressor<R> {
@@ -25,12 +29,37 @@ impl<R: AsyncBufRead> Decompressor<R> {
         Decompressor::Bz2(decoder)
     }
 
+    pub fn zstd(reader: R) -> Self {
+        let mut decoder = ZstdDecoder::new(reader);
+        decoder.multiple_members(true);
+        Decompressor::Zstd(decoder)
+    }
+
+    /// Pick the decompressor by the file extension, e.g. of a sync index
+    pub fn detect(reader: R, filename: &str) -> Self {
+        if filename.ends_with(".gz") || filename.ends_with(".tgz") {
+            Decompressor::gz(reader)
+        } else if filename.ends_with(".xz") {
+            Decompressor::xz(reader)
+        } else if filename.ends_with(".zst") {
+            Decompressor::zstd(reader)
+        } else {
+            Decompressor::Plain(reader)
+        }
+    }
+
+    pub fn lzma(reader: R) -> Self {
+        Decompressor::Lzma(LzmaDecoder::new(reader))
+    }
+
     pub fn into_inner(self) -> R {
         match self {
             Decompressor::Plain(r) => r,
             Decompressor::Gz(r) => r.into_inner(),
             Decompressor::Xz(r) => r.into_inner(),
             Decompressor::Bz2(r) => r.into_inner(),
+            Decompressor::Zstd(r) => r.into_inner(),
+            Decompressor::Lzma(r) => r.into_inner(),
         }
     }
 }
@@ -46,6 +75,8 @@ impl<R: AsyncBufRead + Unpin> AsyncRead for Decompressor<R> {
             Decompressor::Gz(r) => Pin::new(r).poll_read(cx, buf),
             Decompressor::Xz(r) => Pin::new(r).poll_read(cx, buf),
             Decompressor::Bz2(r) => Pin::new(r).poll_read(cx, buf),
+            Decompressor::Zstd(r) => Pin::new(r).poll_read(cx, buf),
+            Decompressor::Lzma(r) => Pin::new(r).poll_read(cx, buf),
         }
     }
 }
@@ -13,8 +13,16 @@ use sqlx::Row;
 use std::borrow::Cow;
 use std::env;
 use std::io::{Read, Write};
+use std::time::Duration;
 
-const RETRY_LIMIT: i64 = 5;
+const RETRY_LIMIT: i16 = 5;
+const RETRY_BACKOFF_BASE: Duration = Duration::from_secs(10 * 60);
+
+/// Delay until the next attempt after a task has failed `retries` times
+pub fn retry_backoff(retries: i16) -> Duration {
+    let exp = retries.saturating_sub(1).clamp(0, 16) as u32;
+    RETRY_BACKOFF_BASE * 2u32.pow(exp)
+}
 
 fn compress_json<W: Write, T: Serialize + ?Sized>(writer: W, obj: &T) -> Result<()> {
     let mut writer = lz4_flex::frame::FrameEncoder::new(writer);
@@ -116,6 +124,69 @@ impl Client {
         Ok(())
     }
 
+    /// Register checksums that were published by a vendor, e.g. `md5:...` in a Debian Sources index
+    ///
+    /// Aliases that are already known are left untouched.
+    pub async fn register_index_aliases(&self, aliases: &[String], canonical: &str) -> Result<()> {
+        for alias in aliases {
+            let algo = alias
+                .split_once(':')
+                .map(|(algo, _)| algo)
+                .unwrap_or("unknown");
+            sqlx::query(
+                "INSERT INTO aliases (alias_from, alias_to, reason)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING",
+            )
+            .bind(alias)
+            .bind(canonical)
+            .bind(format!("{algo}(index)"))
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Register an alias unless `alias_from` already points to an artifact
+    pub async fn insert_alias_if_missing(
+        &self,
+        alias_from: &str,
+        alias_to: &str,
+        reason: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO aliases (alias_from, alias_to, reason)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(alias_from)
+        .bind(alias_to)
+        .bind(reason)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Record an alias an artifact claims for itself, these are never used to resolve artifacts
+    pub async fn insert_alias_hint(
+        &self,
+        alias_from: &str,
+        alias_to: &str,
+        reason: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO alias_hints (alias_from, alias_to, reason)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(alias_from)
+        .bind(alias_to)
+        .bind(reason)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
     pub async fn get_artifact_alias(&self, chksum: &str) -> Result<Option<Alias>> {
         let result = sqlx::query_as::<_, Alias>(
             "SELECT *
@@ -147,17 +218,23 @@ impl Client {
 
     pub async fn insert_ref(&self, obj: &Ref) -> Result<()> {
         let _result = sqlx::query(
-            "INSERT INTO refs (chksum, vendor, package, version, filename, last_seen)
-            VALUES ($1, $2, $3, $4, $5, now())
+            "INSERT INTO refs (chksum, vendor, package, version, filename, role, repo, arch, last_seen)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now())
             ON CONFLICT (chksum, vendor, package, version) DO UPDATE SET
             last_seen = EXCLUDED.last_seen,
-            filename = COALESCE(EXCLUDED.filename, refs.filename)",
+            filename = COALESCE(EXCLUDED.filename, refs.filename),
+            role = COALESCE(EXCLUDED.role, refs.role),
+            repo = COALESCE(EXCLUDED.repo, refs.repo),
+            arch = COALESCE(EXCLUDED.arch, refs.arch)",
         )
         .bind(&obj.chksum)
         .bind(&obj.vendor)
         .bind(&obj.package)
         .bind(&obj.version)
         .bind(&obj.filename)
+        .bind(&obj.role)
+        .bind(&obj.repo)
+        .bind(&obj.arch)
         .execute(&self.pool)
         .await?;
         Ok(())
@@ -225,8 +302,15 @@ impl Client {
     }
 
     pub async fn get_all_refs_for(&self, chksum: &str) -> Result<Vec<RefView>> {
-        let mut result = sqlx::query_as::<_, Ref>(
-            "SELECT *
+        let mut result = sqlx::query_as::<_, RefWithRepos>(
+            "SELECT t.*, ARRAY(
+                SELECT p.repo::text
+                FROM package_repos p
+                WHERE p.vendor = t.vendor
+                AND p.package = t.package
+                AND p.version = t.version
+                ORDER BY p.repo ASC
+            ) repos
             FROM (
                 SELECT refs.*
                 FROM refs
@@ -245,7 +329,9 @@ impl Client {
 
         let mut rows = Vec::new();
         while let Some(row) = result.try_next().await? {
-            rows.push(row.into());
+            let mut view = RefView::from(row.r);
+            view.repos = row.repos;
+            rows.push(view);
         }
         Ok(rows)
     }
@@ -280,44 +366,194 @@ impl Client {
     }
 
     pub async fn bump_task_error_counter(&self, task: &Task, error: &str) -> Result<()> {
+        let retries = task.retries + 1;
+        if retries >= RETRY_LIMIT {
+            return self.fail_task(task, error).await;
+        }
+
         let _result = sqlx::query(
             "UPDATE tasks
             SET retries = retries + 1,
-            error = $2
-            WHERE id = $1",
+            error = $2,
+            leased_until = NULL,
+            locked_by = NULL,
+            next_attempt_at = now() + make_interval(secs => $3)
+            WHERE id = $1
+            AND locked_by IS NOT DISTINCT FROM $4",
+        )
+        .bind(task.id)
+        .bind(error)
+        .bind(retry_backoff(retries).as_secs_f64())
+        .bind(&task.locked_by)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Move the task into failed state, it's not going to be attempted again
+    pub async fn fail_task(&self, task: &Task, error: &str) -> Result<()> {
+        let _result = sqlx::query(
+            "UPDATE tasks
+            SET retries = retries + 1,
+            error = $2,
+            leased_until = NULL,
+            locked_by = NULL,
+            next_attempt_at = NULL,
+            failed_at = now()
+            WHERE id = $1
+            AND locked_by IS NOT DISTINCT FROM $3",
         )
         .bind(task.id)
         .bind(error)
+        .bind(&task.locked_by)
         .execute(&self.pool)
         .await?;
         Ok(())
     }
 
-    pub async fn get_random_task(&self) -> Result<Option<Task>> {
+    /// Atomically pick a random task and lease it to us, other workers are
+    /// going to skip it until the lease has expired
+    ///
+    /// The lease is identified by a random token in `locked_by`, once it expired and the task
+    /// has been claimed by somebody else we can't renew, complete or fail it anymore.
+    pub async fn claim_task(&self, lease: Duration) -> Result<Option<Task>> {
+        let token = format!("{:016x}", fastrand::u64(..));
         let result = sqlx::query_as(
-            "SELECT *
+            "UPDATE tasks
+            SET leased_until = now() + make_interval(secs => $1),
+            locked_by = $2
+            WHERE id = (
+                SELECT id
                 FROM tasks
-                WHERE retries < $1
+                WHERE failed_at IS NULL
+                AND (next_attempt_at IS NULL OR next_attempt_at < now())
+                AND (leased_until IS NULL OR leased_until < now())
                 ORDER BY RANDOM()
-                LIMIT 1",
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *",
         )
-        .bind(RETRY_LIMIT)
+        .bind(lease.as_secs_f64())
+        .bind(token)
         .fetch_optional(&self.pool)
         .await?;
         Ok(result)
     }
 
-    pub async fn delete_task(&self, task: &Task) -> Result<()> {
-        let _result = sqlx::query(
-            "DELETE FROM tasks
-            WHERE key = $1",
+    pub async fn renew_task_lease(&self, task: &Task, lease: Duration) -> Result<()> {
+        let result = sqlx::query(
+            "UPDATE tasks
+            SET leased_until = now() + make_interval(secs => $2)
+            WHERE id = $1
+            AND locked_by IS NOT DISTINCT FROM $3",
         )
-        .bind(&task.key)
+        .bind(task.id)
+        .bind(lease.as_secs_f64())
+        .bind(&task.locked_by)
         .execute(&self.pool)
         .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::TaskLeaseLost(task.key.clone()));
+        }
         Ok(())
     }
 
+    pub async fn get_task(&self, key: &str) -> Result<Option<Task>> {
+        let result = sqlx::query_as(
+            "SELECT *
+            FROM tasks
+            WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn get_task_by_id(&self, id: i64) -> Result<Option<Task>> {
+        let result = sqlx::query_as(
+            "SELECT *
+            FROM tasks
+            WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn list_tasks(&self, filter: &TaskFilter, limit: Option<usize>) -> Result<Vec<Task>> {
+        let query = format!(
+            "SELECT *
+            FROM tasks
+            {TASK_FILTER_SQL}
+            ORDER BY retries DESC, id ASC
+            LIMIT $5"
+        );
+        let mut result = sqlx::query_as::<_, Task>(&query)
+            .bind(&filter.prefix)
+            .bind(&filter.error)
+            .bind(filter.min_retries)
+            .bind(filter.failed)
+            .bind(limit.map(|limit| limit as i64))
+            .fetch(&self.pool);
+
+        let mut rows = Vec::new();
+        while let Some(row) = result.try_next().await? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    /// Reset the retry counter of all matching tasks so they are attempted again
+    pub async fn retry_tasks(&self, filter: &TaskFilter) -> Result<u64> {
+        let query = format!(
+            "UPDATE tasks
+            SET retries = 0,
+            next_attempt_at = NULL,
+            failed_at = NULL
+            {TASK_FILTER_SQL}"
+        );
+        let result = sqlx::query(&query)
+            .bind(&filter.prefix)
+            .bind(&filter.error)
+            .bind(filter.min_retries)
+            .bind(filter.failed)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_tasks(&self, filter: &TaskFilter) -> Result<u64> {
+        let query = format!(
+            "DELETE FROM tasks
+            {TASK_FILTER_SQL}"
+        );
+        let result = sqlx::query(&query)
+            .bind(&filter.prefix)
+            .bind(&filter.error)
+            .bind(filter.min_retries)
+            .bind(filter.failed)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Delete the task, unless it has been claimed by somebody else in the meantime
+    pub async fn delete_task(&self, task: &Task) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM tasks
+            WHERE id = $1
+            AND locked_by IS NOT DISTINCT FROM $2",
+        )
+        .bind(task.id)
+        .bind(&task.locked_by)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn insert_package(&self, package: &Package) -> Result<()> {
         let _result = sqlx::query(
             "INSERT INTO packages (vendor, package, version)
@@ -332,6 +568,29 @@ impl Client {
         Ok(())
     }
 
+    /// Record that a package is part of a repository, e.g. a release/suite of a distribution
+    pub async fn insert_package_repo(
+        &self,
+        vendor: &str,
+        package: &str,
+        version: &str,
+        repo: &str,
+    ) -> Result<()> {
+        let _result = sqlx::query(
+            "INSERT INTO package_repos (vendor, package, version, repo)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (vendor, package, version, repo) DO UPDATE SET
+            last_seen = now()",
+        )
+        .bind(vendor)
+        .bind(package)
+        .bind(version)
+        .bind(repo)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
     pub async fn get_package(
         &self,
         vendor: &str,
@@ -479,6 +738,204 @@ impl Client {
         Ok(rows)
     }
 
+    pub async fn insert_artifact_submodule(
+        &self,
+        chksum: &str,
+        submodule: &ingest::git::Submodule,
+    ) -> Result<()> {
+        let _result = sqlx::query(
+            "INSERT INTO artifact_submodules (chksum, path, url, commit)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (chksum, path) DO UPDATE SET
+            url = EXCLUDED.url,
+            commit = EXCLUDED.commit",
+        )
+        .bind(chksum)
+        .bind(&submodule.path)
+        .bind(&submodule.url)
+        .bind(&submodule.commit)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_artifact_submodules(&self, chksum: &str) -> Result<Vec<ArtifactSubmodule>> {
+        let mut result = sqlx::query_as::<_, ArtifactSubmodule>(
+            "SELECT *
+            FROM artifact_submodules
+            WHERE chksum = $1
+            ORDER BY path ASC",
+        )
+        .bind(chksum)
+        .fetch(&self.pool);
+
+        let mut rows = Vec::new();
+        while let Some(row) = result.try_next().await? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    pub async fn insert_git_signature(
+        &self,
+        commit: &str,
+        signature: &ingest::git::Signature,
+        required: bool,
+    ) -> Result<()> {
+        let _result = sqlx::query(
+            "INSERT INTO git_signatures (object, commit, kind, format, status, fingerprint, required)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (object) DO UPDATE SET
+            format = EXCLUDED.format,
+            status = EXCLUDED.status,
+            fingerprint = EXCLUDED.fingerprint,
+            required = git_signatures.required OR EXCLUDED.required,
+            verified_at = NOW()",
+        )
+        .bind(&signature.object)
+        .bind(commit)
+        .bind(signature.kind)
+        .bind(signature.format.map(|f| f.as_str()))
+        .bind(signature.status.as_str())
+        .bind(&signature.fingerprint)
+        .bind(required)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get the signatures of the git commits that were snapshotted into this artifact
+    pub async fn get_git_signatures(&self, chksum: &str) -> Result<Vec<GitSignature>> {
+        let mut result = sqlx::query_as::<_, GitSignature>(
+            "SELECT DISTINCT s.*
+            FROM git_signatures s
+            JOIN aliases a ON a.alias_from IN ('git:' || s.commit, 'gitsm:' || s.commit)
+            WHERE a.alias_to = $1
+            ORDER BY s.commit ASC, s.kind DESC",
+        )
+        .bind(chksum)
+        .fetch(&self.pool);
+
+        let mut rows = Vec::new();
+        while let Some(row) = result.try_next().await? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    pub async fn insert_artifact_signature(&self, signature: &ArtifactSignature) -> Result<()> {
+        let _result = sqlx::query(
+            "INSERT INTO artifact_signatures (chksum, vendor, url, signature_url, status, fingerprint)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (chksum, vendor, signature_url) DO UPDATE SET
+            url = EXCLUDED.url,
+            status = EXCLUDED.status,
+            fingerprint = EXCLUDED.fingerprint,
+            verified_at = NOW()",
+        )
+        .bind(&signature.chksum)
+        .bind(&signature.vendor)
+        .bind(&signature.url)
+        .bind(&signature.signature_url)
+        .bind(&signature.status)
+        .bind(&signature.fingerprint)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn has_artifact_signature(&self, chksum: &str, vendor: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "SELECT 1
+            FROM artifact_signatures
+            WHERE chksum = $1
+            AND vendor = $2",
+        )
+        .bind(chksum)
+        .bind(vendor)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(result.is_some())
+    }
+
+    pub async fn get_artifact_signatures(&self, chksum: &str) -> Result<Vec<ArtifactSignature>> {
+        let mut result = sqlx::query_as::<_, ArtifactSignature>(
+            "SELECT *
+            FROM artifact_signatures
+            WHERE chksum = $1
+            OR chksum IN (SELECT alias_from FROM aliases WHERE alias_to = $1)
+            ORDER BY vendor ASC, signature_url ASC",
+        )
+        .bind(chksum)
+        .fetch(&self.pool);
+
+        let mut rows = Vec::new();
+        while let Some(row) = result.try_next().await? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    /// Record that a tag resolved to a commit, returns other commits the tag pointed to before
+    pub async fn insert_git_tag(
+        &self,
+        remote: &str,
+        tag: &str,
+        commit: &str,
+    ) -> Result<Vec<GitTag>> {
+        let _result = sqlx::query(
+            "INSERT INTO git_tags (remote, tag, commit)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (remote, tag, commit) DO UPDATE SET
+            last_seen = NOW()",
+        )
+        .bind(remote)
+        .bind(tag)
+        .bind(commit)
+        .execute(&self.pool)
+        .await?;
+
+        let query = format!(
+            "SELECT {GIT_TAG_COLUMNS}
+            FROM git_tags
+            WHERE remote = $1 AND tag = $2 AND commit != $3
+            ORDER BY first_seen ASC"
+        );
+        let mut result = sqlx::query_as::<_, GitTag>(&query)
+            .bind(remote)
+            .bind(tag)
+            .bind(commit)
+            .fetch(&self.pool);
+
+        let mut rows = Vec::new();
+        while let Some(row) = result.try_next().await? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    /// List all tags that resolved to more than one commit
+    pub async fn get_moved_git_tags(&self) -> Result<Vec<GitTag>> {
+        let query = format!(
+            "SELECT {GIT_TAG_COLUMNS}
+            FROM git_tags t
+            JOIN (
+                SELECT remote, tag, MAX(last_seen) latest
+                FROM git_tags
+                GROUP BY remote, tag
+                HAVING COUNT(*) > 1
+            ) moved USING (remote, tag)
+            ORDER BY moved.latest DESC, remote ASC, tag ASC, t.first_seen ASC"
+        );
+        let mut result = sqlx::query_as::<_, GitTag>(&query).fetch(&self.pool);
+
+        let mut rows = Vec::new();
+        while let Some(row) = result.try_next().await? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
     pub async fn get_sbom_refs_for_sbom(&self, sbom: &Sbom) -> Result<Vec<SbomRef>> {
         let mut result = sqlx::query_as::<_, SbomRef>(
             "SELECT *
@@ -536,7 +993,7 @@ impl Client {
             FROM refs
             GROUP BY vendor
             ORDER BY vendor",
-            Some(RETRY_LIMIT),
+            None,
         )
         .await
     }
@@ -545,10 +1002,22 @@ impl Client {
         self.get_stats(
             "SELECT split_part(key, ':', 1) k, count(*) num
             FROM tasks
-            WHERE retries < $1
+            WHERE failed_at IS NULL
+            GROUP BY k
+            ORDER BY k",
+            None,
+        )
+        .await
+    }
+
+    pub async fn stats_failed_tasks(&self) -> Result<Vec<(String, i64)>> {
+        self.get_stats(
+            "SELECT split_part(key, ':', 1) k, count(*) num
+            FROM tasks
+            WHERE failed_at IS NOT NULL
             GROUP BY k
             ORDER BY k",
-            Some(RETRY_LIMIT),
+            None,
         )
         .await
     }
@@ -639,6 +1108,48 @@ pub struct SbomRef {
     pub path: String,
 }
 
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct ArtifactSubmodule {
+    pub chksum: String,
+    pub path: String,
+    pub url: String,
+    pub commit: String,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct GitSignature {
+    pub object: String,
+    pub commit: String,
+    pub kind: String,
+    pub format: Option<String>,
+    pub status: String,
+    pub fingerprint: Option<String>,
+    pub required: bool,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct ArtifactSignature {
+    pub chksum: String,
+    pub vendor: String,
+    pub url: String,
+    pub signature_url: String,
+    pub status: String,
+    pub fingerprint: Option<String>,
+}
+
+const GIT_TAG_COLUMNS: &str = "remote, tag, commit,
+    to_char(first_seen, 'YYYY-MM-DD HH24:MI:SS') first_seen,
+    to_char(last_seen, 'YYYY-MM-DD HH24:MI:SS') last_seen";
+
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct GitTag {
+    pub remote: String,
+    pub tag: String,
+    pub commit: String,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
 #[derive(sqlx::FromRow, Debug, Serialize)]
 pub struct Ref {
     pub chksum: String,
@@ -646,6 +1157,9 @@ pub struct Ref {
     pub package: String,
     pub version: String,
     pub filename: Option<String>,
+    pub role: Option<String>,
+    pub repo: Option<String>,
+    pub arch: Option<String>,
 }
 
 #[derive(sqlx::FromRow, Debug, Serialize)]
@@ -656,91 +1170,105 @@ pub struct RefView {
     pub package: String,
     pub version: String,
     pub filename: Option<String>,
+    pub role: Option<String>,
+    pub repo: Option<String>,
+    pub arch: Option<String>,
     pub href: Option<String>,
+    /// The repositories this package version has been seen in, e.g. release/suite of a distribution
+    pub repos: Vec<String>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct RefWithRepos {
+    #[sqlx(flatten)]
+    r: Ref,
+    repos: Vec<String>,
+}
+
+/// The human readable name of a vendor
+pub fn display_vendor(vendor: &str) -> Cow<'static, str> {
+    match vendor {
+        "archlinux" => Cow::Borrowed("Arch Linux"),
+        "aur" => Cow::Borrowed("AUR"),
+        "debian" => Cow::Borrowed("Debian"),
+        "fedora" => Cow::Borrowed("Fedora"),
+        "alpine" => Cow::Borrowed("Alpine"),
+        "opensuse" => Cow::Borrowed("openSUSE"),
+        "kali" => Cow::Borrowed("Kali"),
+        "gentoo" => Cow::Borrowed("Gentoo"),
+        "homebrew" => Cow::Borrowed("Homebrew"),
+        "wolfi" => Cow::Borrowed("Wolfi OS"),
+        "guix" => Cow::Borrowed("Guix"),
+        "ubuntu" => Cow::Borrowed("Ubuntu"),
+        "void" => Cow::Borrowed("Void Linux"),
+        "yocto" => Cow::Borrowed("Yocto Project"),
+        other => Cow::Owned(other.to_owned()),
+    }
 }
 
 impl From<Ref> for RefView {
     fn from(r: Ref) -> Self {
-        let (display_vendor, href) = match r.vendor.as_str() {
-            "archlinux" => {
-                let href = format!("https://archlinux.org/packages/?q={}", r.package);
-                (Cow::Borrowed("Arch Linux"), Some(href))
-            }
-            "debian" => {
-                let href = format!("https://packages.debian.org/search?keywords={}", r.package);
-                (Cow::Borrowed("Debian"), Some(href))
-            }
-            "fedora" => {
-                let href = format!("https://packages.fedoraproject.org/pkgs/{}/", r.package);
-                (Cow::Borrowed("Fedora"), Some(href))
-            }
-            "alpine" => {
-                let href = format!("https://pkgs.alpinelinux.org/packages?name={}", r.package);
-                (Cow::Borrowed("Alpine"), Some(href))
-            }
-            "opensuse" => {
-                let href = format!(
-                    "https://build.opensuse.org/package/show/openSUSE:Factory/{}",
-                    r.package
-                );
-                // alternative: https://src.opensuse.org/rpm/{} or https://code.opensuse.org/package/{}
-                (Cow::Borrowed("openSUSE"), Some(href))
-            }
-            "kali" => {
-                let href = format!("https://pkg.kali.org/pkg/{}", r.package);
-                (Cow::Borrowed("Kali"), Some(href))
-            }
-            "gentoo" => {
-                let href = format!("https://packages.gentoo.org/packages/{}", r.package);
-                (Cow::Borrowed("Gentoo"), Some(href))
-            }
-            "homebrew" => {
-                let href = format!("https://formulae.brew.sh/formula/{}", r.package);
-                (Cow::Borrowed("Homebrew"), Some(href))
-            }
-            "wolfi" => {
-                let href = format!(
-                    "https://github.com/wolfi-dev/os/blob/main/{}.yaml",
-                    r.package
-                );
-                (Cow::Borrowed("Wolfi OS"), Some(href))
-            }
-            "guix" => {
-                let href = format!("https://packages.guix.gnu.org/packages/{}", r.package);
-                (Cow::Borrowed("Guix"), Some(href))
-            }
-            "ubuntu" => {
-                let href = format!(
-                    "https://packages.ubuntu.com/search?suite=all&searchon=names&keywords={}",
-                    r.package
-                );
-                (Cow::Borrowed("Ubuntu"), Some(href))
-            }
-            "void" => {
-                let href = format!(
-                    "https://voidlinux.org/packages/?arch=x86_64&q={}",
-                    r.package
-                );
-                (Cow::Borrowed("Void Linux"), Some(href))
-            }
-            "yocto" => {
-                let href = format!(
-                    "https://layers.openembedded.org/layerindex/branch/master/recipes/?q={}",
-                    r.package
-                );
-                (Cow::Borrowed("Yocto Project"), Some(href))
-            }
-            other => (Cow::Owned(other.to_owned()), None),
+        let href = match r.vendor.as_str() {
+            "archlinux" => Some(format!("https://archlinux.org/packages/?q={}", r.package)),
+            "aur" => Some(format!("https://aur.archlinux.org/pkgbase/{}", r.package)),
+            "debian" => Some(format!(
+                "https://packages.debian.org/search?keywords={}",
+                r.package
+            )),
+            "fedora" => Some(format!(
+                "https://packages.fedoraproject.org/pkgs/{}/",
+                r.package
+            )),
+            "alpine" => Some(format!(
+                "https://pkgs.alpinelinux.org/packages?name={}",
+                r.package
+            )),
+            // alternative: https://src.opensuse.org/rpm/{} or https://code.opensuse.org/package/{}
+            "opensuse" => Some(format!(
+                "https://build.opensuse.org/package/show/openSUSE:Factory/{}",
+                r.package
+            )),
+            "kali" => Some(format!("https://pkg.kali.org/pkg/{}", r.package)),
+            "gentoo" => Some(format!(
+                "https://packages.gentoo.org/packages/{}",
+                r.package
+            )),
+            "homebrew" => Some(format!("https://formulae.brew.sh/formula/{}", r.package)),
+            "wolfi" => Some(format!(
+                "https://github.com/wolfi-dev/os/blob/main/{}.yaml",
+                r.package
+            )),
+            "guix" => Some(format!(
+                "https://packages.guix.gnu.org/packages/{}",
+                r.package
+            )),
+            "ubuntu" => Some(format!(
+                "https://packages.ubuntu.com/search?suite=all&searchon=names&keywords={}",
+                r.package
+            )),
+            "void" => Some(format!(
+                "https://voidlinux.org/packages/?arch=x86_64&q={}",
+                r.package
+            )),
+            "yocto" => Some(format!(
+                "https://layers.openembedded.org/layerindex/branch/master/recipes/?q={}",
+                r.package
+            )),
+            _ => None,
         };
 
         RefView {
+            display_vendor: display_vendor(&r.vendor),
             chksum: r.chksum,
             vendor: r.vendor,
-            display_vendor,
             package: r.package,
             version: r.version,
             filename: r.filename,
+            role: r.role,
+            repo: r.repo,
+            arch: r.arch,
             href,
+            repos: Vec::new(),
         }
     }
 }
@@ -752,6 +1280,12 @@ pub struct Task {
     pub data: serde_json::Value,
     pub retries: i16,
     pub error: Option<String>,
+    #[serde(skip)]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub failed_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub locked_by: Option<String>,
 }
 
 impl Task {
@@ -763,6 +1297,9 @@ impl Task {
             data,
             retries: 0,
             error: None,
+            next_attempt_at: None,
+            failed_at: None,
+            locked_by: None,
         })
     }
 
@@ -772,12 +1309,43 @@ impl Task {
     }
 }
 
+const TASK_FILTER_SQL: &str = "WHERE ($1::varchar IS NULL OR starts_with(key, $1))
+    AND ($2::varchar IS NULL OR strpos(error, $2) > 0)
+    AND ($3::smallint IS NULL OR retries >= $3)
+    AND ($4 = false OR failed_at IS NOT NULL)";
+
+#[derive(Debug, Default)]
+pub struct TaskFilter {
+    pub prefix: Option<String>,
+    pub error: Option<String>,
+    pub min_retries: Option<i16>,
+    pub failed: bool,
+}
+
+impl TaskFilter {
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_none() && self.error.is_none() && self.min_retries.is_none() && !self.failed
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum TaskData {
     FetchTar {
         url: String,
+        /// Alternative urls to try in order if `url` fails
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        mirrors: Vec<String>,
+        /// Checksums of the download published by the vendor, registered as aliases once ingested
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        aliases: Vec<String>,
         compression: Option<String>,
         success_ref: Option<DownloadRef>,
+        /// The sha256 of the download published by the vendor, the task fails if it doesn't match
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+        /// Verify a detached signature while the download is ingested
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<DetachedSignature>,
     },
     PacmanGitSnapshot {
         vendor: String,
@@ -790,6 +1358,8 @@ pub enum TaskData {
         package: String,
         version: String,
         url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        repo: Option<String>,
     },
     #[serde(alias = "AlpineGitApkbuild")]
     ApkbuildGit {
@@ -806,14 +1376,35 @@ pub enum TaskData {
         package: String,
         version: String,
     },
+    /// Import all packages built from the same void-packages commit with a single download
+    VoidLinuxCommit {
+        vendor: String,
+        commit: String,
+        packages: Vec<VoidPackage>,
+    },
     GitSnapshot {
         url: String,
     },
+    HgSnapshot {
+        url: String,
+    },
+    SvnSnapshot {
+        url: String,
+    },
     IndexSbom {
         // support old task format
         strain: Option<String>,
         chksum: String,
     },
+    VerifySignature {
+        vendor: String,
+        url: String,
+        signature_url: String,
+        chksum: String,
+        /// Fingerprints the vendor accepts signatures from, any key in the keyring if empty
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        keys: Vec<String>,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -823,6 +1414,23 @@ pub struct DownloadRef {
     pub version: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DetachedSignature {
+    pub vendor: String,
+    pub signature_url: String,
+    /// The checksum the vendor expects for the signed file
+    pub chksum: String,
+    /// Fingerprints of the keys the vendor trusts to sign this file
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct VoidPackage {
+    pub srcpkg: String,
+    pub package: String,
+    pub version: String,
+}
+
 #[derive(sqlx::FromRow, Debug, Serialize)]
 pub struct Package {
     pub vendor: String,
@@ -857,4 +1465,31 @@ mod tests {
         let decompressed = decompress_json::<_, BTreeMap<String, String>>(&buf[..]).unwrap();
         assert_eq!(obj, decompressed);
     }
+
+    #[test]
+    fn test_parse_fetch_task_without_mirrors() {
+        let task = Task {
+            data: serde_json::json!({
+                "FetchTar": {
+                    "url": "https://example.com/foo-1.0.tar.gz",
+                    "compression": null,
+                    "success_ref": null,
+                }
+            }),
+            ..Task::new(String::new(), &TaskData::GitSnapshot { url: String::new() }).unwrap()
+        };
+        let TaskData::FetchTar { url, mirrors, .. } = task.data().unwrap() else {
+            panic!("Unexpected task type");
+        };
+        assert_eq!(url, "https://example.com/foo-1.0.tar.gz");
+        assert_eq!(mirrors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let delays = (1..RETRY_LIMIT)
+            .map(|retries| retry_backoff(retries).as_secs() / 60)
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [10, 20, 40, 80]);
+    }
 }
@@ -52,6 +52,18 @@ pub enum Error {
     JoinError(#[from] tokio::task::JoinError),
     #[error("Child process has exited with error: {0}")]
     ChildExit(std::process::ExitStatus),
+    #[error("Invalid url rewrite rule, expected `from=to`: {0:?}")]
+    InvalidRewriteRule(String),
+    #[error("Refusing to download from unsupported url: {0:?}")]
+    UnsupportedUrl(String),
+    #[error("Invalid manifest line, expected `<path> <vendor> <package> <version>`: {0:?}")]
+    InvalidManifestLine(String),
+    #[error("Filename pattern is missing a named capture group: {0:?}")]
+    MissingCaptureGroup(&'static str),
+    #[error("A vendor is required when using a filename pattern")]
+    PatternMissingVendor,
+    #[error("Failed to ingest {0} files from directory")]
+    IngestDirFailed(usize),
     #[error("Parser encountered invalid data")]
     InvalidData,
     #[error("Parser encountered unknown variable: ${0}")]
@@ -60,18 +72,42 @@ pub enum Error {
     InvalidPkgbuild(String),
     #[error("Rpm is missing a `primary` data entry")]
     RpmMissingPrimary,
+    #[error("Failed to find any mirror for rpm repository")]
+    RpmMissingMirrors,
+    #[error("Expansion of rpm spec macro exceeded size limit")]
+    RpmMacroExpansionTooLarge,
+    #[error("Parser encountered invalid rpm: {0}")]
+    InvalidRpm(&'static str),
     #[error("Unknown git reference string: {0:?}")]
     UnknownGitRef(String),
     #[error("Invalid git reference: {0:?}")]
     InvalidGitRef(ingest::git::GitUrl),
     #[error("Error in git operation")]
     GitError(ExitStatus),
+    #[error("Refusing to fetch from unsupported git remote: {0:?}")]
+    UnsupportedGitRemote(String),
     #[error("Timeout of git fetch operation")]
     GitFetchTimeout,
     #[error("Error in git fetch operation")]
     GitFetchError(ExitStatus),
     #[error("Failed to parse git rev-parse output")]
     GitRevParseError(String),
+    #[error("Unknown version control reference: {0:?}")]
+    UnknownVcsRef(String),
+    #[error("Missing revision for version control url: {0:?}")]
+    MissingVcsRevision(String),
+    #[error("Timeout of version control operation")]
+    VcsTimeout,
+    #[error("Error in mercurial operation")]
+    HgError(ExitStatus),
+    #[error("Failed to parse mercurial log output: {0:?}")]
+    HgLogError(String),
+    #[error("Error in subversion operation")]
+    SvnError(ExitStatus),
+    #[error("Error in gpg operation")]
+    GpgError(ExitStatus),
+    #[error("Downloaded file does not match expected checksum {0:?}")]
+    ChecksumMismatch(String),
     #[error("Failed to determine filename for Sources index")]
     AptIndexMissingSources,
     #[error("Unknown sbom strain: {0:?}")]
@@ -88,6 +124,35 @@ pub enum Error {
     WolfiUnknownSubstitute(String),
     #[error("String is poisoned, failed to interpolate: {0:?}")]
     YoctoPoisonedStr(String),
+    #[error("Task not found: {0:?}")]
+    TaskNotFound(String),
+    #[error("Lease on task has been lost to another worker: {0:?}")]
+    TaskLeaseLost(String),
+    #[error("Refusing to purge all tasks without filter, use --all to confirm")]
+    TaskFilterMissing,
+}
+
+impl Error {
+    /// Errors that are not going to go away by retrying the task
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Error::Reqwest(err) => matches!(
+                err.status(),
+                Some(reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE)
+            ),
+            Error::UnknownGitRef(_)
+            | Error::InvalidGitRef(_)
+            | Error::UnsupportedGitRemote(_)
+            | Error::UnsupportedUrl(_)
+            | Error::UnknownVcsRef(_)
+            | Error::ChecksumMismatch(_)
+            | Error::InvalidRpm(_)
+            | Error::MissingVcsRevision(_)
+            | Error::AlpineMissingRepo
+            | Error::UnrecognizedApkVendor(_) => true,
+            _ => false,
+        }
+    }
 }
 
 // TODO: consider fixing this
@@ -1,6 +1,7 @@
 use crate::apkbuild;
 use crate::db::{self, Task, TaskData};
 use crate::errors::*;
+use crate::ingest::signature;
 use crate::utils;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
@@ -31,17 +32,31 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
 
         let chksum = format!("sha512:{sha512}");
 
+        let signature_url = signature::find_signature_url(url, &apkbuild.source);
+
         // check if already known
         if db.resolve_artifact(&chksum).await?.is_none() {
-            db.insert_task(&Task::new(
-                format!("fetch:{url}"),
-                &TaskData::FetchTar {
-                    url: url.to_string(),
-                    compression: None,
-                    success_ref: None,
-                },
-            )?)
-            .await?;
+            let task = if let Some(signature_url) = signature_url {
+                // verify the signature while downloading, so the file is only fetched once
+                signature::fetch_task(vendor, url, signature_url, &chksum, &[])?
+            } else {
+                Task::new(
+                    format!("fetch:{url}"),
+                    &TaskData::FetchTar {
+                        url: url.to_string(),
+                        mirrors: Vec::new(),
+                        aliases: Vec::new(),
+                        compression: None,
+                        success_ref: None,
+                        sha256: None,
+                        signature: None,
+                    },
+                )?
+            };
+            db.insert_task(&task).await?;
+        } else if let Some(signature_url) = signature_url {
+            let task = signature::verify_task(vendor, url, signature_url, &chksum, &[])?;
+            db.insert_task(&task).await?;
         }
 
         let r = db::Ref {
@@ -50,6 +65,9 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
             package: package.to_string(),
             version: version.to_string(),
             filename: Some(url.to_string()),
+            role: None,
+            repo: None,
+            arch: None,
         };
         info!("insert: {r:?}");
         db.insert_ref(&r).await?;
@@ -0,0 +1,367 @@
+use crate::args;
+use crate::db;
+use crate::errors::*;
+use crate::ingest;
+use digest::Digest;
+use futures::stream::{self, StreamExt};
+use regex::Regex;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Tar,
+    Gz,
+    Xz,
+    Bz2,
+    Zstd,
+}
+
+impl Format {
+    /// Detect the archive format from the first bytes of a file
+    ///
+    /// Compressed files are assumed to contain a tar, this is only verified during import.
+    pub fn detect(magic: &[u8]) -> Option<Format> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gz)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Format::Xz)
+        } else if magic.starts_with(b"BZh") {
+            Some(Format::Bz2)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else if magic.get(257..262) == Some(b"ustar") {
+            Some(Format::Tar)
+        } else {
+            None
+        }
+    }
+
+    pub fn compression(&self) -> Option<&'static str> {
+        match self {
+            Format::Tar => None,
+            Format::Gz => Some("gz"),
+            Format::Xz => Some("xz"),
+            Format::Bz2 => Some("bz2"),
+            Format::Zstd => Some("zst"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RefInfo {
+    pub vendor: String,
+    pub package: String,
+    pub version: String,
+}
+
+/// Parse a manifest with one `<path> <vendor> <package> <version>` entry per line
+///
+/// Paths are relative to the ingested directory, empty lines and lines starting with `#` are ignored.
+pub fn parse_manifest(text: &str) -> Result<HashMap<PathBuf, RefInfo>> {
+    let mut manifest = HashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut columns = line.split_whitespace();
+        let (Some(path), Some(vendor), Some(package), Some(version), None) = (
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+        ) else {
+            return Err(Error::InvalidManifestLine(line.to_string()));
+        };
+
+        manifest.insert(
+            PathBuf::from(path),
+            RefInfo {
+                vendor: vendor.to_string(),
+                package: package.to_string(),
+                version: version.to_string(),
+            },
+        );
+    }
+    Ok(manifest)
+}
+
+pub struct RefResolver {
+    manifest: HashMap<PathBuf, RefInfo>,
+    pattern: Option<(String, Regex)>,
+}
+
+impl RefResolver {
+    pub fn new(
+        manifest: HashMap<PathBuf, RefInfo>,
+        pattern: Option<(String, Regex)>,
+    ) -> Result<Self> {
+        if let Some((_, regex)) = &pattern {
+            for group in ["package", "version"] {
+                if !regex.capture_names().any(|name| name == Some(group)) {
+                    return Err(Error::MissingCaptureGroup(group));
+                }
+            }
+        }
+        Ok(RefResolver { manifest, pattern })
+    }
+
+    pub fn resolve(&self, relative: &Path) -> Option<RefInfo> {
+        if let Some(info) = self.manifest.get(relative) {
+            return Some(info.clone());
+        }
+
+        let (vendor, regex) = self.pattern.as_ref()?;
+        let filename = relative.file_name()?.to_str()?;
+        let caps = regex.captures(filename)?;
+        Some(RefInfo {
+            vendor: vendor.to_string(),
+            package: caps.name("package")?.as_str().to_string(),
+            version: caps.name("version")?.as_str().to_string(),
+        })
+    }
+}
+
+async fn read_magic<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut buf = vec![0; 512];
+    let mut n = 0;
+    while n < buf.len() {
+        let read = reader.read(&mut buf[n..]).await?;
+        if read == 0 {
+            break;
+        }
+        n += read;
+    }
+    buf.truncate(n);
+    Ok(buf)
+}
+
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await?;
+    let mut sha256 = Sha256::new();
+    let mut buf = [0; 4096];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        sha256.update(&buf[..n]);
+    }
+    Ok(format!("sha256:{}", hex::encode(sha256.finalize())))
+}
+
+async fn walk(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut queue = vec![root.to_path_buf()];
+    while let Some(dir) = queue.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                queue.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Ingest a single file, returns false if it's not an archive
+async fn ingest_file(
+    db: &db::Client,
+    refs: &RefResolver,
+    root: &Path,
+    path: &Path,
+    reindex: bool,
+) -> Result<bool> {
+    let mut file = File::open(path).await?;
+    let magic = read_magic(&mut file).await?;
+    let Some(format) = Format::detect(&magic) else {
+        debug!("Skipping file, not an archive: {path:?}");
+        return Ok(false);
+    };
+
+    let chksum = sha256_file(path).await?;
+    if !reindex && db.resolve_artifact(&chksum).await?.is_some() {
+        debug!("Skipping file, artifact is already known: {path:?} ({chksum})");
+    } else {
+        info!("Ingesting file as {format:?}: {path:?}");
+        let file = File::open(path).await?;
+        ingest::tar::stream_data(Some(db), file, format.compression()).await?;
+    }
+
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if let Some(info) = refs.resolve(relative) {
+        let r = db::Ref {
+            chksum,
+            vendor: info.vendor,
+            package: info.package,
+            version: info.version,
+            filename: relative
+                .file_name()
+                .and_then(|f| f.to_str())
+                .map(String::from),
+            role: None,
+            repo: None,
+            arch: None,
+        };
+        info!("insert: {r:?}");
+        db.insert_ref(&r).await?;
+    }
+
+    Ok(true)
+}
+
+pub async fn run(args: &args::IngestDir) -> Result<()> {
+    let db = db::Client::create().await?;
+
+    let manifest = if let Some(path) = &args.manifest {
+        let text = fs::read_to_string(path).await?;
+        parse_manifest(&text)?
+    } else {
+        HashMap::new()
+    };
+    let pattern = if let Some(pattern) = &args.pattern {
+        let vendor = args.vendor.clone().ok_or(Error::PatternMissingVendor)?;
+        Some((vendor, Regex::new(pattern)?))
+    } else {
+        None
+    };
+    let refs = RefResolver::new(manifest, pattern)?;
+
+    let files = walk(&args.path).await?;
+    info!("Found {} files in {:?}", files.len(), args.path);
+
+    let mut ingested = 0;
+    let mut errors = 0;
+    let (db, refs) = (&db, &refs);
+    let mut results = stream::iter(&files)
+        .map(|path| async move {
+            let result = ingest_file(db, refs, &args.path, path, args.reindex).await;
+            (path, result)
+        })
+        .buffer_unordered(args.concurrency.max(1));
+    while let Some((path, result)) = results.next().await {
+        match result {
+            Ok(true) => ingested += 1,
+            Ok(false) => (),
+            Err(err) => {
+                error!("Failed to ingest {path:?}: {err:#}");
+                errors += 1;
+            }
+        }
+    }
+
+    info!("Processed {ingested} archives");
+    if errors > 0 {
+        return Err(Error::IngestDirFailed(errors));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(Format::detect(&[0x1f, 0x8b, 0x08, 0x00]), Some(Format::Gz));
+        assert_eq!(
+            Format::detect(&[0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00]),
+            Some(Format::Xz)
+        );
+        assert_eq!(Format::detect(b"BZh91AY&SY"), Some(Format::Bz2));
+        assert_eq!(
+            Format::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x04]),
+            Some(Format::Zstd)
+        );
+
+        let mut tar = vec![0; 512];
+        tar[..8].copy_from_slice(b"foo-1.0/");
+        tar[257..263].copy_from_slice(b"ustar\0");
+        assert_eq!(Format::detect(&tar), Some(Format::Tar));
+
+        assert_eq!(Format::detect(b"PK\x03\x04"), None);
+        assert_eq!(Format::detect(b"hello world"), None);
+        assert_eq!(Format::detect(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(
+            "# distfiles
+ripgrep-14.1.0.tar.gz gentoo ripgrep 14.1.0
+
+sub/dir/zstd-1.5.6.tar.zst archlinux zstd 1.5.6-1
+",
+        )
+        .unwrap();
+        assert_eq!(
+            manifest,
+            [
+                (
+                    PathBuf::from("ripgrep-14.1.0.tar.gz"),
+                    RefInfo {
+                        vendor: "gentoo".to_string(),
+                        package: "ripgrep".to_string(),
+                        version: "14.1.0".to_string(),
+                    }
+                ),
+                (
+                    PathBuf::from("sub/dir/zstd-1.5.6.tar.zst"),
+                    RefInfo {
+                        vendor: "archlinux".to_string(),
+                        package: "zstd".to_string(),
+                        version: "1.5.6-1".to_string(),
+                    }
+                ),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        assert!(parse_manifest("foo.tar.gz gentoo foo").is_err());
+        assert!(parse_manifest("foo.tar.gz gentoo foo 1.0 extra").is_err());
+    }
+
+    #[test]
+    fn test_resolve_refs() {
+        let manifest = parse_manifest("pinned.tar.gz debian pinned 2.0-1").unwrap();
+        let regex = Regex::new(r"^(?P<package>.+)-(?P<version>[^-]+)\.tar\.").unwrap();
+        let refs = RefResolver::new(manifest, Some(("gentoo".to_string(), regex))).unwrap();
+
+        assert_eq!(
+            refs.resolve(Path::new("pinned.tar.gz")),
+            Some(RefInfo {
+                vendor: "debian".to_string(),
+                package: "pinned".to_string(),
+                version: "2.0-1".to_string(),
+            })
+        );
+        assert_eq!(
+            refs.resolve(Path::new("a/b/libfoo-bar-1.2.3.tar.xz")),
+            Some(RefInfo {
+                vendor: "gentoo".to_string(),
+                package: "libfoo-bar".to_string(),
+                version: "1.2.3".to_string(),
+            })
+        );
+        assert_eq!(refs.resolve(Path::new("README")), None);
+    }
+
+    #[test]
+    fn test_pattern_missing_group() {
+        let regex = Regex::new(r"^(?P<package>.+)\.tar\.").unwrap();
+        let refs = RefResolver::new(HashMap::new(), Some(("gentoo".to_string(), regex)));
+        assert!(refs.is_err());
+    }
+}
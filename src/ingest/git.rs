@@ -2,38 +2,65 @@ use crate::args;
 use crate::db;
 use crate::errors::*;
 use crate::ingest;
-use fd_lock::RwLock;
-use std::io::BufRead;
+use crate::utils;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::str::FromStr;
-use tokio::fs;
+use std::time::SystemTime;
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::process;
 use tokio::time::{self, Duration};
+use tokio_tar::{Archive, EntryType};
 
 /// Do not tolerate occupying more than 20min of our time
 pub const CLONE_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+/// Protect against submodules that (indirectly) reference themselves
+pub const MAX_SUBMODULE_DEPTH: usize = 8;
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct GitUrl {
     url: String,
     tag: Option<String>,
     commit: Option<String>,
+    submodules: bool,
+    /// The source demands a valid signature on the tag or commit (`?signed`)
+    signed: bool,
 }
 
 impl FromStr for GitUrl {
     type Err = Error;
 
     fn from_str(full_url: &str) -> Result<GitUrl> {
-        let url = full_url.strip_prefix("git+").unwrap_or(full_url);
-        let url = url.trim_end_matches("?signed");
+        let (url, submodules) = if let Some(url) = full_url.strip_prefix("gitsm+") {
+            (url, true)
+        } else {
+            (full_url.strip_prefix("git+").unwrap_or(full_url), false)
+        };
+        // `?signed` may show up before or after the fragment
+        let (url, signed_suffix) = url
+            .strip_suffix("?signed")
+            .map(|url| (url, true))
+            .unwrap_or((url, false));
 
         let (url, info) = url
             .rsplit_once('#')
             .map(|(url, info)| (url, Some(info)))
             .unwrap_or((url, None));
 
+        let (url, signed_query) = url
+            .strip_suffix("?signed")
+            .map(|url| (url, true))
+            .unwrap_or((url, false));
+
         let mut git = GitUrl {
-            url: url.trim_end_matches("?signed").to_string(),
+            url: url.to_string(),
+            submodules,
+            signed: signed_suffix || signed_query,
             ..Default::default()
         };
 
@@ -49,72 +76,516 @@ impl FromStr for GitUrl {
     }
 }
 
-pub async fn take_snapshot(db: &db::Client, git: &GitUrl, tmp: &str) -> Result<()> {
-    fs::create_dir_all(tmp).await?;
-    let dir = fs::File::open(tmp).await?;
-    info!("Getting lock on filesystem git workdir...");
-    let mut lock = RwLock::new(dir.into_std().await);
-    let _lock = lock.write();
-    debug!("Acquired lock");
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submodule {
+    pub path: String,
+    pub url: String,
+    pub commit: String,
+}
 
-    let reference = if let Some(tag) = &git.tag {
-        tag
-    } else if let Some(commit) = &git.commit {
-        commit
-    } else {
-        return Err(Error::InvalidGitRef(git.clone()));
-    };
+/// Parse the gitlinks (mode 160000) from `git ls-tree -r -z` output
+pub fn parse_gitlinks(ls_tree: &[u8]) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    for entry in ls_tree.split(|b| *b == 0) {
+        let Ok(entry) = std::str::from_utf8(entry) else {
+            continue;
+        };
+        let Some((info, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let mut info = info.split(' ');
+        if let (Some("160000"), Some("commit"), Some(commit)) =
+            (info.next(), info.next(), info.next())
+        {
+            out.push((path.to_string(), commit.to_string()));
+        }
+    }
+    out
+}
 
-    let path = format!("{}/git", tmp.strip_suffix('/').unwrap_or(tmp));
-    if fs::metadata(&path).await.is_ok() {
-        debug!("Running cleanup of temporary git repository");
-        fs::remove_dir_all(&path).await?;
+/// Parse `git config --get-regexp` output of a .gitmodules file into a map of path to url
+pub fn parse_gitmodules(config: &str) -> BTreeMap<String, String> {
+    let mut paths = BTreeMap::new();
+    let mut urls = BTreeMap::new();
+    for line in config.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+        let Some(key) = key.strip_prefix("submodule.") else {
+            continue;
+        };
+        if let Some(name) = key.strip_suffix(".path") {
+            paths.insert(name, value);
+        } else if let Some(name) = key.strip_suffix(".url") {
+            urls.insert(name, value);
+        }
     }
 
-    // Checking out a single commit occupies 40GB disk
-    if [
-        "https://chromium.googlesource.com/chromium/src.git",
-        "https://github.com/chromium/chromium.git",
-    ]
-    .contains(&git.url.as_str())
-    {
-        info!("Detected chromium, skipping 🤡: {:?}", git.url);
-        return Ok(());
+    paths
+        .into_iter()
+        .flat_map(|(name, path)| {
+            let url = urls.get(name)?;
+            Some((path.to_string(), url.to_string()))
+        })
+        .collect()
+}
+
+/// Resolve a submodule url that may be relative to the url of the superproject
+pub fn resolve_submodule_url(base: &str, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_string();
     }
 
-    // run git clone
-    info!("Setting up git repository");
-    let status = process::Command::new("git")
-        .args(["init", "-qb", "main", &path])
-        .status()
+    let mut base = base.trim_end_matches('/');
+    let mut url = url;
+    loop {
+        if let Some(rest) = url.strip_prefix("./") {
+            url = rest;
+        } else if let Some(rest) = url.strip_prefix("../") {
+            url = rest;
+            base = base.rsplit_once('/').map(|(base, _)| base).unwrap_or(base);
+        } else {
+            break;
+        }
+    }
+    format!("{base}/{url}")
+}
+
+async fn git_output(path: &str, args: &[&str]) -> Result<std::process::Output> {
+    let output = process::Command::new("git")
+        .args(["-C", path])
+        .args(args)
+        .output()
         .await?;
-    if !status.success() {
-        return Err(Error::GitError(status));
+    Ok(output)
+}
+
+/// List the submodules pinned by a commit, if any
+async fn read_submodules(path: &str, remote: &str, commit: &str) -> Result<Vec<Submodule>> {
+    let output = git_output(path, &["ls-tree", "-r", "-z", commit]).await?;
+    if !output.status.success() {
+        return Err(Error::GitError(output.status));
+    }
+    let gitlinks = parse_gitlinks(&output.stdout);
+    if gitlinks.is_empty() {
+        return Ok(vec![]);
     }
 
-    // https://gitlab.archlinux.org/pacman/pacman/-/commit/0828a085c146601f21d5e4afb5f396f00de2963b
-    debug!("Setting up .git/info/attributes to disable .gitattributes");
-    fs::write(
-        format!("{path}/.git/info/attributes"),
-        b"* -export-subst -export-ignore\n",
+    let blob = format!("{commit}:.gitmodules");
+    let output = git_output(
+        path,
+        &["config", "--blob", &blob, "--get-regexp", r"^submodule\."],
     )
     .await?;
+    let config = String::from_utf8_lossy(&output.stdout);
+    let urls = parse_gitmodules(&config);
 
-    debug!("Adding git remote: {:?}", git.url);
-    let status = process::Command::new("git")
-        .args(["-C", &path, "remote", "add", "origin", &git.url])
-        .status()
-        .await?;
-    if !status.success() {
-        return Err(Error::GitError(status));
+    let mut submodules = Vec::new();
+    for (path, commit) in gitlinks {
+        let Some(url) = urls.get(&path) else {
+            warn!("Submodule is missing from .gitmodules: {path:?}");
+            continue;
+        };
+        // the urls are controlled by the archived repository, refuse anything but network remotes
+        let url = resolve_submodule_url(remote, url);
+        validate_remote(&url)?;
+        submodules.push(Submodule { path, url, commit });
     }
+    Ok(submodules)
+}
 
-    info!(
-        "Fetching git VCS tree-ish reference from {:?}: {:?}",
-        git.url, reference
+async fn archive_to_file(path: &str, commit: &str, prefix: &str, out: &Path) -> Result<()> {
+    let out = out.to_str().ok_or(Error::InvalidData)?;
+    let prefix = format!("--prefix={prefix}");
+    let output = git_output(
+        path,
+        &[
+            "-c",
+            "core.abbrev=no",
+            "archive",
+            "--format",
+            "tar",
+            &prefix,
+            "-o",
+            out,
+            commit,
+        ],
+    )
+    .await?;
+    if !output.status.success() {
+        return Err(Error::GitError(output.status));
+    }
+    Ok(())
+}
+
+/// Determine the byte range of a tar that holds its entries, excluding a leading pax global
+/// header, the directory entry of `root` and the end-of-archive marker
+async fn archive_entries_range(path: &Path, root: &str) -> Result<(u64, u64)> {
+    let mut tar = Archive::new(File::open(path).await?);
+    let mut entries = tar.entries()?;
+
+    let (mut start, mut end) = (0, 0);
+    let mut leading = true;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let size = entry.header().entry_size()?;
+        let entry_end = (entry.raw_file_position() + size).div_ceil(512) * 512;
+        // the directory of a submodule is already part of its superproject
+        let entry_type = entry.header().entry_type();
+        let is_root = entry_type == EntryType::Directory
+            && !root.is_empty()
+            && entry.path()? == Path::new(root);
+        if leading && (entry_type == EntryType::XGlobalHeader || is_root) {
+            start = entry_end;
+        } else {
+            leading = false;
+        }
+        end = end.max(entry_end);
+    }
+    Ok((start, end.max(start)))
+}
+
+/// Archive a commit including all of its (nested) submodules into a single tar
+async fn archive_with_submodules(
+    db: Option<&db::Client>,
+    git: &GitUrl,
+    path: &str,
+    commit: &str,
+    workdir: &Path,
+    rewrites: &[utils::RewriteRule],
+) -> Result<(ingest::tar::TarSummary, Vec<Submodule>)> {
+    let mut archives = Vec::new();
+    let superproject = workdir.join("0.tar");
+    archive_to_file(path, commit, "", &superproject).await?;
+    archives.push((superproject, String::new()));
+
+    let mut submodules = Vec::new();
+    let mut queue = read_submodules(path, &git.url, commit)
+        .await?
+        .into_iter()
+        .map(|submodule| (submodule, 1))
+        .collect::<VecDeque<_>>();
+
+    while let Some((submodule, depth)) = queue.pop_front() {
+        info!(
+            "Fetching git submodule {:?} from {:?}: {:?}",
+            submodule.path, submodule.url, submodule.commit
+        );
+        fetch_commit(path, &submodule.url, &submodule.commit, rewrites).await?;
+
+        let file = workdir.join(format!("{}.tar", archives.len()));
+        let prefix = format!("{}/", submodule.path);
+        archive_to_file(path, &submodule.commit, &prefix, &file).await?;
+        archives.push((file, submodule.path.clone()));
+
+        if depth < MAX_SUBMODULE_DEPTH {
+            for nested in read_submodules(path, &submodule.url, &submodule.commit).await? {
+                let nested = Submodule {
+                    path: format!("{}/{}", submodule.path, nested.path),
+                    ..nested
+                };
+                queue.push_back((nested, depth + 1));
+            }
+        } else {
+            warn!(
+                "Reached maximum submodule depth, not descending further into {:?}",
+                submodule.path
+            );
+        }
+
+        submodules.push(submodule);
+    }
+
+    // concatenate all entries, the pax global headers would only describe the superproject
+    let combined = workdir.join("combined.tar");
+    let mut out = File::create(&combined).await?;
+    for (archive, root) in &archives {
+        let (start, end) = archive_entries_range(archive, root).await?;
+        let mut file = File::open(archive).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        io::copy(&mut file.take(end - start), &mut out).await?;
+    }
+    out.write_all(&[0; 1024]).await?;
+    out.flush().await?;
+
+    let file = File::open(&combined).await?;
+    let summary = ingest::tar::stream_data(db, file, None).await?;
+    Ok((summary, submodules))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureFormat {
+    OpenPgp,
+    Ssh,
+}
+
+impl SignatureFormat {
+    /// Detect the signature format of a raw tag or commit object
+    pub fn detect(object: &[u8]) -> Option<SignatureFormat> {
+        let contains = |needle: &[u8]| object.windows(needle.len()).any(|w| w == needle);
+        if contains(b"-----BEGIN PGP SIGNATURE-----") {
+            Some(SignatureFormat::OpenPgp)
+        } else if contains(b"-----BEGIN SSH SIGNATURE-----") {
+            Some(SignatureFormat::Ssh)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureFormat::OpenPgp => "openpgp",
+            SignatureFormat::Ssh => "ssh",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureStatus {
+    Valid,
+    Invalid,
+    /// The signature may be fine, but the key is not in our keyring
+    UnknownKey,
+    /// The signature is good, but the vendor doesn't list any keys it trusts
+    Unattested,
+    /// The signature is good, but made by a key the vendor doesn't list as trusted
+    UntrustedKey,
+    Unsigned,
+}
+
+impl SignatureStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureStatus::Valid => "valid",
+            SignatureStatus::Invalid => "invalid",
+            SignatureStatus::UnknownKey => "unknown-key",
+            SignatureStatus::Unattested => "unattested",
+            SignatureStatus::UntrustedKey => "untrusted-key",
+            SignatureStatus::Unsigned => "unsigned",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    /// The id of the signed tag or commit object
+    pub object: String,
+    pub kind: &'static str,
+    pub format: Option<SignatureFormat>,
+    pub status: SignatureStatus,
+    pub fingerprint: Option<String>,
+}
+
+/// Interpret the `[GNUPG:]` status lines of `git verify-tag --raw`
+pub fn parse_gpg_status(output: &str) -> (SignatureStatus, Option<String>) {
+    let (mut good, mut bad, mut missing_key) = (false, false, false);
+    let mut fingerprint = None;
+    for line in output.lines() {
+        let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+            continue;
+        };
+        let mut fields = line.split(' ');
+        let keyword = fields.next();
+        let fields = fields.collect::<Vec<_>>();
+        match keyword {
+            Some("GOODSIG") => good = true,
+            Some("BADSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG") => {
+                bad = true;
+                fingerprint = fingerprint.or(fields.first().map(|s| s.to_string()));
+            }
+            // the last field is the fingerprint of the primary key
+            Some("VALIDSIG") => {
+                fingerprint = fields.get(9).or(fields.first()).map(|s| s.to_string())
+            }
+            Some("ERRSIG") => {
+                missing_key = true;
+                fingerprint = fields.get(6).or(fields.first()).map(|s| s.to_string());
+            }
+            _ => (),
+        }
+    }
+
+    let status = if bad {
+        SignatureStatus::Invalid
+    } else if good && fingerprint.is_some() {
+        SignatureStatus::Valid
+    } else if missing_key {
+        SignatureStatus::UnknownKey
+    } else {
+        SignatureStatus::Invalid
+    };
+    (status, fingerprint)
+}
+
+/// Interpret the `ssh-keygen -Y verify` output of `git verify-tag --raw`
+pub fn parse_ssh_verify(output: &str, success: bool) -> (SignatureStatus, Option<String>) {
+    let fingerprint = output.lines().find_map(|line| {
+        let line = line.strip_prefix("Good \"git\" signature ")?;
+        let (_, key) = line.rsplit_once(" key ")?;
+        Some(key.to_string())
+    });
+
+    let status = match fingerprint {
+        Some(_) if success => SignatureStatus::Valid,
+        Some(_) if output.contains("No principal matched") => SignatureStatus::UnknownKey,
+        _ => SignatureStatus::Invalid,
+    };
+    (status, fingerprint)
+}
+
+/// Verify the signature of a tag or commit object against the configured keyrings
+pub async fn verify_signature(
+    path: &str,
+    object: &str,
+    kind: &'static str,
+    keyring: &args::GitKeyring,
+) -> Result<Signature> {
+    let output = git_output(path, &["cat-file", kind, object]).await?;
+    if !output.status.success() {
+        return Err(Error::GitError(output.status));
+    }
+    let mut signature = Signature {
+        object: object.to_string(),
+        kind,
+        format: SignatureFormat::detect(&output.stdout),
+        status: SignatureStatus::Unsigned,
+        fingerprint: None,
+    };
+    let Some(format) = signature.format else {
+        return Ok(signature);
+    };
+
+    let allowed_signers = keyring
+        .git_allowed_signers
+        .as_deref()
+        .unwrap_or(Path::new("/dev/null"));
+    let mut cmd = process::Command::new("git");
+    cmd.args(["-C", path, "-c"])
+        .arg(format!(
+            "gpg.ssh.allowedSignersFile={}",
+            allowed_signers.display()
+        ))
+        .args([&format!("verify-{kind}"), "--raw", object]);
+    if let Some(home) = &keyring.git_gnupghome {
+        cmd.env("GNUPGHOME", home);
+    }
+    let output = cmd.output().await?;
+    let output_str = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
     );
+
+    (signature.status, signature.fingerprint) = match format {
+        SignatureFormat::OpenPgp => parse_gpg_status(&output_str),
+        SignatureFormat::Ssh => parse_ssh_verify(&output_str, output.status.success()),
+    };
+    Ok(signature)
+}
+
+/// Only fetch from network remotes, git would interpret anything starting with `-` as an option
+pub fn validate_remote(url: &str) -> Result<()> {
+    if !url.starts_with('-')
+        && ["https://", "git://", "ssh://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme))
+    {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedGitRemote(url.to_string()))
+    }
+}
+
+/// A bare repository in the git cache, exclusively locked while this is held
+pub struct CachedRepo {
+    pub path: String,
+    name: String,
+    _lock: std::fs::File,
+}
+
+fn cache_dir(tmp: &str) -> PathBuf {
+    Path::new(tmp).join("repos")
+}
+
+/// Open (or create) the cached bare repository for a remote and wait for exclusive access
+pub async fn open_cached_repo(tmp: &str, url: &str) -> Result<CachedRepo> {
+    validate_remote(url)?;
+    let dir = cache_dir(tmp);
+    fs::create_dir_all(&dir).await?;
+
+    let name = hex::encode(Sha256::digest(url));
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(format!("{name}.lock")))?;
+    info!("Getting lock on cached git repository for {url:?}...");
+    let lock = tokio::task::spawn_blocking(move || lock.lock().map(|_| lock)).await??;
+    debug!("Acquired lock");
+    // the mtime of the lock file is used to evict the least recently used repositories
+    lock.set_modified(SystemTime::now())?;
+
+    let path = dir.join(format!("{name}.git"));
+    let path = path.to_str().ok_or(Error::InvalidData)?.to_string();
+    if fs::metadata(format!("{path}/HEAD")).await.is_err() {
+        if fs::metadata(&path).await.is_ok() {
+            debug!("Removing incomplete cached git repository: {path:?}");
+            fs::remove_dir_all(&path).await?;
+        }
+
+        info!("Setting up cached git repository for {url:?}: {path:?}");
+        let status = process::Command::new("git")
+            .args(["init", "--bare", "-qb", "main", &path])
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(Error::GitError(status));
+        }
+
+        // https://gitlab.archlinux.org/pacman/pacman/-/commit/0828a085c146601f21d5e4afb5f396f00de2963b
+        debug!("Setting up info/attributes to disable .gitattributes");
+        fs::write(
+            format!("{path}/info/attributes"),
+            b"* -export-subst -export-ignore\n",
+        )
+        .await?;
+    }
+
+    Ok(CachedRepo {
+        path,
+        name,
+        _lock: lock,
+    })
+}
+
+/// Fetch a commit into the repository, unless it's already present
+///
+/// The url is validated before the rewrite rules are applied, those may point to a local mirror.
+async fn fetch_commit(
+    path: &str,
+    url: &str,
+    commit: &str,
+    rewrites: &[utils::RewriteRule],
+) -> Result<()> {
+    validate_remote(url)?;
+    let object = format!("{commit}^{{commit}}");
+    if git_output(path, &["cat-file", "-e", &object])
+        .await?
+        .status
+        .success()
+    {
+        debug!("Git commit is already present in repository: {commit:?}");
+        return Ok(());
+    }
+
+    // keep a ref so the objects are not garbage collected and are advertised in later fetches
+    let refspec = format!("+{commit}:refs/commits/{commit}");
+    fetch_refspec(path, &utils::rewrite_url(rewrites, url), &refspec).await
+}
+
+async fn fetch_refspec(path: &str, url: &str, refspec: &str) -> Result<()> {
     let child = process::Command::new("git")
-        .args(["-C", &path, "fetch", "origin", reference])
+        .args(["-C", path, "fetch", "--no-tags", "--", url, refspec])
         .status();
     let Ok(status) = time::timeout(CLONE_TIMEOUT, child).await else {
         return Err(Error::GitFetchTimeout);
@@ -123,32 +594,254 @@ pub async fn take_snapshot(db: &db::Client, git: &GitUrl, tmp: &str) -> Result<(
     if !status.success() {
         return Err(Error::GitFetchError(status));
     }
+    Ok(())
+}
+
+async fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    let mut queue = vec![path.to_path_buf()];
+    while let Some(dir) = queue.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                queue.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
 
-    info!("Resolving FETCH_HEAD git ref");
-    let output = process::Command::new("git")
-        .args(["-C", &path, "rev-list", "-n1", "FETCH_HEAD"])
-        .output()
-        .await?;
+/// Return the size of a cached repository as recorded after its last use, measure it if unknown
+async fn cached_repo_size(dir: &Path, name: &str) -> Result<u64> {
+    let size_path = dir.join(format!("{name}.size"));
+    match fs::read_to_string(&size_path).await {
+        Ok(size) => {
+            if let Ok(size) = size.trim().parse() {
+                return Ok(size);
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err.into()),
+    }
+    let size = dir_size(&dir.join(format!("{name}.git"))).await?;
+    fs::write(&size_path, size.to_string()).await?;
+    Ok(size)
+}
+
+/// Delete the least recently used repositories until the cache fits into `max_size` bytes
+///
+/// Only the size of `keep` is measured, the size of the other repositories is recorded in a
+/// `.size` file after they have been used. Repositories that are currently locked are skipped.
+pub async fn evict_cache(tmp: &str, max_size: u64, keep: &CachedRepo) -> Result<()> {
+    let dir = cache_dir(tmp);
+
+    let keep_size = dir_size(Path::new(&keep.path)).await?;
+    fs::write(
+        dir.join(format!("{}.size", keep.name)),
+        keep_size.to_string(),
+    )
+    .await?;
+
+    let mut repos = Vec::new();
+    let mut total = keep_size;
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".git"))
+            .map(String::from)
+        else {
+            continue;
+        };
+        if name == keep.name {
+            continue;
+        }
+        let size = cached_repo_size(&dir, &name).await?;
+        total += size;
+        let lock = dir.join(format!("{name}.lock"));
+        let last_used = fs::metadata(&lock)
+            .await
+            .and_then(|m| m.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        repos.push((last_used, name, size));
+    }
+    debug!("Git repository cache is using {total} bytes (max: {max_size})");
+
+    repos.sort();
+    for (_, name, size) in repos {
+        if total <= max_size {
+            break;
+        }
+
+        let lock = match std::fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join(format!("{name}.lock")))
+        {
+            Ok(lock) => lock,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Cached git repository has no lock file, not evicting: {name:?}");
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if lock.try_lock().is_err() {
+            debug!("Cached git repository is in use, not evicting: {name:?}");
+            continue;
+        }
+
+        info!("Evicting cached git repository ({size} bytes): {name:?}");
+        fs::remove_dir_all(dir.join(format!("{name}.git"))).await?;
+        fs::remove_file(dir.join(format!("{name}.size"))).await?;
+        total = total.saturating_sub(size);
+    }
+
+    Ok(())
+}
+
+pub async fn take_snapshot(
+    db: &db::Client,
+    git: &GitUrl,
+    tmp: &str,
+    cache: &args::GitCache,
+    keyring: &args::GitKeyring,
+    rewrites: &[utils::RewriteRule],
+) -> Result<()> {
+    // Checking out a single commit occupies 40GB disk
+    if [
+        "https://chromium.googlesource.com/chromium/src.git",
+        "https://github.com/chromium/chromium.git",
+    ]
+    .contains(&git.url.as_str())
+    {
+        info!("Detected chromium, skipping 🤡: {:?}", git.url);
+        return Ok(());
+    }
+
+    let repo = open_cached_repo(tmp, &git.url).await?;
+    let result = snapshot_cached_repo(db, git, tmp, &repo, keyring, rewrites).await;
+    if let Err(err) = evict_cache(tmp, cache.git_cache_size * 1024 * 1024, &repo).await {
+        warn!("Failed to evict cached git repositories: {err:#}");
+    }
+    result
+}
+
+async fn snapshot_cached_repo(
+    db: &db::Client,
+    git: &GitUrl,
+    tmp: &str,
+    repo: &CachedRepo,
+    keyring: &args::GitKeyring,
+    rewrites: &[utils::RewriteRule],
+) -> Result<()> {
+    let path = &repo.path;
+
+    let reference = if let Some(tag) = &git.tag {
+        info!(
+            "Fetching git VCS tree-ish reference from {:?}: {:?}",
+            git.url, tag
+        );
+        let url = utils::rewrite_url(rewrites, &git.url);
+        fetch_refspec(path, &url, &format!("+{tag}:refs/tags/{tag}")).await?;
+        format!("refs/tags/{tag}")
+    } else if let Some(commit) = &git.commit {
+        info!("Fetching git commit from {:?}: {:?}", git.url, commit);
+        fetch_commit(path, &git.url, commit, rewrites).await?;
+        commit.to_string()
+    } else {
+        return Err(Error::InvalidGitRef(git.clone()));
+    };
+
+    info!("Resolving git ref: {reference:?}");
+    let output = git_output(path, &["rev-list", "-n1", &reference]).await?;
     if !output.status.success() {
-        return Err(Error::GitFetchError(status));
+        return Err(Error::GitError(output.status));
     }
     let Some(Ok(commit)) = output.stdout.lines().next() else {
         let output = String::from_utf8_lossy(&output.stdout).into_owned();
         return Err(Error::GitRevParseError(output));
     };
-    info!("Resolved ref FETCH_HEAD to git commit: {commit:?}");
+    info!("Resolved ref {reference:?} to git commit: {commit:?}");
+
+    // an annotated tag is a separate object and carries its own signature
+    let output = git_output(path, &["rev-parse", &reference]).await?;
+    let object = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let signature = if output.status.success() && object != commit {
+        verify_signature(path, &object, "tag", keyring).await?
+    } else {
+        verify_signature(path, &commit, "commit", keyring).await?
+    };
+    info!(
+        "Signature of git {} {:?}: {} (fingerprint: {:?})",
+        signature.kind,
+        signature.object,
+        signature.status.as_str(),
+        signature.fingerprint
+    );
+    if git.signed && signature.status != SignatureStatus::Valid {
+        warn!(
+            "Source demands a signed git {}, but signature is {}: {:?}",
+            signature.kind,
+            signature.status.as_str(),
+            git.url
+        );
+    }
+    db.insert_git_signature(&commit, &signature, git.signed)
+        .await?;
 
-    info!("Taking `git archive` snapshot of FETCH_HEAD");
+    if let Some(tag) = &git.tag {
+        let previous = db.insert_git_tag(&git.url, tag, &commit).await?;
+        if !previous.is_empty() {
+            let previous = previous.iter().map(|t| &t.commit).collect::<Vec<_>>();
+            warn!(
+                "Git tag {tag:?} of {:?} moved to {commit:?}, previously: {previous:?}",
+                git.url
+            );
+        }
+    }
+
+    if git.submodules {
+        let workdir = Path::new(tmp).join(format!("git-archives-{:016x}", fastrand::u64(..)));
+        fs::create_dir_all(&workdir).await?;
+
+        info!("Taking `git archive` snapshot of {commit:?} including submodules");
+        let result =
+            archive_with_submodules(Some(db), git, path, &commit, &workdir, rewrites).await;
+        fs::remove_dir_all(&workdir).await?;
+        let (summary, submodules) = result?;
+
+        let chksum = &summary.inner_digests.sha256;
+        db.insert_alias_from_to(&format!("gitsm:{commit}"), chksum, "git-archive-submodules")
+            .await?;
+
+        for submodule in &submodules {
+            db.insert_artifact_submodule(chksum, submodule).await?;
+
+            // also snapshot each submodule on its own
+            let url = format!("git+{}#commit={}", submodule.url, submodule.commit);
+            if let Some(task) = utils::task_for_url(&url) {
+                db.insert_task(&task).await?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    info!("Taking `git archive` snapshot of {commit:?}");
     let mut child = process::Command::new("git")
         .args([
             "-C",
-            &path,
+            path,
             "-c",
             "core.abbrev=no",
             "archive",
             "--format",
             "tar",
-            "FETCH_HEAD",
+            &commit,
         ])
         .stdout(Stdio::piped())
         .spawn()?;
@@ -158,7 +851,7 @@ pub async fn take_snapshot(db: &db::Client, git: &GitUrl, tmp: &str) -> Result<(
 
     let status = child.wait().await?;
     if !status.success() {
-        return Err(Error::GitFetchError(status));
+        return Err(Error::GitError(status));
     }
 
     db.insert_alias_from_to(
@@ -174,7 +867,15 @@ pub async fn take_snapshot(db: &db::Client, git: &GitUrl, tmp: &str) -> Result<(
 pub async fn run(args: &args::IngestGit) -> Result<()> {
     let db = db::Client::create().await?;
 
-    take_snapshot(&db, &args.git, &args.tmp).await?;
+    take_snapshot(
+        &db,
+        &args.git,
+        &args.tmp,
+        &args.cache,
+        &args.keyring,
+        &args.rewrites.rules,
+    )
+    .await?;
 
     Ok(())
 }
@@ -193,6 +894,8 @@ mod tests {
                 url: "https://github.com/curl/curl.git".to_string(),
                 tag: None,
                 commit: None,
+                submodules: false,
+                signed: true,
             }
         );
     }
@@ -207,10 +910,102 @@ mod tests {
                 url: "https://github.com/curl/curl.git".to_string(),
                 tag: Some("curl-8_7_1".to_string()),
                 commit: None,
+                submodules: false,
+                signed: true,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_gitsm_url() {
+        let url = "gitsm+https://github.com/ziglang/zig.git#commit=d9e9f5e1a3a8b5e1e4b5a0c1f2b8d1f7c9b3a2e4";
+        let git = url.parse::<GitUrl>().unwrap();
+        assert_eq!(
+            git,
+            GitUrl {
+                url: "https://github.com/ziglang/zig.git".to_string(),
+                tag: None,
+                commit: Some("d9e9f5e1a3a8b5e1e4b5a0c1f2b8d1f7c9b3a2e4".to_string()),
+                submodules: true,
+                signed: false,
             }
         );
     }
 
+    #[test]
+    fn test_parse_gitlinks() {
+        let ls_tree = b"100644 blob 8e66654a1d4a6f2a9c1e5d5c1d0b5e2f3a4b5c6d\t.gitmodules\x00160000 commit 0b4fbcf8a1c6a0ef6c6d1b1d82ab5cb4e6a1f3e2\tdeps/zlib\x00100644 blob e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\tREADME\x00";
+        assert_eq!(
+            parse_gitlinks(ls_tree),
+            vec![(
+                "deps/zlib".to_string(),
+                "0b4fbcf8a1c6a0ef6c6d1b1d82ab5cb4e6a1f3e2".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_gitmodules() {
+        let config = "submodule.zlib.path deps/zlib
+submodule.zlib.url https://github.com/madler/zlib.git
+submodule.zlib.branch develop
+submodule.docs.path docs
+submodule.docs.url ../docs.git
+submodule.orphan.path orphan
+";
+        assert_eq!(
+            parse_gitmodules(config),
+            [
+                (
+                    "deps/zlib".to_string(),
+                    "https://github.com/madler/zlib.git".to_string()
+                ),
+                ("docs".to_string(), "../docs.git".to_string()),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn test_validate_remote() {
+        for url in [
+            "https://github.com/kpcyrd/what-the-src.git",
+            "git://git.kernel.org/pub/scm/git/git.git",
+            "ssh://git@example.com/repo.git",
+        ] {
+            assert!(validate_remote(url).is_ok(), "{url:?}");
+        }
+        for url in [
+            "--upload-pack=touch /tmp/pwned://example.com/repo.git",
+            "-uhttps://example.com/repo.git",
+            "file:///etc",
+            "/etc",
+            "../repo.git",
+            "ext::sh -c touch% /tmp/pwned",
+            "http://example.com/repo.git",
+        ] {
+            assert!(validate_remote(url).is_err(), "{url:?}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_submodule_url() {
+        let base = "https://github.com/example/project.git";
+        assert_eq!(
+            resolve_submodule_url(base, "../docs.git"),
+            "https://github.com/example/docs.git"
+        );
+        assert_eq!(
+            resolve_submodule_url(base, "./vendor/lib.git"),
+            "https://github.com/example/project.git/vendor/lib.git"
+        );
+        assert_eq!(
+            resolve_submodule_url(base, "https://gitlab.com/other/lib.git"),
+            "https://gitlab.com/other/lib.git"
+        );
+    }
+
     #[test]
     fn parse_git_url_commit() {
         let url = "git+https://github.com/rapid7/metasploit-framework.git?signed#commit=77fb7ae14f17fd7f4851bca87e0c28c704797591";
@@ -221,7 +1016,436 @@ mod tests {
                 url: "https://github.com/rapid7/metasploit-framework.git".to_string(),
                 tag: None,
                 commit: Some("77fb7ae14f17fd7f4851bca87e0c28c704797591".to_string()),
+                submodules: false,
+                signed: true,
             }
         );
     }
+
+    async fn git(dir: &Path, args: &[&str]) -> String {
+        let output = process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args([
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "-c",
+                "protocol.file.allow=always",
+            ])
+            .args(args)
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed: {output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_archive_with_submodules() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-git-test-{:016x}", fastrand::u64(..)));
+        let (lib, project, work, workdir) = (
+            tmp.join("lib"),
+            tmp.join("project"),
+            tmp.join("work"),
+            tmp.join("archives"),
+        );
+        for dir in [&lib, &project, &work, &workdir] {
+            fs::create_dir_all(dir).await.unwrap();
+        }
+
+        git(&lib, &["init", "-qb", "main"]).await;
+        fs::write(lib.join("lib.c"), "int lib() { return 1; }\n")
+            .await
+            .unwrap();
+        git(&lib, &["add", "lib.c"]).await;
+        git(&lib, &["commit", "-qm", "init"]).await;
+        let lib_commit = git(&lib, &["rev-parse", "HEAD"]).await;
+
+        git(&project, &["init", "-qb", "main"]).await;
+        fs::write(project.join("main.c"), "int main() { return 0; }\n")
+            .await
+            .unwrap();
+        git(&project, &["add", "main.c"]).await;
+        git(
+            &project,
+            &["submodule", "add", "-q", lib.to_str().unwrap(), "deps/lib"],
+        )
+        .await;
+        // local remotes are refused, the commit is made available in the repository upfront
+        let lib_url = "https://example.com/lib.git";
+        git(
+            &project,
+            &[
+                "config",
+                "-f",
+                ".gitmodules",
+                "submodule.deps/lib.url",
+                lib_url,
+            ],
+        )
+        .await;
+        git(&project, &["add", ".gitmodules"]).await;
+        git(&project, &["commit", "-qm", "init"]).await;
+        let commit = git(&project, &["rev-parse", "HEAD"]).await;
+
+        git(&work, &["init", "-qb", "main"]).await;
+        git(&work, &["fetch", "-q", project.to_str().unwrap(), &commit]).await;
+        git(&work, &["fetch", "-q", lib.to_str().unwrap(), &lib_commit]).await;
+
+        let url = format!("gitsm+{}#commit={commit}", project.to_str().unwrap());
+        let git_url = url.parse::<GitUrl>().unwrap();
+        let result = archive_with_submodules(
+            None,
+            &git_url,
+            work.to_str().unwrap(),
+            &commit,
+            &workdir,
+            &[],
+        )
+        .await;
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        let (summary, submodules) = result.unwrap();
+        assert_eq!(
+            submodules,
+            vec![Submodule {
+                path: "deps/lib".to_string(),
+                url: lib_url.to_string(),
+                commit: lib_commit,
+            }]
+        );
+        let paths = summary
+            .files
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                ".gitmodules",
+                "deps/",
+                "deps/lib/",
+                "main.c",
+                "deps/lib/lib.c"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malicious_submodule_urls() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-git-test-{:016x}", fastrand::u64(..)));
+        let (lib, project) = (tmp.join("lib"), tmp.join("project"));
+        for dir in [&lib, &project] {
+            fs::create_dir_all(dir).await.unwrap();
+        }
+
+        git(&lib, &["init", "-qb", "main"]).await;
+        git(&lib, &["commit", "-q", "--allow-empty", "-m", "init"]).await;
+
+        git(&project, &["init", "-qb", "main"]).await;
+        git(
+            &project,
+            &["submodule", "add", "-q", lib.to_str().unwrap(), "deps/lib"],
+        )
+        .await;
+
+        let pwned = tmp.join("pwned");
+        let mut results = Vec::new();
+        for url in [
+            format!("--upload-pack=touch {}", pwned.display()),
+            "file:///etc".to_string(),
+            "/etc".to_string(),
+            "ext::sh -c touch% /tmp/pwned".to_string(),
+            "../../../../etc".to_string(),
+        ] {
+            git(
+                &project,
+                &[
+                    "config",
+                    "-f",
+                    ".gitmodules",
+                    "submodule.deps/lib.url",
+                    &url,
+                ],
+            )
+            .await;
+            git(&project, &["add", ".gitmodules"]).await;
+            git(&project, &["commit", "-qm", "update"]).await;
+            let commit = git(&project, &["rev-parse", "HEAD"]).await;
+
+            let result = read_submodules(
+                project.to_str().unwrap(),
+                "https://example.com/project.git",
+                &commit,
+            )
+            .await;
+            results.push((url, result));
+        }
+        let pwned = fs::metadata(&pwned).await.is_ok();
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        assert!(!pwned);
+        for (url, result) in results {
+            assert!(
+                matches!(result, Err(Error::UnsupportedGitRemote(_))),
+                "{url:?}: {result:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_repo_eviction() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-git-test-{:016x}", fastrand::u64(..)));
+        let origin = tmp.join("origin");
+        fs::create_dir_all(&origin).await.unwrap();
+        git(&origin, &["init", "-qb", "main"]).await;
+        fs::write(origin.join("README"), "hello world\n")
+            .await
+            .unwrap();
+        git(&origin, &["add", "README"]).await;
+        git(&origin, &["commit", "-qm", "init"]).await;
+        let commit = git(&origin, &["rev-parse", "HEAD"]).await;
+
+        let cache = tmp.join("cache");
+        let cache = cache.to_str().unwrap();
+        let url = "https://example.com/origin.git";
+
+        // populate the cache from the local repository, fetch_commit finds the commit present
+        let repo = open_cached_repo(cache, url).await.unwrap();
+        git(
+            Path::new(&repo.path),
+            &["fetch", "-q", origin.to_str().unwrap(), &commit],
+        )
+        .await;
+        fetch_commit(&repo.path, url, &commit, &[]).await.unwrap();
+        let first = repo.path.clone();
+
+        // repositories that are in use are not evicted
+        let other = open_cached_repo(cache, "https://example.com/other.git")
+            .await
+            .unwrap();
+        evict_cache(cache, 0, &other).await.unwrap();
+        assert!(fs::metadata(&first).await.is_ok());
+        let first_size = format!("{}.size", first.strip_suffix(".git").unwrap());
+        assert_ne!(fs::read_to_string(&first_size).await.unwrap(), "0");
+
+        // the recorded size is trusted, this repository now fits into the cache
+        fs::write(&first_size, "0").await.unwrap();
+        drop(repo);
+        evict_cache(
+            cache,
+            dir_size(Path::new(&other.path)).await.unwrap(),
+            &other,
+        )
+        .await
+        .unwrap();
+        assert!(fs::metadata(&first).await.is_ok());
+
+        evict_cache(cache, 0, &other).await.unwrap();
+        assert!(fs::metadata(&first).await.is_err());
+        assert!(fs::metadata(&first_size).await.is_err());
+        assert!(fs::metadata(&other.path).await.is_ok());
+
+        // a new repository is set up after eviction
+        let repo = open_cached_repo(cache, url).await.unwrap();
+        assert_eq!(repo.path, first);
+        assert!(fs::metadata(format!("{}/HEAD", repo.path)).await.is_ok());
+        drop(repo);
+
+        fs::remove_dir_all(&tmp).await.unwrap();
+    }
+
+    #[test]
+    fn test_detect_signature_format() {
+        let tag = b"object 4ef813a52dff31a41c5fd50cc24a777688c4e4c0
+type commit
+tag v1.0
+tagger Test <test@example.com> 1720000000 +0000
+
+v1.0
+-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQ==
+-----END SSH SIGNATURE-----
+";
+        assert_eq!(SignatureFormat::detect(tag), Some(SignatureFormat::Ssh));
+        let commit = b"tree 8e66654a1d4a6f2a9c1e5d5c1d0b5e2f3a4b5c6d
+author Test <test@example.com> 1720000000 +0000
+committer Test <test@example.com> 1720000000 +0000
+gpgsig -----BEGIN PGP SIGNATURE-----
+ iHUEABYKAB0WIQQ1KTdAVL/aM6P2WV2MHQ6G05I/IAUCZw==
+ -----END PGP SIGNATURE-----
+
+init
+";
+        assert_eq!(
+            SignatureFormat::detect(commit),
+            Some(SignatureFormat::OpenPgp)
+        );
+        assert_eq!(SignatureFormat::detect(b"tree 8e66654a\n\ninit\n"), None);
+    }
+
+    #[test]
+    fn test_parse_gpg_status_valid() {
+        let output = "[GNUPG:] NEWSIG
+[GNUPG:] KEY_CONSIDERED 3529374054BFDA33A3F6595D8C1D0E86D3923F20 0
+[GNUPG:] SIG_ID oryteJ7yqi5Fhmc97357FDj9bro 2024-07-09 1720512000
+[GNUPG:] GOODSIG 8C1D0E86D3923F20 Test <test@example.com>
+[GNUPG:] VALIDSIG 3529374054BFDA33A3F6595D8C1D0E86D3923F20 2024-07-09 1720512000 0 4 0 22 8 00 3529374054BFDA33A3F6595D8C1D0E86D3923F20
+[GNUPG:] TRUST_ULTIMATE 0 pgp
+";
+        assert_eq!(
+            parse_gpg_status(output),
+            (
+                SignatureStatus::Valid,
+                Some("3529374054BFDA33A3F6595D8C1D0E86D3923F20".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_gpg_status_unknown_key() {
+        let output = "[GNUPG:] NEWSIG
+[GNUPG:] ERRSIG 8C1D0E86D3923F20 22 8 00 1720512000 9 3529374054BFDA33A3F6595D8C1D0E86D3923F20
+[GNUPG:] NO_PUBKEY 8C1D0E86D3923F20
+";
+        assert_eq!(
+            parse_gpg_status(output),
+            (
+                SignatureStatus::UnknownKey,
+                Some("3529374054BFDA33A3F6595D8C1D0E86D3923F20".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_gpg_status_bad() {
+        let output = "[GNUPG:] NEWSIG
+[GNUPG:] KEY_CONSIDERED 3529374054BFDA33A3F6595D8C1D0E86D3923F20 0
+[GNUPG:] BADSIG 8C1D0E86D3923F20 Test <test@example.com>
+";
+        assert_eq!(
+            parse_gpg_status(output),
+            (
+                SignatureStatus::Invalid,
+                Some("8C1D0E86D3923F20".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_ssh_verify() {
+        let output = "Good \"git\" signature for test@example.com with ED25519 key SHA256:vgKtOQ7mT/JZgArREFGP9euygwpdUN0ECvOxNnr7qDk\n";
+        assert_eq!(
+            parse_ssh_verify(output, true),
+            (
+                SignatureStatus::Valid,
+                Some("SHA256:vgKtOQ7mT/JZgArREFGP9euygwpdUN0ECvOxNnr7qDk".to_string())
+            )
+        );
+
+        let output = "Good \"git\" signature with ED25519 key SHA256:vgKtOQ7mT/JZgArREFGP9euygwpdUN0ECvOxNnr7qDk
+No principal matched.
+";
+        assert_eq!(
+            parse_ssh_verify(output, false),
+            (
+                SignatureStatus::UnknownKey,
+                Some("SHA256:vgKtOQ7mT/JZgArREFGP9euygwpdUN0ECvOxNnr7qDk".to_string())
+            )
+        );
+
+        assert_eq!(
+            parse_ssh_verify("Could not verify signature.\n", false),
+            (SignatureStatus::Invalid, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_ssh_signature() {
+        if std::process::Command::new("ssh-keygen")
+            .arg("-?")
+            .output()
+            .is_err()
+        {
+            eprintln!("ssh-keygen is not installed, skipping test");
+            return;
+        }
+
+        let tmp = std::env::temp_dir().join(format!("whatsrc-git-test-{:016x}", fastrand::u64(..)));
+        let repo = tmp.join("repo");
+        fs::create_dir_all(&repo).await.unwrap();
+        let key = tmp.join("key");
+        let status = process::Command::new("ssh-keygen")
+            .args([
+                "-q",
+                "-t",
+                "ed25519",
+                "-N",
+                "",
+                "-C",
+                "test@example.com",
+                "-f",
+            ])
+            .arg(&key)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        let public_key = fs::read_to_string(tmp.join("key.pub")).await.unwrap();
+        let allowed_signers = tmp.join("allowed_signers");
+        fs::write(&allowed_signers, format!("test@example.com {public_key}"))
+            .await
+            .unwrap();
+
+        git(&repo, &["init", "-qb", "main"]).await;
+        fs::write(repo.join("README"), "hello world\n")
+            .await
+            .unwrap();
+        git(&repo, &["add", "README"]).await;
+        git(&repo, &["commit", "-qm", "init"]).await;
+        let signing_key = format!("user.signingkey={}", key.to_str().unwrap());
+        git(
+            &repo,
+            &[
+                "-c",
+                "gpg.format=ssh",
+                "-c",
+                &signing_key,
+                "tag",
+                "-s",
+                "-m",
+                "v1.0",
+                "v1.0",
+            ],
+        )
+        .await;
+        git(&repo, &["tag", "-a", "-m", "v1.1", "v1.1"]).await;
+        let signed = git(&repo, &["rev-parse", "v1.0"]).await;
+        let unsigned = git(&repo, &["rev-parse", "v1.1"]).await;
+
+        let path = repo.to_str().unwrap();
+        let trusted = args::GitKeyring {
+            git_gnupghome: None,
+            git_allowed_signers: Some(allowed_signers),
+        };
+        let untrusted = args::GitKeyring {
+            git_gnupghome: None,
+            git_allowed_signers: None,
+        };
+        let valid = verify_signature(path, &signed, "tag", &trusted).await;
+        let unknown = verify_signature(path, &signed, "tag", &untrusted).await;
+        let missing = verify_signature(path, &unsigned, "tag", &trusted).await;
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        let valid = valid.unwrap();
+        assert_eq!(valid.format, Some(SignatureFormat::Ssh));
+        assert_eq!(valid.status, SignatureStatus::Valid);
+        assert!(valid.fingerprint.unwrap().starts_with("SHA256:"));
+        assert_eq!(unknown.unwrap().status, SignatureStatus::UnknownKey);
+        let missing = missing.unwrap();
+        assert_eq!(missing.status, SignatureStatus::Unsigned);
+        assert_eq!(missing.format, None);
+    }
 }
@@ -0,0 +1,256 @@
+use crate::args;
+use crate::db;
+use crate::errors::*;
+use crate::ingest;
+use crate::ingest::git::CLONE_TIMEOUT;
+use crate::utils;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::fs;
+use tokio::process;
+use tokio::time;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HgUrl {
+    url: String,
+    revision: Option<String>,
+}
+
+impl FromStr for HgUrl {
+    type Err = Error;
+
+    fn from_str(full_url: &str) -> Result<HgUrl> {
+        let url = full_url.strip_prefix("hg+").unwrap_or(full_url);
+
+        let (url, info) = url
+            .rsplit_once('#')
+            .map(|(url, info)| (url, Some(info)))
+            .unwrap_or((url, None));
+
+        let mut hg = HgUrl {
+            url: url.to_string(),
+            ..Default::default()
+        };
+
+        if let Some(info) = info {
+            match info.split_once('=') {
+                Some(("revision", value)) => hg.revision = Some(value.to_string()),
+                _ => return Err(Error::UnknownVcsRef(info.to_string())),
+            };
+        }
+
+        Ok(hg)
+    }
+}
+
+async fn hg(args: &[&str]) -> Result<std::process::Output> {
+    let child = process::Command::new("hg")
+        .args(["--noninteractive", "--config", "ui.archivemeta=false"])
+        .args(args)
+        .output();
+    let Ok(output) = time::timeout(CLONE_TIMEOUT, child).await else {
+        return Err(Error::VcsTimeout);
+    };
+    let output = output?;
+    if !output.status.success() {
+        return Err(Error::HgError(output.status));
+    }
+    Ok(output)
+}
+
+/// Clone the repository into `path` and ingest an archive of the given revision
+///
+/// Returns the full changeset id the revision resolved to.
+pub async fn snapshot(
+    db: Option<&db::Client>,
+    hg_url: &HgUrl,
+    path: &Path,
+) -> Result<(String, ingest::tar::TarSummary)> {
+    let Some(revision) = &hg_url.revision else {
+        return Err(Error::MissingVcsRevision(hg_url.url.clone()));
+    };
+    // hg would interpret this as an option, e.g. `--config=hooks...`
+    if hg_url.url.starts_with('-') {
+        return Err(Error::UnsupportedUrl(hg_url.url.clone()));
+    }
+
+    let repo = path.join("repo");
+    let repo = repo.to_str().ok_or(Error::InvalidData)?;
+    let export = path.join("export");
+
+    info!(
+        "Cloning mercurial revision from {:?}: {:?}",
+        hg_url.url, revision
+    );
+    hg(&[
+        "clone",
+        "--noupdate",
+        "--rev",
+        revision,
+        "--",
+        &hg_url.url,
+        repo,
+    ])
+    .await?;
+
+    // tags are resolved by the remote and may not exist locally, the requested revision is our only head
+    let output = hg(&[
+        "--repository",
+        repo,
+        "log",
+        "--rev",
+        "tip",
+        "--template",
+        "{node}",
+    ])
+    .await?;
+    let node = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if node.len() != 40 || !node.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::HgLogError(node));
+    }
+    info!("Resolved mercurial revision to changeset: {node:?}");
+
+    let export_str = export.to_str().ok_or(Error::InvalidData)?;
+    hg(&[
+        "--repository",
+        repo,
+        "archive",
+        "--rev",
+        &node,
+        "--type",
+        "files",
+        export_str,
+    ])
+    .await?;
+
+    let summary = ingest::tar::stream_dir(db, &export).await?;
+    Ok((node, summary))
+}
+
+pub async fn take_snapshot(
+    db: &db::Client,
+    hg_url: &HgUrl,
+    tmp: &str,
+    rewrites: &[utils::RewriteRule],
+) -> Result<()> {
+    let path = Path::new(tmp).join(format!("hg-{:016x}", fastrand::u64(..)));
+    fs::create_dir_all(&path).await?;
+
+    let remote = HgUrl {
+        url: utils::rewrite_url(rewrites, &hg_url.url),
+        ..hg_url.clone()
+    };
+    let result = snapshot(Some(db), &remote, &path).await;
+    if let Err(err) = fs::remove_dir_all(&path).await {
+        warn!("Failed to remove temporary directory {path:?}: {err:#}");
+    }
+    let (node, summary) = result?;
+
+    db.insert_alias_from_to(
+        &format!("hg:{node}"),
+        &summary.inner_digests.sha256,
+        "hg-archive",
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn run(args: &args::IngestHg) -> Result<()> {
+    let db = db::Client::create().await?;
+
+    take_snapshot(&db, &args.hg, &args.tmp, &args.rewrites.rules).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hg_url() {
+        let url = "hg+https://hg.mozilla.org/projects/nss#revision=NSS_3_101_RTM";
+        let hg = url.parse::<HgUrl>().unwrap();
+        assert_eq!(
+            hg,
+            HgUrl {
+                url: "https://hg.mozilla.org/projects/nss".to_string(),
+                revision: Some("NSS_3_101_RTM".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_hg_url_unknown_ref() {
+        let url = "hg+https://hg.mozilla.org/projects/nss#branch=default";
+        assert!(url.parse::<HgUrl>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_option_url() {
+        let hg_url = "hg+--config=hooks.pre-clone=touch /tmp/pwned#revision=default"
+            .parse::<HgUrl>()
+            .unwrap();
+        let result = snapshot(None, &hg_url, Path::new("/nonexistent")).await;
+        assert!(matches!(result, Err(Error::UnsupportedUrl(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires mercurial"]
+    async fn test_snapshot_local_repo() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-hg-test-{:016x}", fastrand::u64(..)));
+        let origin = tmp.join("origin");
+        let origin_str = origin.to_str().unwrap();
+        fs::create_dir_all(&origin).await.unwrap();
+        hg(&["init", origin_str]).await.unwrap();
+        fs::write(origin.join("hello.txt"), "hello world\n")
+            .await
+            .unwrap();
+        hg(&["--repository", origin_str, "add", "hello.txt"])
+            .await
+            .unwrap();
+        hg(&[
+            "--repository",
+            origin_str,
+            "commit",
+            "--user",
+            "test <test@example.com>",
+            "--date",
+            "0 0",
+            "--message",
+            "init",
+        ])
+        .await
+        .unwrap();
+        hg(&[
+            "--repository",
+            origin_str,
+            "tag",
+            "--user",
+            "test",
+            "--date",
+            "0 0",
+            "v1.0",
+        ])
+        .await
+        .unwrap();
+
+        let hg_url = format!("hg+{origin_str}#revision=v1.0")
+            .parse::<HgUrl>()
+            .unwrap();
+        let work = tmp.join("work");
+        fs::create_dir_all(&work).await.unwrap();
+        let result = snapshot(None, &hg_url, &work).await;
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        let (node, summary) = result.unwrap();
+        assert_eq!(node.len(), 40);
+        let paths = summary
+            .files
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["hello.txt"]);
+    }
+}
@@ -1,7 +1,11 @@
 pub mod alpine;
+pub mod dir;
 pub mod git;
+pub mod hg;
 pub mod pacman;
 pub mod rpm;
+pub mod signature;
+pub mod svn;
 pub mod tar;
 pub mod void;
 pub mod wolfi;
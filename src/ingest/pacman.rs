@@ -1,12 +1,14 @@
 use crate::args;
 use crate::db;
 use crate::errors::*;
+use crate::ingest::signature;
 use crate::pkgbuild;
 use crate::sbom;
 use crate::utils;
 use async_compression::tokio::bufread::GzipDecoder;
 use futures::StreamExt;
 use srcinfo::Srcinfo;
+use std::collections::HashMap;
 use tokio::io::{self, AsyncRead, AsyncReadExt};
 use tokio_tar::Archive;
 
@@ -14,9 +16,20 @@ use tokio_tar::Archive;
 pub struct Snapshot {
     pkgbuild: String,
     srcinfo: Option<String>,
+    /// Public keys in `keys/pgp/`, trusted to sign upstream releases
+    pgp_keys: Vec<String>,
 }
 
 impl Snapshot {
+    /// A snapshot that only consists of `.SRCINFO`, e.g. from the AUR metadata
+    pub fn from_srcinfo(srcinfo: String) -> Snapshot {
+        Snapshot {
+            pkgbuild: String::new(),
+            srcinfo: Some(srcinfo),
+            pgp_keys: Vec::new(),
+        }
+    }
+
     pub async fn parse_from_tgz<R: AsyncRead + Unpin>(
         db: &db::Client,
         reader: R,
@@ -27,6 +40,7 @@ impl Snapshot {
 
         let mut pkgbuild = None;
         let mut srcinfo = None;
+        let mut pgp_keys = Vec::new();
 
         let mut entries = tar.entries()?;
         while let Some(entry) = entries.next().await {
@@ -35,6 +49,14 @@ impl Snapshot {
             let Some(file_name) = path.file_name() else {
                 continue;
             };
+            if path.parent().is_some_and(|p| p.ends_with("keys/pgp"))
+                && path.extension().is_some_and(|ext| ext == "asc")
+            {
+                let mut buf = String::new();
+                entry.read_to_string(&mut buf).await?;
+                pgp_keys.push(buf);
+                continue;
+            }
             match file_name.to_str() {
                 Some(".SRCINFO") => {
                     let mut buf = String::new();
@@ -71,17 +93,19 @@ impl Snapshot {
         Ok(Snapshot {
             pkgbuild: pkgbuild.ok_or(Error::InvalidData)?,
             srcinfo,
+            pgp_keys,
         })
     }
 
-    fn get_from_archvec(vec: &[srcinfo::ArchVec]) -> &[String] {
+    fn get_from_archvec<'a>(vec: &'a [srcinfo::ArchVec], arch: Option<&str>) -> &'a [String] {
         vec.iter()
-            .find(|x| x.arch.is_none())
+            .find(|x| x.arch.as_deref() == arch)
             .map(|e| &e.vec[..])
             .unwrap_or(&[])
     }
 
     fn source_entries_from_lists(
+        arch: Option<&str>,
         max: usize,
         sources: &[String],
         sha256sums: &[String],
@@ -109,6 +133,7 @@ impl Snapshot {
                 sha256: Self::filter_skip(sha256sums.get(i)),
                 sha512: Self::filter_skip(sha512sums.get(i)),
                 blake2b: Self::filter_skip(b2sums.get(i)),
+                arch: arch.map(String::from),
             });
         }
         out
@@ -118,41 +143,69 @@ impl Snapshot {
         v.filter(|v| *v != "SKIP").cloned()
     }
 
+    /// Fingerprints of the keys that are allowed to sign upstream releases
+    pub fn valid_pgp_keys(&self) -> Result<Vec<String>> {
+        let Some(srcinfo) = &self.srcinfo else {
+            return Ok(vec![]);
+        };
+        let srcinfo = Srcinfo::parse_buf(srcinfo.as_bytes())?;
+        Ok(srcinfo.base.valid_pgp_keys)
+    }
+
     pub fn source_entries(&self) -> Result<Vec<SourceEntry>> {
+        let mut out = Vec::new();
         if let Some(srcinfo) = &self.srcinfo {
             let srcinfo = Srcinfo::parse_buf(srcinfo.as_bytes())?;
-            let sources = Self::get_from_archvec(&srcinfo.base.source);
-            let sha256sums = Self::get_from_archvec(&srcinfo.base.sha256sums);
-            let sha512sums = Self::get_from_archvec(&srcinfo.base.sha512sums);
-            let b2sums = Self::get_from_archvec(&srcinfo.base.b2sums);
-
-            Ok(Self::source_entries_from_lists(
-                sources.len(),
-                sources,
-                sha256sums,
-                sha512sums,
-                b2sums,
-            ))
+            let base = &srcinfo.base;
+
+            let mut arches = vec![None];
+            for arch in base.source.iter().flat_map(|x| x.arch.as_deref()) {
+                if !arches.contains(&Some(arch)) {
+                    arches.push(Some(arch));
+                }
+            }
+
+            for arch in arches {
+                let sources = Self::get_from_archvec(&base.source, arch);
+                out.extend(Self::source_entries_from_lists(
+                    arch,
+                    sources.len(),
+                    sources,
+                    Self::get_from_archvec(&base.sha256sums, arch),
+                    Self::get_from_archvec(&base.sha512sums, arch),
+                    Self::get_from_archvec(&base.b2sums, arch),
+                ));
+            }
         } else {
             let pkgbuild = pkgbuild::parse(self.pkgbuild.as_bytes())?;
 
-            let max = [
-                pkgbuild.sha256sums.len(),
-                pkgbuild.sha512sums.len(),
-                pkgbuild.b2sums.len(),
-            ]
-            .into_iter()
-            .max()
-            .unwrap_or(0);
-
-            Ok(Self::source_entries_from_lists(
-                max,
-                &[],
-                &pkgbuild.sha256sums,
-                &pkgbuild.sha512sums,
-                &pkgbuild.b2sums,
-            ))
+            let arches = [(None, &pkgbuild.sums)].into_iter().chain(
+                pkgbuild
+                    .arch
+                    .iter()
+                    .map(|(arch, x)| (Some(arch.as_str()), x)),
+            );
+            for (arch, sums) in arches {
+                let max = [
+                    sums.sha256sums.len(),
+                    sums.sha512sums.len(),
+                    sums.b2sums.len(),
+                ]
+                .into_iter()
+                .max()
+                .unwrap_or(0);
+
+                out.extend(Self::source_entries_from_lists(
+                    arch,
+                    max,
+                    &[],
+                    &sums.sha256sums,
+                    &sums.sha512sums,
+                    &sums.b2sums,
+                ));
+            }
         }
+        Ok(out)
     }
 }
 
@@ -162,6 +215,8 @@ pub struct SourceEntry {
     sha256: Option<String>,
     sha512: Option<String>,
     blake2b: Option<String>,
+    /// The architecture this source is specific to, if any
+    arch: Option<String>,
 }
 
 impl SourceEntry {
@@ -175,38 +230,129 @@ impl SourceEntry {
     }
 }
 
-pub async fn stream_data<R: AsyncRead + Unpin>(
+/// The architectures each checksum is used for, `None` if it's used for all of them
+fn merge_arches(entries: &[SourceEntry]) -> HashMap<String, Option<String>> {
+    let mut out = HashMap::<String, Option<String>>::new();
+    for entry in entries {
+        let Some(chksum) = entry.preferred_chksum() else {
+            continue;
+        };
+        match (out.get_mut(&chksum), &entry.arch) {
+            (None, arch) => {
+                out.insert(chksum, arch.clone());
+            }
+            (Some(Some(arches)), Some(arch)) => {
+                if !arches.split(' ').any(|x| x == arch) {
+                    arches.push(' ');
+                    arches.push_str(arch);
+                }
+            }
+            (Some(merged), None) => *merged = None,
+            (Some(None), Some(_)) => (),
+        }
+    }
+    out
+}
+
+/// Sources of user submitted packages (AUR) may only be fetched over https, so they can't
+/// point to local files or smuggle options into git
+pub fn is_allowed_untrusted_url(url: &str) -> bool {
+    let url = url.strip_prefix("git+").unwrap_or(url);
+    let Some(rest) = url.strip_prefix("https://") else {
+        return false;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map(|(_, host)| host)
+        .unwrap_or(authority);
+    !host.is_empty() && !host.starts_with('-')
+}
+
+/// Vendors that review the keys in `keys/pgp/` of a package, only these are imported into the
+/// shared release keyring
+pub const TRUSTED_KEY_VENDORS: &[&str] = &["archlinux"];
+
+/// Record the sources of a snapshot as refs and queue tasks to fetch them
+///
+/// If the snapshot is not `trusted`, only sources allowed by [`is_allowed_untrusted_url`] are
+/// fetched.
+pub async fn insert_source_entries(
     db: &db::Client,
-    reader: R,
+    snapshot: &Snapshot,
     vendor: &str,
     package: &str,
     version: &str,
-    prefer_pkgbuild: bool,
+    trusted: bool,
 ) -> Result<()> {
-    let mut snapshot = Snapshot::parse_from_tgz(db, reader).await?;
-    if prefer_pkgbuild {
-        snapshot.srcinfo = None;
-    }
+    let valid_pgp_keys = snapshot.valid_pgp_keys()?;
 
-    for entry in snapshot.source_entries()? {
+    let entries = snapshot.source_entries()?;
+    let urls = entries
+        .iter()
+        .flat_map(|entry| entry.url.clone())
+        .collect::<Vec<_>>();
+    let arches = merge_arches(&entries);
+
+    for entry in entries {
         debug!("Found source entry: {entry:?}");
         let Some(chksum) = entry.preferred_chksum() else {
             continue;
         };
 
         // TODO: check if already known
-        if let Some(url) = &entry.url {
-            if let Some(task) = utils::task_for_url(url) {
+        if let Some(url) = entry
+            .url
+            .as_ref()
+            .filter(|url| trusted || is_allowed_untrusted_url(url))
+        {
+            let task = match signature::find_signature_url(url, &urls) {
+                // verify the signature while downloading, so the file is only fetched once
+                Some(signature_url)
+                    if utils::is_possible_tar_artifact(url)
+                        && db.resolve_artifact(&chksum).await?.is_none() =>
+                {
+                    Some(signature::fetch_task(
+                        vendor,
+                        url,
+                        signature_url,
+                        &chksum,
+                        &valid_pgp_keys,
+                    )?)
+                }
+                // the artifact is already known, only download it again if it was never verified
+                Some(signature_url) => {
+                    if !db.has_artifact_signature(&chksum, vendor).await? {
+                        let task = signature::verify_task(
+                            vendor,
+                            url,
+                            signature_url,
+                            &chksum,
+                            &valid_pgp_keys,
+                        )?;
+                        db.insert_task(&task).await?;
+                    }
+                    utils::task_for_url(url)
+                }
+                None => utils::task_for_url(url),
+            };
+            if let Some(task) = task {
                 db.insert_task(&task).await?;
             }
+        } else if let Some(url) = &entry.url {
+            warn!("Not fetching source of untrusted package {package:?}: {url:?}");
         }
 
+        let arch = arches.get(&chksum).cloned().flatten();
         let r = db::Ref {
             chksum,
             vendor: vendor.to_string(),
             package: package.to_string(),
             version: version.to_string(),
             filename: entry.url,
+            role: None,
+            repo: None,
+            arch,
         };
         info!("insert: {r:?}");
         db.insert_ref(&r).await?;
@@ -215,6 +361,38 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
     Ok(())
 }
 
+pub async fn stream_data<R: AsyncRead + Unpin>(
+    db: &db::Client,
+    reader: R,
+    vendor: &str,
+    package: &str,
+    version: &str,
+    prefer_pkgbuild: bool,
+    keyring: &args::ReleaseKeyring,
+) -> Result<()> {
+    let mut snapshot = Snapshot::parse_from_tgz(db, reader).await?;
+    if prefer_pkgbuild {
+        snapshot.srcinfo = None;
+    }
+
+    // key handling is best-effort and must never fail the import of the sources
+    if !snapshot.pgp_keys.is_empty() {
+        if !TRUSTED_KEY_VENDORS.contains(&vendor) {
+            debug!("Not importing pgp keys of untrusted vendor: {vendor:?}");
+        } else if keyring.release_gnupghome.is_some() {
+            info!("Importing {} pgp keys", snapshot.pgp_keys.len());
+            if let Err(err) = signature::import_keys(keyring, &snapshot.pgp_keys).await {
+                warn!("Failed to import pgp keys of {package:?}: {err:#}");
+            }
+        } else {
+            debug!("No release keyring configured, not importing pgp keys");
+        }
+    }
+    insert_source_entries(db, &snapshot, vendor, package, version, true).await?;
+
+    Ok(())
+}
+
 pub async fn run(args: &args::IngestPacmanSnapshot) -> Result<()> {
     let db = db::Client::create().await?;
 
@@ -226,8 +404,98 @@ pub async fn run(args: &args::IngestPacmanSnapshot) -> Result<()> {
         &args.package,
         &args.version,
         args.prefer_pkgbuild,
+        &args.keyring,
     )
     .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_untrusted_urls() {
+        for url in [
+            "https://github.com/Jguer/yay/archive/v12.3.5.tar.gz",
+            "git+https://github.com/Jguer/yay.git#tag=v12.3.5",
+            "git+https://git@github.com/Jguer/yay.git",
+        ] {
+            assert!(is_allowed_untrusted_url(url), "{url:?}");
+        }
+        for url in [
+            "git+--upload-pack=touch /tmp/pwned://example.com/repo.git#commit=abcd",
+            "git+https://-uexample.com/repo.git",
+            "git+https://user@-uexample.com/repo.git",
+            "file:///etc/shadow",
+            "/etc/shadow",
+            "http://example.com/foo-1.0.tar.gz",
+            "git+file:///etc",
+            "hg+https://example.com/repo#revision=1",
+            "svn+https://example.com/repo#revision=1",
+            "https:///etc/shadow",
+        ] {
+            assert!(!is_allowed_untrusted_url(url), "{url:?}");
+        }
+    }
+
+    #[test]
+    fn test_source_entries_arch() {
+        let snapshot = Snapshot::from_srcinfo(
+            "pkgbase = example
+	pkgver = 1.0
+	pkgrel = 1
+	arch = x86_64
+	arch = aarch64
+	source = https://example.com/example-1.0.tar.gz
+	source = https://example.com/shared.patch
+	sha256sums = 7a1258a5dfc48c54cea1092adddb6bcfb1fcf19c7272c0a6a9e1d2d7daee6e12
+	sha256sums = 2fb6121a6eb4c5e6afaf22212b2ed15022500a4bc34bb3dc0f9782c1d43c3962
+	source_x86_64 = https://example.com/example-1.0-x86_64.bin
+	source_x86_64 = https://example.com/helper-1.0.tar.gz
+	sha256sums_x86_64 = f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128
+	sha256sums_x86_64 = 5d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c592
+	source_aarch64 = https://example.com/example-1.0-aarch64.bin
+	source_aarch64 = https://example.com/helper-1.0.tar.gz
+	sha256sums_aarch64 = cd594be73fcf632544195d09518901b1055ae86dcf463a5d446a83beba66073c
+	sha256sums_aarch64 = 5d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c592
+
+pkgname = example
+"
+            .to_string(),
+        );
+        let entries = snapshot.source_entries().unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.url.as_deref().unwrap(), e.arch.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("https://example.com/example-1.0.tar.gz", None),
+                ("https://example.com/shared.patch", None),
+                ("https://example.com/example-1.0-x86_64.bin", Some("x86_64")),
+                ("https://example.com/helper-1.0.tar.gz", Some("x86_64")),
+                (
+                    "https://example.com/example-1.0-aarch64.bin",
+                    Some("aarch64")
+                ),
+                ("https://example.com/helper-1.0.tar.gz", Some("aarch64")),
+            ]
+        );
+
+        let arches = merge_arches(&entries);
+        assert_eq!(
+            arches["sha256:f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128"],
+            Some("x86_64".to_string())
+        );
+        assert_eq!(
+            arches["sha256:5d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c592"],
+            Some("x86_64 aarch64".to_string())
+        );
+        assert_eq!(
+            arches["sha256:7a1258a5dfc48c54cea1092adddb6bcfb1fcf19c7272c0a6a9e1d2d7daee6e12"],
+            None
+        );
+    }
+}
@@ -1,139 +1,395 @@
 use crate::args;
 use crate::chksums::{Checksums, Hasher};
+use crate::compression::Decompressor;
 use crate::db;
 use crate::errors::*;
 use crate::ingest;
+use crate::rpm_spec;
 use crate::utils;
-use futures::StreamExt;
-use std::process::Stdio;
-use std::sync::Arc;
-use tokio::io::{self, AsyncRead};
-use tokio::process::Command;
-use tokio_tar::{Archive, EntryType};
+use rpm::CompressionType;
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncReadExt};
 
+const LEAD_SIZE: usize = 96;
+const HEADER_MAGIC: [u8; 4] = [0x8e, 0xad, 0xe8, 0x01];
+/// Refuse to allocate absurd amounts of memory for untrusted headers
+const MAX_HEADER_SIZE: usize = 64 * 1024 * 1024;
+
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const MAX_CPIO_NAME_SIZE: usize = 4096;
+
+/// Metadata from the rpm header
+#[derive(Debug, PartialEq)]
+pub struct RpmMetadata {
+    pub name: String,
+    pub version: String,
+    pub release: String,
+    /// Used to expand `%{url}` in the spec file
+    pub url: Option<String>,
+    pub compression: CompressionType,
+}
+
+impl RpmMetadata {
+    fn from_header(header: &rpm::PackageMetadata) -> Result<Self> {
+        Ok(RpmMetadata {
+            name: header.get_name()?.to_string(),
+            version: header.get_version()?.to_string(),
+            release: header.get_release()?.to_string(),
+            url: header.get_url().ok().map(String::from),
+            compression: header.get_payload_compressor()?,
+        })
+    }
+
+    /// Macros for spec file interpolation, these have already been expanded by rpmbuild
+    pub fn macros(&self) -> HashMap<String, String> {
+        let mut macros = HashMap::from([
+            ("name".to_string(), self.name.clone()),
+            ("version".to_string(), self.version.clone()),
+            ("release".to_string(), self.release.clone()),
+        ]);
+        if let Some(url) = &self.url {
+            macros.insert("url".to_string(), url.clone());
+        }
+        macros
+    }
+}
+
+/// Read a header structure (signature or main header) and append it to `buf`
+async fn read_header_structure<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    align: bool,
+) -> Result<()> {
+    let mut intro = [0u8; 16];
+    reader.read_exact(&mut intro).await?;
+    if intro[..4] != HEADER_MAGIC {
+        return Err(Error::InvalidRpm("Header is missing magic bytes"));
+    }
+    let index_count = u32::from_be_bytes(intro[8..12].try_into().unwrap()) as usize;
+    let data_size = u32::from_be_bytes(intro[12..16].try_into().unwrap()) as usize;
+
+    let mut size = index_count
+        .checked_mul(16)
+        .and_then(|n| n.checked_add(data_size))
+        .filter(|n| *n <= MAX_HEADER_SIZE)
+        .ok_or(Error::InvalidRpm("Header is too large"))?;
+    // the signature header is padded to 8 bytes
+    if align {
+        size += (8 - data_size % 8) % 8;
+    }
+
+    buf.extend_from_slice(&intro);
+    let start = buf.len();
+    buf.resize(start + size, 0);
+    reader.read_exact(&mut buf[start..]).await?;
+    Ok(())
+}
+
+/// Read the lead, signature and header of an rpm, the reader is then positioned at the payload
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<RpmMetadata> {
+    let mut buf = vec![0; LEAD_SIZE];
+    reader.read_exact(&mut buf).await?;
+    read_header_structure(reader, &mut buf, true).await?;
+    read_header_structure(reader, &mut buf, false).await?;
+
+    let header = rpm::PackageMetadata::parse(&mut &buf[..])?;
+    RpmMetadata::from_header(&header)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CpioHeader {
+    pub name: String,
+    pub mode: u32,
+    pub size: u64,
+}
+
+impl CpioHeader {
+    pub fn is_regular_file(&self) -> bool {
+        self.mode & 0o170000 == 0o100000
+    }
+}
+
+fn parse_hex_field(field: &[u8]) -> Result<u64> {
+    let field = std::str::from_utf8(field)?;
+    u64::from_str_radix(field, 16).map_err(|_| Error::InvalidRpm("Invalid number in cpio header"))
+}
+
+async fn skip_padding<R: AsyncRead + Unpin>(reader: &mut R, len: u64) -> Result<()> {
+    let padding = ((4 - len % 4) % 4) as usize;
+    let mut buf = [0u8; 3];
+    reader.read_exact(&mut buf[..padding]).await?;
+    Ok(())
+}
+
+/// Read the next `newc` cpio header, returns None at the end of the archive
+pub async fn read_cpio_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<CpioHeader>> {
+    let mut header = [0u8; CPIO_HEADER_SIZE];
+    reader.read_exact(&mut header).await?;
+    if !matches!(&header[..6], b"070701" | b"070702") {
+        return Err(Error::InvalidRpm("Unsupported cpio format"));
+    }
+
+    let mode = parse_hex_field(&header[14..22])? as u32;
+    let size = parse_hex_field(&header[54..62])?;
+    let name_size = parse_hex_field(&header[94..102])? as usize;
+    if name_size > MAX_CPIO_NAME_SIZE {
+        return Err(Error::InvalidRpm("Cpio filename is too long"));
+    }
+
+    let mut name = vec![0; name_size];
+    reader.read_exact(&mut name).await?;
+    skip_padding(reader, (CPIO_HEADER_SIZE + name_size) as u64).await?;
+
+    let name = std::str::from_utf8(&name)?.trim_end_matches('\0');
+    if name == CPIO_TRAILER {
+        return Ok(None);
+    }
+
+    Ok(Some(CpioHeader {
+        name: name.to_string(),
+        mode,
+        size,
+    }))
+}
+
+/// Read the cpio payload and return the refs of the files in it
+///
+/// Without a database the refs are only returned, nothing is imported.
 pub async fn read_routine<R: AsyncRead + Unpin>(
-    db: &db::Client,
-    reader: R,
+    db: Option<&db::Client>,
+    mut reader: R,
+    metadata: &RpmMetadata,
     vendor: String,
     package: String,
     version: String,
-) -> Result<()> {
-    let mut tar = Archive::new(reader);
-    let mut entries = tar.entries()?;
+    repo: Option<String>,
+) -> Result<Vec<db::Ref>> {
+    let mut spec = None;
+    let mut files = Vec::new();
 
-    while let Some(entry) = entries.next().await {
-        let entry = entry?;
-        let filename = {
-            let path = entry.path()?;
-            debug!("Found entry in .rpm: {:?}", path);
+    while let Some(header) = read_cpio_header(&mut reader).await? {
+        debug!("Found entry in .rpm: {:?}", header.name);
+        let mut entry = (&mut reader).take(header.size);
 
-            if entry.header().entry_type() != EntryType::Regular {
-                continue;
-            }
+        let filename = if header.is_regular_file() {
+            Path::new(&header.name)
+                .file_name()
+                .and_then(|f| f.to_str())
+                .map(String::from)
+        } else {
+            None
+        };
 
-            let Some(filename) = path.file_name() else {
-                continue;
-            };
-            let Some(filename) = filename.to_str() else {
-                continue;
+        if let Some(filename) = filename {
+            // TODO: find a better solution for this, can we just autodetect all regardless of file name?
+            let archive_w_compression = if filename.ends_with(".tar.gz")
+                || filename.ends_with(".tgz")
+                || filename.ends_with(".crate")
+            {
+                Some(Some("gz"))
+            } else if filename.ends_with(".tar.xz") {
+                Some(Some("xz"))
+            } else if filename.ends_with(".tar.bz2") {
+                Some(Some("bz2"))
+            } else if filename.ends_with(".tar") {
+                Some(None)
+            } else {
+                None
             };
 
-            filename.to_string()
-        };
+            let chksum = match archive_w_compression {
+                Some(compression) => {
+                    // in case of chromium, calculate the checksum but do not import
+                    let tar_db = if filename.starts_with("chromium-") {
+                        None
+                    } else {
+                        db
+                    };
+                    let summary = ingest::tar::stream_data(tar_db, &mut entry, compression).await?;
+                    summary.outer_digests.sha256.clone()
+                }
+                None if filename.ends_with(".spec") => {
+                    let mut reader = Hasher::new(&mut entry);
+                    if header.size > rpm_spec::MAX_SPEC_SIZE {
+                        warn!("Spec file {filename:?} is too large, not parsing");
+                        io::copy(&mut reader, &mut io::sink()).await?;
+                    } else {
+                        let mut buf = Vec::new();
+                        reader.read_to_end(&mut buf).await?;
+                        match rpm_spec::parse_with_macros(&buf, metadata.macros()) {
+                            Ok(parsed) => spec = Some(parsed),
+                            Err(err) => warn!("Failed to parse spec file {filename:?}: {err:#}"),
+                        }
+                    }
+                    let (_, digests): (_, Checksums) = reader.digests();
+                    digests.sha256
+                }
+                None => {
+                    let mut reader = Hasher::new(&mut entry);
+                    io::copy(&mut reader, &mut io::sink()).await?;
+                    let (_, digests): (_, Checksums) = reader.digests();
+                    digests.sha256
+                }
+            };
+            files.push((filename, chksum));
+        }
 
-        // TODO: find a better solution for this, can we just autodetect all regardless of file name?
-        let archive_w_compression = if filename.ends_with(".tar.gz")
-            || filename.ends_with(".tgz")
-            || filename.ends_with(".crate")
-        {
-            Some(Some("gz"))
-        } else if filename.ends_with(".tar.xz") {
-            Some(Some("xz"))
-        } else if filename.ends_with(".tar.bz2") {
-            Some(Some("bz2"))
-        } else if filename.ends_with(".tar") {
-            Some(None)
-        } else {
-            None;
-        };
+        // make sure the entry is fully consumed, even if the tar parser stopped early
+        io::copy(&mut entry, &mut io::sink()).await?;
+        skip_padding(&mut reader, header.size).await?;
+    }
 
-        let chksum = match archive_w_compression {
-            Some(compression) => {
-                // in case of chromium, calculate the checksum but do not import
-                let tar_db = if filename.starts_with("chromium-") {
-                    None
-                } else {
-                    Some(db)
-                };
-                let summary = ingest::tar::stream_data(tar_db, entry, compression).await?;
-                summary.outer_digests.sha256.clone()
-            }
-            None => {
-                let hasher = Hasher::new(entry).await?;
-                hasher.digests.sha256.clone()
-            }
-        };
+    // the spec file may come after the files it references, so insert refs last
+    let mut refs = Vec::new();
+    for (filename, chksum) in files {
+        let source = spec.as_ref().and_then(|spec| spec.find_source(&filename));
+        let url = source.and_then(rpm_spec::SpecSource::url);
 
         let r = db::Ref {
-            chksum: chksum,
+            chksum,
             vendor: vendor.to_string(),
             package: package.to_string(),
             version: version.to_string(),
-            filename: Some(filename.to_string()),
+            filename: Some(url.unwrap_or(&filename).to_string()),
+            role: source.map(|source| source.kind.as_str().to_string()),
+            repo: repo.clone(),
+            arch: None,
         };
-        info!("insert ref: {r:?}");
-        db.insert_ref(&r).await?;
+        if let Some(db) = db {
+            info!("insert ref: {r:?}");
+            db.insert_ref(&r).await?;
+        }
+        refs.push(r);
+    }
+    Ok(refs)
+}
+
+/// Wrap the rpm payload into the decompressor announced in the header
+pub fn decompress_payload<R: AsyncBufRead>(
+    reader: R,
+    compression: CompressionType,
+) -> Decompressor<R> {
+    match compression {
+        CompressionType::None => Decompressor::Plain(reader),
+        CompressionType::Gzip => Decompressor::gz(reader),
+        CompressionType::Xz => Decompressor::xz(reader),
+        CompressionType::Bzip2 => Decompressor::bz2(reader),
+        CompressionType::Zstd => Decompressor::zstd(reader),
     }
-    Ok(())
 }
 
 pub async fn stream_data<R: AsyncRead + Unpin>(
-    db: Arc<db::Client>,
-    mut reader: R,
+    db: &db::Client,
+    reader: R,
     vendor: String,
     package: String,
     version: String,
+    repo: Option<String>,
 ) -> Result<()> {
-    let mut child = Command::new("bsdtar")
-        .args(["-c", "@-"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
-
-    let mut stdin = child.stdin.take().unwrap();
-    let writer = async {
-        let n = io::copy(&mut reader, &mut stdin).await;
-        drop(stdin);
-        n
-    };
-
-    let stdout = child.stdout.take().unwrap();
-    let reader =
-        tokio::spawn(async move { read_routine(&db, stdout, vendor, package, version).await });
-
-    let (reader, writer) = tokio::join!(reader, writer);
-    debug!("Sent {} bytes to child process", writer?);
-    let status = child.wait().await?;
-    if !status.success() {
-        return Err(Error::ChildExit(status));
-    }
+    let mut reader = io::BufReader::new(reader);
+    let metadata = read_header(&mut reader).await?;
+    info!("Found rpm header: {metadata:?}");
+
+    let payload = decompress_payload(reader, metadata.compression);
+    read_routine(Some(db), payload, &metadata, vendor, package, version, repo).await?;
     debug!("Finished processing .rpm");
-    reader?
+    Ok(())
 }
 
 pub async fn run(args: &args::IngestRpm) -> Result<()> {
     let db = db::Client::create().await?;
-    let db = Arc::new(db);
 
     let reader = utils::fetch_or_open(&args.file, args.fetch).await?;
     stream_data(
-        db.clone(),
+        &db,
         reader,
         args.vendor.to_string(),
         args.package.to_string(),
         args.version.to_string(),
+        args.repo.clone(),
     )
     .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chksums;
+
+    #[tokio::test]
+    async fn test_read_rpm_payload() {
+        let dir = std::env::temp_dir().join(format!("whatsrc-rpm-{:016x}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spec_path = dir.join("hello.spec");
+        let spec = "Name: hello\nSource0: %{url}/%{name}-%{version}.txt\n";
+        std::fs::write(&spec_path, spec).unwrap();
+        let source_path = dir.join("hello-1.0.txt");
+        std::fs::write(&source_path, "hello world\n").unwrap();
+
+        let pkg = rpm::PackageBuilder::new("hello", "1.0", "MIT", "noarch", "says hello")
+            .release("3")
+            .url("https://example.com")
+            .compression(CompressionType::Gzip)
+            .with_file(&spec_path, rpm::FileOptions::new("/hello.spec"))
+            .unwrap()
+            .with_file(&source_path, rpm::FileOptions::new("/hello-1.0.txt"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut buf = Vec::new();
+        pkg.write(&mut buf).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut reader = io::BufReader::new(&buf[..]);
+        let metadata = read_header(&mut reader).await.unwrap();
+        assert_eq!(
+            metadata,
+            RpmMetadata {
+                name: "hello".to_string(),
+                version: "1.0".to_string(),
+                release: "3".to_string(),
+                url: Some("https://example.com".to_string()),
+                compression: CompressionType::Gzip,
+            }
+        );
+
+        let payload = decompress_payload(reader, metadata.compression);
+        let refs = read_routine(
+            None,
+            payload,
+            &metadata,
+            "fedora".to_string(),
+            "hello".to_string(),
+            "1.0-3".to_string(),
+            Some("updates".to_string()),
+        )
+        .await
+        .unwrap();
+        // the spec file comes after the source it references
+        let refs = refs
+            .into_iter()
+            .map(|r| (r.chksum, r.filename.unwrap(), r.role, r.repo))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            refs,
+            [
+                (
+                    chksums::sha256(b"hello world\n"),
+                    "https://example.com/hello-1.0.txt".to_string(),
+                    Some("source".to_string()),
+                    Some("updates".to_string()),
+                ),
+                (
+                    chksums::sha256(spec.as_bytes()),
+                    "hello.spec".to_string(),
+                    None,
+                    Some("updates".to_string()),
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,540 @@
+use crate::args;
+use crate::chksums::{Checksums, Hasher};
+use crate::db::{DetachedSignature, Task, TaskData};
+use crate::errors::*;
+use crate::ingest::git::{self, SignatureStatus};
+use crate::utils;
+use std::future::Future;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::process;
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+
+/// File extensions of detached signatures that are listed next to the signed file
+pub const SIGNATURE_EXTENSIONS: &[&str] = &[".sig", ".asc"];
+
+/// Find the detached signature of `url` in a list of source urls
+pub fn find_signature_url<'a>(url: &str, sources: &'a [String]) -> Option<&'a str> {
+    SIGNATURE_EXTENSIONS.iter().find_map(|ext| {
+        let signature = format!("{url}{ext}");
+        sources
+            .iter()
+            .map(String::as_str)
+            .find(|source| *source == signature)
+    })
+}
+
+pub fn verify_task(
+    vendor: &str,
+    url: &str,
+    signature_url: &str,
+    chksum: &str,
+    keys: &[String],
+) -> Result<Task> {
+    Task::new(
+        format!("verify-sig:{vendor}:{signature_url}"),
+        &TaskData::VerifySignature {
+            vendor: vendor.to_string(),
+            url: url.to_string(),
+            signature_url: signature_url.to_string(),
+            chksum: chksum.to_string(),
+            keys: keys.to_vec(),
+        },
+    )
+}
+
+/// Download the signed file and verify the signature while it's ingested
+pub fn fetch_task(
+    vendor: &str,
+    url: &str,
+    signature_url: &str,
+    chksum: &str,
+    keys: &[String],
+) -> Result<Task> {
+    Task::new(
+        format!("fetch:{url}"),
+        &TaskData::FetchTar {
+            url: url.to_string(),
+            mirrors: Vec::new(),
+            aliases: Vec::new(),
+            compression: None,
+            success_ref: None,
+            sha256: None,
+            signature: Some(DetachedSignature {
+                vendor: vendor.to_string(),
+                signature_url: signature_url.to_string(),
+                chksum: chksum.to_string(),
+                keys: keys.to_vec(),
+            }),
+        },
+    )
+}
+
+fn gpg(keyring: &args::ReleaseKeyring) -> process::Command {
+    let mut cmd = process::Command::new("gpg");
+    cmd.args(["--batch", "--no-tty", "--status-fd", "1"]);
+    if let Some(home) = &keyring.release_gnupghome {
+        cmd.env("GNUPGHOME", home);
+    }
+    cmd
+}
+
+/// Import the ascii-armored public keys a vendor considers trustworthy
+pub async fn import_keys(keyring: &args::ReleaseKeyring, keys: &[String]) -> Result<()> {
+    for key in keys {
+        let mut child = gpg(keyring)
+            .args(["--import", "--import-options", "import-minimal"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(key.as_bytes()).await?;
+        drop(stdin);
+
+        let status = child.wait().await?;
+        if !status.success() {
+            return Err(Error::GpgError(status));
+        }
+    }
+    Ok(())
+}
+
+/// Export the `keys` of a vendor from the shared keyring into a keyring of their own
+async fn export_keys(
+    keyring: &args::ReleaseKeyring,
+    tmp: &str,
+    keys: &[String],
+) -> Result<PathBuf> {
+    fs::create_dir_all(tmp).await?;
+    let path = Path::new(tmp).join(format!("keyring-{:016x}.gpg", fastrand::u64(..)));
+    // stdout is used for status messages, so the keys need to go into a file
+    let status = gpg(keyring)
+        .arg("--output")
+        .arg(&path)
+        .arg("--export")
+        .arg("--")
+        .args(keys)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(Error::GpgError(status));
+    }
+    Ok(path)
+}
+
+/// Download a file and verify it against its detached signature
+///
+/// Returns the checksums of the downloaded file, so the caller can make sure it's the
+/// expected artifact.
+pub async fn verify_detached(
+    http: &utils::HttpClient,
+    keyring: &args::ReleaseKeyring,
+    tmp: &str,
+    url: &str,
+    signature_url: &str,
+    keys: &[String],
+) -> Result<(Checksums, SignatureStatus, Option<String>)> {
+    info!("Downloading signed file: {url:?}");
+    let reader = http.fetch(url).await?;
+    let (digests, verified) = verify_detached_stream(
+        http,
+        keyring,
+        tmp,
+        signature_url,
+        keys,
+        reader,
+        |reader| async move {
+            let mut reader = Hasher::new(reader);
+            io::copy(&mut reader, &mut io::sink()).await?;
+            let (_, digests) = reader.digests();
+            Ok(digests)
+        },
+    )
+    .await?;
+    let (status, fingerprint) = verified?;
+    Ok((digests, status, fingerprint))
+}
+
+/// The outcome of a signature check, or why the signature couldn't be checked
+pub type Verified = Result<(SignatureStatus, Option<String>)>;
+
+/// Verify a detached signature while the signed file is handed to `consume`
+///
+/// This way the signed file only needs to be downloaded once, even if it's also ingested.
+/// Problems with the signature itself don't prevent `consume` from running.
+///
+/// Only the vendor's `keys` are used to verify the signature. If the vendor doesn't list any,
+/// the whole keyring is used and [`check_allowed_key`] reports the signature as unattested.
+pub async fn verify_detached_stream<R, F, Fut, T>(
+    http: &utils::HttpClient,
+    keyring: &args::ReleaseKeyring,
+    tmp: &str,
+    signature_url: &str,
+    keys: &[String],
+    reader: R,
+    consume: F,
+) -> Result<(T, Verified)>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+    F: FnOnce(Box<dyn AsyncRead + Send + Unpin>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let path = match fetch_signature(http, tmp, signature_url).await {
+        Ok(path) => path,
+        Err(err) => {
+            let consumed = consume(Box::new(reader)).await?;
+            return Ok((consumed, Err(err)));
+        }
+    };
+
+    let vendor_keyring = if keys.is_empty() {
+        None
+    } else {
+        match export_keys(keyring, tmp, keys).await {
+            Ok(path) => Some(path),
+            Err(err) => {
+                remove_temp_file(&path).await;
+                let consumed = consume(Box::new(reader)).await?;
+                return Ok((consumed, Err(err)));
+            }
+        }
+    };
+
+    let result = verify_with_file(keyring, vendor_keyring.as_deref(), &path, reader, consume).await;
+    remove_temp_file(&path).await;
+    if let Some(path) = &vendor_keyring {
+        remove_temp_file(path).await;
+    }
+    result
+}
+
+async fn remove_temp_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path).await {
+        warn!("Failed to remove temporary file {path:?}: {err:#}");
+    }
+}
+
+async fn fetch_signature(
+    http: &utils::HttpClient,
+    tmp: &str,
+    signature_url: &str,
+) -> Result<PathBuf> {
+    info!("Downloading detached signature: {signature_url:?}");
+    let mut signature = Vec::new();
+    http.fetch(signature_url)
+        .await?
+        .read_to_end(&mut signature)
+        .await?;
+
+    fs::create_dir_all(tmp).await?;
+    let path = Path::new(tmp).join(format!("sig-{:016x}", fastrand::u64(..)));
+    fs::write(&path, &signature).await?;
+    Ok(path)
+}
+
+async fn verify_with_file<R, F, Fut, T>(
+    keyring: &args::ReleaseKeyring,
+    vendor_keyring: Option<&Path>,
+    signature: &Path,
+    reader: R,
+    consume: F,
+) -> Result<(T, Verified)>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+    F: FnOnce(Box<dyn AsyncRead + Send + Unpin>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut cmd = gpg(keyring);
+    if let Some(path) = vendor_keyring {
+        cmd.arg("--no-default-keyring").arg("--keyring").arg(path);
+    }
+    let spawned = cmd
+        .arg("--verify")
+        .arg(signature)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) => {
+            let consumed = consume(Box::new(reader)).await?;
+            return Ok((consumed, Err(err.into())));
+        }
+    };
+    let stdin = child.stdin.take().unwrap();
+
+    let (tx, rx) = mpsc::channel(16);
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    });
+    let consumer = Box::new(StreamReader::new(Box::pin(stream)));
+
+    let (copied, consumed) = tokio::join!(tee(reader, stdin, tx), consume(consumer));
+    copied?;
+    let consumed = consumed?;
+
+    let verified = child
+        .wait_with_output()
+        .await
+        .map(|output| git::parse_gpg_status(&String::from_utf8_lossy(&output.stdout)))
+        .map_err(Error::from);
+    Ok((consumed, verified))
+}
+
+/// Feed the signed file to gpg and the consumer at the same time
+async fn tee<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut reader: R,
+    stdin: W,
+    tx: mpsc::Sender<io::Result<Cursor<Vec<u8>>>>,
+) -> Result<()> {
+    let mut stdin = Some(stdin);
+    let mut tx = Some(tx);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) => {
+                if let Some(tx) = &tx {
+                    let _ = tx
+                        .send(Err(io::Error::new(err.kind(), err.to_string())))
+                        .await;
+                }
+                return Err(err.into());
+            }
+        };
+        let chunk = &buf[..n];
+
+        if let Some(writer) = &mut stdin {
+            if let Err(err) = writer.write_all(chunk).await {
+                warn!("Failed to pass signed file to gpg: {err:#}");
+                stdin = None;
+            }
+        }
+        // the consumer may stop early, keep feeding gpg anyway
+        if let Some(sender) = &tx {
+            if sender.send(Ok(Cursor::new(chunk.to_vec()))).await.is_err() {
+                tx = None;
+            }
+        }
+        if stdin.is_none() && tx.is_none() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Only accept signatures from keys the vendor explicitly lists
+///
+/// All vendors share one keyring, so a good signature is only attributed to a vendor
+/// if the vendor vouches for the key.
+pub fn check_allowed_key(
+    status: SignatureStatus,
+    fingerprint: Option<&str>,
+    keys: &[String],
+) -> SignatureStatus {
+    if status != SignatureStatus::Valid {
+        return status;
+    }
+    if keys.is_empty() {
+        return SignatureStatus::Unattested;
+    }
+    let allowed = fingerprint
+        .is_some_and(|fingerprint| keys.iter().any(|key| key.eq_ignore_ascii_case(fingerprint)));
+    if allowed {
+        status
+    } else {
+        SignatureStatus::UntrustedKey
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_signature_url() {
+        let sources = [
+            "https://www.kernel.org/pub/software/scm/git/git-2.45.2.tar.xz".to_string(),
+            "https://www.kernel.org/pub/software/scm/git/git-2.45.2.tar.xz.sig".to_string(),
+            "https://ftp.gnu.org/gnu/bash/bash-5.2.tar.gz".to_string(),
+            "https://ftp.gnu.org/gnu/bash/bash-5.2.tar.gz.asc".to_string(),
+            "https://example.com/foo-1.0.tar.gz".to_string(),
+        ];
+        assert_eq!(
+            find_signature_url(&sources[0], &sources),
+            Some("https://www.kernel.org/pub/software/scm/git/git-2.45.2.tar.xz.sig")
+        );
+        assert_eq!(
+            find_signature_url(&sources[2], &sources),
+            Some("https://ftp.gnu.org/gnu/bash/bash-5.2.tar.gz.asc")
+        );
+        assert_eq!(find_signature_url(&sources[4], &sources), None);
+    }
+
+    async fn gen_key(keyring: &args::ReleaseKeyring, uid: &str) -> String {
+        let status = gpg(keyring)
+            .args([
+                "--passphrase",
+                "",
+                "--quick-gen-key",
+                uid,
+                "ed25519",
+                "sign",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        let output = gpg(keyring)
+            .args(["--with-colons", "--list-keys", uid])
+            .output()
+            .await
+            .unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix("fpr:"))
+            .map(|line| line.trim_matches(':').to_string())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_only_with_vendor_keys() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-sig-test-{:016x}", fastrand::u64(..)));
+        let home = tmp.join("gnupg");
+        fs::create_dir_all(&home).await.unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&home, std::fs::Permissions::from_mode(0o700))
+                .await
+                .unwrap();
+        }
+        let keyring = args::ReleaseKeyring {
+            release_gnupghome: Some(home),
+        };
+        let tmp_str = tmp.to_str().unwrap();
+
+        let vendor_key = gen_key(&keyring, "vendor@example.com").await;
+        let other_key = gen_key(&keyring, "other@example.com").await;
+
+        let data = b"hello world\n";
+        fs::write(tmp.join("data"), data).await.unwrap();
+        let status = gpg(&keyring)
+            .args(["--local-user", &other_key, "--detach-sign"])
+            .arg(tmp.join("data"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        let signature = tmp.join("data.sig");
+
+        let mut results = Vec::new();
+        for keys in [vec![vendor_key.clone()], vec![other_key.clone()]] {
+            let vendor_keyring = export_keys(&keyring, tmp_str, &keys).await.unwrap();
+            let (_, verified) = verify_with_file(
+                &keyring,
+                Some(&vendor_keyring),
+                &signature,
+                &data[..],
+                |mut reader| async move {
+                    io::copy(&mut reader, &mut io::sink()).await?;
+                    Ok(())
+                },
+            )
+            .await
+            .unwrap();
+            results.push(verified.unwrap().0);
+        }
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        assert_eq!(
+            results,
+            [SignatureStatus::UnknownKey, SignatureStatus::Valid]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tee() {
+        let data = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        let (tx, rx) = mpsc::channel(16);
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        });
+        let mut consumer = StreamReader::new(Box::pin(stream));
+        let mut gpg = Vec::new();
+        let mut consumed = Vec::new();
+        let (copied, read) = tokio::join!(
+            tee(&data[..], &mut gpg, tx),
+            consumer.read_to_end(&mut consumed)
+        );
+        copied.unwrap();
+        read.unwrap();
+        assert_eq!(gpg, data);
+        assert_eq!(consumed, data);
+    }
+
+    #[tokio::test]
+    async fn test_tee_consumer_stops_early() {
+        let data = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        let mut gpg = Vec::new();
+        tee(&data[..], &mut gpg, tx).await.unwrap();
+        assert_eq!(gpg, data);
+    }
+
+    #[test]
+    fn test_check_allowed_key() {
+        let keys = ["96E07AF25771955980DAD10020D04E5A713660A7".to_string()];
+        assert_eq!(
+            check_allowed_key(
+                SignatureStatus::Valid,
+                Some("96e07af25771955980dad10020d04e5a713660a7"),
+                &keys
+            ),
+            SignatureStatus::Valid
+        );
+        assert_eq!(
+            check_allowed_key(
+                SignatureStatus::Valid,
+                Some("3529374054BFDA33A3F6595D8C1D0E86D3923F20"),
+                &keys
+            ),
+            SignatureStatus::UntrustedKey
+        );
+        assert_eq!(
+            check_allowed_key(
+                SignatureStatus::Valid,
+                Some("3529374054BFDA33A3F6595D8C1D0E86D3923F20"),
+                &[]
+            ),
+            SignatureStatus::Unattested
+        );
+        assert_eq!(
+            check_allowed_key(SignatureStatus::UnknownKey, None, &keys),
+            SignatureStatus::UnknownKey
+        );
+        assert_eq!(
+            check_allowed_key(SignatureStatus::Invalid, None, &[]),
+            SignatureStatus::Invalid
+        );
+    }
+}
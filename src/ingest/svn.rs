@@ -0,0 +1,222 @@
+use crate::args;
+use crate::db;
+use crate::errors::*;
+use crate::ingest;
+use crate::ingest::git::CLONE_TIMEOUT;
+use crate::utils;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::fs;
+use tokio::process;
+use tokio::time;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SvnUrl {
+    url: String,
+    revision: Option<u64>,
+}
+
+impl SvnUrl {
+    /// The identifier we use for an exported revision, e.g. `svn:https://svn.example.com/trunk@1234`
+    pub fn alias(&self) -> Option<String> {
+        let revision = self.revision?;
+        Some(format!("svn:{}@{revision}", self.url))
+    }
+}
+
+impl FromStr for SvnUrl {
+    type Err = Error;
+
+    fn from_str(full_url: &str) -> Result<SvnUrl> {
+        let url = full_url.strip_prefix("svn+").unwrap_or(full_url);
+
+        let (url, info) = url
+            .rsplit_once('#')
+            .map(|(url, info)| (url, Some(info)))
+            .unwrap_or((url, None));
+
+        let mut svn = SvnUrl {
+            url: url.to_string(),
+            ..Default::default()
+        };
+
+        if let Some(info) = info {
+            match info.split_once('=') {
+                Some(("revision", value)) => {
+                    let Ok(revision) = value.parse() else {
+                        return Err(Error::UnknownVcsRef(info.to_string()));
+                    };
+                    svn.revision = Some(revision);
+                }
+                _ => return Err(Error::UnknownVcsRef(info.to_string())),
+            };
+        }
+
+        Ok(svn)
+    }
+}
+
+/// Export the revision into `path` and ingest it
+pub async fn snapshot(
+    db: Option<&db::Client>,
+    svn_url: &SvnUrl,
+    path: &Path,
+) -> Result<ingest::tar::TarSummary> {
+    let Some(revision) = svn_url.revision else {
+        return Err(Error::MissingVcsRevision(svn_url.url.clone()));
+    };
+    // svn would interpret this as an option
+    if svn_url.url.starts_with('-') {
+        return Err(Error::UnsupportedUrl(svn_url.url.clone()));
+    }
+
+    let export = path.join("export");
+    info!(
+        "Exporting subversion revision from {:?}: {revision}",
+        svn_url.url
+    );
+    let child = process::Command::new("svn")
+        .args([
+            "export",
+            "--quiet",
+            "--non-interactive",
+            "--ignore-externals",
+            "--",
+            // peg revision, the path as it existed in this revision
+            &format!("{}@{revision}", svn_url.url),
+        ])
+        .arg(&export)
+        .status();
+    let Ok(status) = time::timeout(CLONE_TIMEOUT, child).await else {
+        return Err(Error::VcsTimeout);
+    };
+    let status = status?;
+    if !status.success() {
+        return Err(Error::SvnError(status));
+    }
+
+    ingest::tar::stream_dir(db, &export).await
+}
+
+pub async fn take_snapshot(
+    db: &db::Client,
+    svn_url: &SvnUrl,
+    tmp: &str,
+    rewrites: &[utils::RewriteRule],
+) -> Result<()> {
+    let Some(alias) = svn_url.alias() else {
+        return Err(Error::MissingVcsRevision(svn_url.url.clone()));
+    };
+
+    let path = Path::new(tmp).join(format!("svn-{:016x}", fastrand::u64(..)));
+    fs::create_dir_all(&path).await?;
+
+    let remote = SvnUrl {
+        url: utils::rewrite_url(rewrites, &svn_url.url),
+        ..svn_url.clone()
+    };
+    let result = snapshot(Some(db), &remote, &path).await;
+    if let Err(err) = fs::remove_dir_all(&path).await {
+        warn!("Failed to remove temporary directory {path:?}: {err:#}");
+    }
+    let summary = result?;
+
+    db.insert_alias_from_to(&alias, &summary.inner_digests.sha256, "svn-export")
+        .await?;
+
+    Ok(())
+}
+
+pub async fn run(args: &args::IngestSvn) -> Result<()> {
+    let db = db::Client::create().await?;
+
+    take_snapshot(&db, &args.svn, &args.tmp, &args.rewrites.rules).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_svn_url() {
+        let url = "svn+svn://svn.code.sf.net/p/xmlstar/code/trunk#revision=120";
+        let svn = url.parse::<SvnUrl>().unwrap();
+        assert_eq!(
+            svn,
+            SvnUrl {
+                url: "svn://svn.code.sf.net/p/xmlstar/code/trunk".to_string(),
+                revision: Some(120),
+            }
+        );
+        assert_eq!(
+            svn.alias().as_deref(),
+            Some("svn:svn://svn.code.sf.net/p/xmlstar/code/trunk@120")
+        );
+    }
+
+    #[test]
+    fn parse_svn_url_invalid_revision() {
+        let url = "svn+https://svn.example.com/repo/trunk#revision=HEAD";
+        assert!(url.parse::<SvnUrl>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_option_url() {
+        let svn_url = "svn+--config-option=config:tunnels:ssh=touch /tmp/pwned#revision=1"
+            .parse::<SvnUrl>()
+            .unwrap();
+        let result = snapshot(None, &svn_url, Path::new("/nonexistent")).await;
+        assert!(matches!(result, Err(Error::UnsupportedUrl(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires subversion"]
+    async fn test_snapshot_local_repo() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-svn-test-{:016x}", fastrand::u64(..)));
+        let repo = tmp.join("repo");
+        let import = tmp.join("import");
+        fs::create_dir_all(import.join("src")).await.unwrap();
+        fs::write(import.join("src/hello.txt"), "hello world\n")
+            .await
+            .unwrap();
+
+        let status = process::Command::new("svnadmin")
+            .arg("create")
+            .arg(&repo)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        let repo_url = format!("file://{}", repo.to_str().unwrap());
+        let status = process::Command::new("svn")
+            .args(["import", "--quiet", "--message", "init"])
+            .arg(&import)
+            .arg(&repo_url)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let svn_url = format!("svn+{repo_url}#revision=1")
+            .parse::<SvnUrl>()
+            .unwrap();
+        let work = tmp.join("work");
+        fs::create_dir_all(&work).await.unwrap();
+        let result = snapshot(None, &svn_url, &work).await;
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        let summary = result.unwrap();
+        let paths = summary
+            .files
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["src", "src/hello.txt"]);
+        assert!(summary
+            .files
+            .iter()
+            .all(|entry| entry.metadata.mtime == Some(0)));
+    }
+}
@@ -8,9 +8,10 @@ use digest::Digest;
 use futures::stream::StreamExt;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
-use tokio::fs::File;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
 use tokio::io::{self, AsyncRead, AsyncReadExt};
-use tokio_tar::{Archive, EntryType};
+use tokio_tar::{Archive, Builder, EntryType, HeaderMode};
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
@@ -90,6 +91,39 @@ pub struct TarSummary {
     pub outer_digests: Checksums,
     pub files: Vec<Entry>,
     pub sbom_refs: Vec<sbom::Ref>,
+    /// The commit `git archive` recorded in the pax global header
+    pub git_commit: Option<String>,
+}
+
+/// Extract the commit id from the `comment` of a pax global header written by `git archive`
+pub fn git_commit_from_pax_comment(comment: &str) -> Option<String> {
+    let comment = comment.trim();
+    if matches!(comment.len(), 40 | 64) && comment.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Some(comment.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+/// Forges that generate their archive downloads with `git archive`, so the commit in the pax header can be trusted
+pub fn is_forge_archive_url(url: &str) -> bool {
+    let Ok(url) = url.parse::<reqwest::Url>() else {
+        return false;
+    };
+    if url.scheme() != "https" || url.port().is_some() {
+        return false;
+    }
+    let Some(segments) = url.path_segments() else {
+        return false;
+    };
+    let segments = segments.collect::<Vec<_>>();
+    matches!(
+        (url.host_str(), segments.as_slice()),
+        (Some("github.com"), [_, _, "archive", _, ..])
+            | (Some("codeload.github.com"), [_, _, "tar.gz" | "tar", _, ..])
+            | (Some("gitlab.com"), [_, .., "-", "archive", _, _])
+            | (Some("codeberg.org" | "git.sr.ht"), [_, _, "archive", _])
+    )
 }
 
 pub async fn stream_data<R: AsyncRead + Unpin>(
@@ -97,12 +131,29 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
     reader: R,
     compression: Option<&str>,
 ) -> Result<TarSummary> {
+    stream_data_checked(db, reader, compression, |_| Ok(())).await
+}
+
+/// Like [`stream_data`], but `check` gets to reject the checksums of the compressed file before
+/// anything is written to the database
+pub async fn stream_data_checked<R, F>(
+    db: Option<&db::Client>,
+    reader: R,
+    compression: Option<&str>,
+    check: F,
+) -> Result<TarSummary>
+where
+    R: AsyncRead + Unpin,
+    F: FnOnce(&Checksums) -> Result<()>,
+{
     // Setup decompressor
     let reader = io::BufReader::new(Hasher::new(reader));
     let (reader, outer_label) = match compression {
         Some("gz") => (Decompressor::gz(reader), "gz(tar)"),
         Some("xz") => (Decompressor::xz(reader), "xz(tar)"),
         Some("bz2") => (Decompressor::bz2(reader), "bz2(tar)"),
+        Some("zst") => (Decompressor::zstd(reader), "zst(tar)"),
+        Some("lzma") => (Decompressor::lzma(reader), "lzma(tar)"),
         None => (Decompressor::Plain(reader), "tar"),
         unknown => panic!("Unknown compression algorithm: {unknown:?}"),
     };
@@ -111,11 +162,25 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
     // Open archive
     let mut tar = Archive::new(reader);
     let mut files = Vec::new();
-    let mut sbom_refs = Vec::new();
+    let mut sboms = Vec::new();
+    let mut git_commit = None;
     {
         let mut entries = tar.entries()?;
         while let Some(entry) = entries.next().await {
             let mut entry = entry?;
+            if entry.header().entry_type() == EntryType::XGlobalHeader {
+                if let Some(extensions) = entry.pax_extensions().await? {
+                    for extension in extensions {
+                        let extension = extension?;
+                        if extension.key() == Ok("comment") {
+                            git_commit =
+                                extension.value().ok().and_then(git_commit_from_pax_comment);
+                        }
+                    }
+                }
+                continue;
+            }
+
             let Some((metadata, is_file)) = Metadata::from_tar_header(&entry)? else {
                 continue;
             };
@@ -144,26 +209,11 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
 
                 let digest = format!("sha256:{}", hex::encode(sha256.finalize()));
 
+                // sboms are only inserted once the whole file passed the check
                 if let Some(sbom) = sbom {
                     if let Ok(data) = String::from_utf8(data) {
-                        if let Some(db) = db {
-                            let sbom = sbom::Sbom::new(sbom, data)?;
-                            let chksum = db.insert_sbom(&sbom).await?;
-                            let strain = sbom.strain();
-                            info!("Inserted sbom {strain:?}: {digest:?}");
-                            sbom_refs.push(sbom::Ref {
-                                strain,
-                                chksum: chksum.clone(),
-                                path: path.clone(),
-                            });
-                            db.insert_task(&db::Task::new(
-                                format!("sbom:{strain}:{chksum}"),
-                                &db::TaskData::IndexSbom {
-                                    strain: Some(strain.to_string()),
-                                    chksum,
-                                },
-                            )?)
-                            .await?;
+                        if db.is_some() {
+                            sboms.push((sbom::Sbom::new(sbom, data)?, path.clone()));
                         }
                     }
                 }
@@ -197,8 +247,29 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
 
     let (_stream, outer_digests) = reader.digests();
     info!("Found digests for outer compressed tar: {outer_digests:?}");
+    check(&outer_digests)?;
 
+    let mut sbom_refs = Vec::new();
     if let Some(db) = db {
+        for (sbom, path) in sboms {
+            let chksum = db.insert_sbom(&sbom).await?;
+            let strain = sbom.strain();
+            info!("Inserted sbom {strain:?}: {chksum:?}");
+            sbom_refs.push(sbom::Ref {
+                strain,
+                chksum: chksum.clone(),
+                path,
+            });
+            db.insert_task(&db::Task::new(
+                format!("sbom:{strain}:{chksum}"),
+                &db::TaskData::IndexSbom {
+                    strain: Some(strain.to_string()),
+                    chksum,
+                },
+            )?)
+            .await?;
+        }
+
         // Insert into database
         db.insert_artifact(&inner_digests.sha256, &files).await?;
         db.register_chksums_aliases(&inner_digests, &inner_digests.sha256, "tar")
@@ -217,9 +288,52 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
         outer_digests,
         files,
         sbom_refs,
+        git_commit,
     })
 }
 
+/// List all entries of a directory recursively, sorted by path
+async fn sorted_entries(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    let mut queue = vec![PathBuf::new()];
+    while let Some(relative) = queue.pop() {
+        let mut entries = fs::read_dir(root.join(&relative)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = relative.join(entry.file_name());
+            if entry.file_type().await?.is_dir() {
+                queue.push(path.clone());
+            }
+            out.push(path);
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Pack a directory into a tar with normalized metadata and ingest it
+///
+/// This is used for version control systems that don't have a stable archive format.
+pub async fn stream_dir(db: Option<&db::Client>, dir: &Path) -> Result<TarSummary> {
+    let entries = sorted_entries(dir).await?;
+    let (writer, reader) = io::duplex(64 * 1024);
+
+    let build = async move {
+        let mut builder = Builder::new(writer);
+        builder.mode(HeaderMode::Deterministic);
+        builder.follow_symlinks(false);
+        for path in entries {
+            builder
+                .append_path_with_name(dir.join(&path), &path)
+                .await?;
+        }
+        builder.into_inner().await?;
+        Ok::<_, Error>(())
+    };
+
+    let (_, summary) = tokio::try_join!(build, stream_data(db, reader, None))?;
+    Ok(summary)
+}
+
 pub async fn run(args: &args::IngestTar) -> Result<()> {
     let db = db::Client::create().await?;
 
@@ -384,6 +498,152 @@ mod tests {
                 },
             ],
             sbom_refs: vec![],
+            git_commit: None,
         });
     }
+
+    #[test]
+    fn test_git_commit_from_pax_comment() {
+        assert_eq!(
+            git_commit_from_pax_comment("4ef813a52dff31a41c5fd50cc24a777688c4e4c0"),
+            Some("4ef813a52dff31a41c5fd50cc24a777688c4e4c0".to_string())
+        );
+        assert_eq!(git_commit_from_pax_comment("v1.0"), None);
+        assert_eq!(git_commit_from_pax_comment(""), None);
+    }
+
+    #[test]
+    fn test_is_forge_archive_url() {
+        for url in [
+            "https://github.com/kpcyrd/sh4d0wup/archive/refs/tags/v0.9.3.tar.gz",
+            "https://github.com/kpcyrd/sh4d0wup/archive/v0.9.3.tar.gz",
+            "https://codeload.github.com/kpcyrd/sh4d0wup/tar.gz/refs/tags/v0.9.3",
+            "https://gitlab.com/gitlab-org/gitlab-foss/-/archive/v17.0.0/gitlab-foss-v17.0.0.tar.gz",
+            "https://gitlab.com/group/subgroup/project/-/archive/v1.0/project-v1.0.tar.gz",
+            "https://codeberg.org/forgejo/forgejo/archive/v7.0.0.tar.gz",
+            "https://git.sr.ht/~sircmpwn/scdoc/archive/1.11.3.tar.gz",
+        ] {
+            assert!(is_forge_archive_url(url), "{url:?}");
+        }
+        for url in [
+            "https://github.com/kpcyrd/sh4d0wup/releases/download/v0.9.3/sh4d0wup-0.9.3.tar.gz",
+            "http://github.com/kpcyrd/sh4d0wup/archive/v0.9.3.tar.gz",
+            "https://github.com:8443/kpcyrd/sh4d0wup/archive/v0.9.3.tar.gz",
+            "https://github.com@example.com/kpcyrd/sh4d0wup/archive/v0.9.3.tar.gz",
+            "https://example.com/kpcyrd/sh4d0wup/archive/v0.9.3.tar.gz",
+            "https://gitlab.com/gitlab-org/gitlab-foss/-/package_files/1/download",
+            "https://ftp.gnu.org/gnu/hello/hello-2.12.1.tar.gz",
+            "not a url",
+        ] {
+            assert!(!is_forge_archive_url(url), "{url:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_data_checked_rejects() {
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+        let data = b"hello world\n";
+        let mut header = tokio_tar::Header::new_ustar();
+        header.set_path("foo-1.0/README").unwrap();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append(&header, &data[..]).await.unwrap();
+        let tar = builder.into_inner().await.unwrap();
+
+        let mut seen = None;
+        let err = stream_data_checked(None, &tar[..], None, |digests| {
+            seen = Some(digests.sha256.clone());
+            Err(Error::ChecksumMismatch("sha256:0000".to_string()))
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch(_)));
+        let summary = stream_data(None, &tar[..], None).await.unwrap();
+        assert_eq!(seen, Some(summary.outer_digests.sha256));
+    }
+
+    #[tokio::test]
+    async fn test_ingest_tar_pax_comment() {
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+
+        let pax = b"52 comment=4ef813a52dff31a41c5fd50cc24a777688c4e4c0\n";
+        let mut header = tokio_tar::Header::new_ustar();
+        header.set_entry_type(EntryType::XGlobalHeader);
+        header.set_path("pax_global_header").unwrap();
+        header.set_size(pax.len() as u64);
+        header.set_cksum();
+        builder.append(&header, &pax[..]).await.unwrap();
+
+        let data = b"hello world\n";
+        let mut header = tokio_tar::Header::new_ustar();
+        header.set_path("foo-1.0/README").unwrap();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append(&header, &data[..]).await.unwrap();
+
+        let tar = builder.into_inner().await.unwrap();
+        let summary = stream_data(None, &tar[..], None).await.unwrap();
+        assert_eq!(
+            summary.git_commit.as_deref(),
+            Some("4ef813a52dff31a41c5fd50cc24a777688c4e4c0")
+        );
+        let paths = summary
+            .files
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["foo-1.0/README"]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_dir_normalized() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-tar-test-{:016x}", fastrand::u64(..)));
+        fs::create_dir_all(tmp.join("foo-1.0/src")).await.unwrap();
+        fs::write(tmp.join("foo-1.0/src/main.c"), "int main() {}\n")
+            .await
+            .unwrap();
+        fs::write(tmp.join("foo-1.0/README"), "hello world\n")
+            .await
+            .unwrap();
+        fs::symlink("README", tmp.join("foo-1.0/README.md"))
+            .await
+            .unwrap();
+
+        let first = stream_dir(None, &tmp.join("foo-1.0")).await;
+        // entries are sorted and timestamps are dropped, so touching files doesn't matter
+        fs::write(tmp.join("foo-1.0/README"), "hello world\n")
+            .await
+            .unwrap();
+        let second = stream_dir(None, &tmp.join("foo-1.0")).await;
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        let first = first.unwrap();
+        assert_eq!(first, second.unwrap());
+        let files = first
+            .files
+            .iter()
+            .map(|entry| {
+                (
+                    entry.path.as_str(),
+                    entry.metadata.mode.as_deref(),
+                    entry.metadata.mtime,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                ("README", Some("0o644"), Some(0)),
+                ("README.md", Some("0o755"), Some(0)),
+                ("src", Some("0o755"), Some(0)),
+                ("src/main.c", Some("0o644"), Some(0)),
+            ]
+        );
+        assert_eq!(
+            first.files[1].metadata.links_to,
+            Some(LinksTo::Symbolic("README".to_string()))
+        );
+    }
 }
@@ -5,17 +5,20 @@ use crate::utils;
 use crate::void_template;
 use async_compression::tokio::bufread::GzipDecoder;
 use futures::StreamExt;
+use std::collections::{BTreeSet, HashMap};
 use tokio::io::{self, AsyncRead, AsyncReadExt};
 use tokio_tar::Archive;
 
-pub async fn extract_template<R: AsyncRead + Unpin>(
+/// Extract the templates of multiple srcpkgs with a single pass over the archive
+pub async fn extract_templates<R: AsyncRead + Unpin>(
     reader: R,
-    srcpkg: &str,
-) -> Result<Option<String>> {
+    srcpkgs: &BTreeSet<&str>,
+) -> Result<HashMap<String, String>> {
     let reader = io::BufReader::new(reader);
     let reader = GzipDecoder::new(reader);
     let mut tar = Archive::new(reader);
 
+    let mut templates = HashMap::new();
     let mut entries = tar.entries()?;
     while let Some(entry) = entries.next().await {
         let mut entry = entry?;
@@ -24,20 +27,33 @@ pub async fn extract_template<R: AsyncRead + Unpin>(
         let Some((_, path)) = path.split_once("/srcpkgs/") else {
             continue;
         };
-        let Some(path) = path.strip_suffix("/template") else {
+        let Some(srcpkg) = path.strip_suffix("/template") else {
             continue;
         };
 
-        if path != srcpkg {
+        if !srcpkgs.contains(srcpkg) {
             continue;
         }
+        let srcpkg = srcpkg.to_string();
 
         let mut buf = String::new();
         entry.read_to_string(&mut buf).await?;
-        return Ok(Some(buf));
+        templates.insert(srcpkg, buf);
+
+        if templates.len() == srcpkgs.len() {
+            break;
+        }
     }
 
-    Ok(None)
+    Ok(templates)
+}
+
+pub async fn extract_template<R: AsyncRead + Unpin>(
+    reader: R,
+    srcpkg: &str,
+) -> Result<Option<String>> {
+    let mut templates = extract_templates(reader, &BTreeSet::from([srcpkg])).await?;
+    Ok(templates.remove(srcpkg))
 }
 
 pub async fn stream_data<R: AsyncRead + Unpin>(
@@ -51,7 +67,18 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
     let Some(template) = extract_template(reader, srcpkg).await? else {
         return Ok(());
     };
-    let template = void_template::parse(&template)?;
+    insert_template_refs(db, &template, vendor, package, version).await
+}
+
+/// Record the distfiles of a template as refs and queue their download
+pub async fn insert_template_refs(
+    db: &db::Client,
+    template: &str,
+    vendor: &str,
+    package: &str,
+    version: &str,
+) -> Result<()> {
+    let template = void_template::parse(template)?;
     debug!("Found Void Linux template: {template:?}");
 
     for i in 0..template.distfiles.len() {
@@ -77,8 +104,12 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
                 format!("fetch:{url}"),
                 &TaskData::FetchTar {
                     url: url.to_string(),
+                    mirrors: Vec::new(),
+                    aliases: Vec::new(),
                     compression: None,
                     success_ref: None,
+                    sha256: None,
+                    signature: None,
                 },
             )?)
             .await?;
@@ -90,6 +121,9 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
             package: package.to_string(),
             version: version.to_string(),
             filename: Some(url.to_string()),
+            role: None,
+            repo: None,
+            arch: None,
         };
         info!("insert: {r:?}");
         db.insert_ref(&r).await?;
@@ -114,3 +148,45 @@ pub async fn run(args: &args::IngestVoid) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::GzipEncoder;
+
+    #[tokio::test]
+    async fn test_extract_templates() {
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+        for (path, data) in [
+            ("void-packages-abcd/srcpkgs/zstd/template", "pkgname=zstd\n"),
+            ("void-packages-abcd/srcpkgs/curl/template", "pkgname=curl\n"),
+            ("void-packages-abcd/srcpkgs/xz/template", "pkgname=xz\n"),
+            ("void-packages-abcd/srcpkgs/xz/patches/fix.patch", "--- a\n"),
+        ] {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, data.as_bytes())
+                .await
+                .unwrap();
+        }
+        let tar = builder.into_inner().await.unwrap();
+        let mut tgz = Vec::new();
+        GzipEncoder::new(&tar[..])
+            .read_to_end(&mut tgz)
+            .await
+            .unwrap();
+
+        let srcpkgs = BTreeSet::from(["xz", "zstd", "missing"]);
+        let templates = extract_templates(&tgz[..], &srcpkgs).await.unwrap();
+        assert_eq!(
+            templates,
+            HashMap::from([
+                ("zstd".to_string(), "pkgname=zstd\n".to_string()),
+                ("xz".to_string(), "pkgname=xz\n".to_string()),
+            ])
+        );
+    }
+}
@@ -161,6 +161,9 @@ pub async fn stream_data<R: AsyncRead + Unpin>(
             package: package.to_string(),
             version: version.to_string(),
             filename: Some(url),
+            role: None,
+            repo: None,
+            arch: None,
         };
         info!("insert: {r:?}");
         db.insert_ref(&r).await?;
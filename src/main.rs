@@ -9,8 +9,10 @@ pub mod errors;
 pub mod ingest;
 pub mod pkgbuild;
 pub mod reindex;
+pub mod rpm_spec;
 pub mod sbom;
 pub mod sync;
+pub mod tasks;
 pub mod utils;
 pub mod void_template;
 pub mod web;
@@ -40,7 +42,10 @@ async fn main() -> Result<()> {
         SubCommand::Web(args) => web::run(&args).await,
         SubCommand::Worker(args) => worker::run(&args).await,
         SubCommand::Plumbing(Plumbing::IngestTar(args)) => ingest::tar::run(&args).await,
+        SubCommand::Plumbing(Plumbing::IngestDir(args)) => ingest::dir::run(&args).await,
         SubCommand::Plumbing(Plumbing::IngestGit(args)) => ingest::git::run(&args).await,
+        SubCommand::Plumbing(Plumbing::IngestHg(args)) => ingest::hg::run(&args).await,
+        SubCommand::Plumbing(Plumbing::IngestSvn(args)) => ingest::svn::run(&args).await,
         SubCommand::Plumbing(Plumbing::IngestPacmanSnapshot(args)) => {
             ingest::pacman::run(&args).await
         }
@@ -51,7 +56,9 @@ async fn main() -> Result<()> {
         SubCommand::Plumbing(Plumbing::SyncAlpine(args)) => sync::alpine::run(&args).await,
         SubCommand::Plumbing(Plumbing::SyncApt(args)) => sync::apt::run(&args).await,
         SubCommand::Plumbing(Plumbing::SyncPacman(args)) => sync::pacman::run(&args).await,
+        SubCommand::Plumbing(Plumbing::SyncAur(args)) => sync::aur::run(&args).await,
         SubCommand::Plumbing(Plumbing::SyncRpm(args)) => sync::rpm::run(&args).await,
+        SubCommand::Plumbing(Plumbing::SyncDistgit(args)) => sync::distgit::run(&args).await,
         SubCommand::Plumbing(Plumbing::SyncGentoo(args)) => sync::gentoo::run(&args).await,
         SubCommand::Plumbing(Plumbing::SyncHomebrew(args)) => sync::homebrew::run(&args).await,
         SubCommand::Plumbing(Plumbing::SyncGuix(args)) => sync::guix::run(&args).await,
@@ -60,5 +67,6 @@ async fn main() -> Result<()> {
         SubCommand::Plumbing(Plumbing::AddRef(args)) => alias::run(&args).await,
         SubCommand::Plumbing(Plumbing::ReindexUrl(args)) => reindex::run_url(&args).await,
         SubCommand::Plumbing(Plumbing::ReindexSbom(args)) => reindex::run_sbom(&args).await,
+        SubCommand::Plumbing(Plumbing::Tasks(args)) => tasks::run(&args).await,
     }
 }
@@ -1,14 +1,37 @@
 use crate::errors::*;
+use std::collections::BTreeMap;
 use std::str;
 use yash_syntax::syntax::{self, TextUnit, Value, WordUnit};
 
 #[derive(Debug, Default, PartialEq)]
-pub struct Pkgbuild {
+pub struct Sums {
     pub sha256sums: Vec<String>,
     pub sha512sums: Vec<String>,
     pub b2sums: Vec<String>,
 }
 
+#[derive(Debug, Default, PartialEq)]
+pub struct Pkgbuild {
+    pub sums: Sums,
+    /// Checksums of architecture-specific sources, e.g. `sha256sums_x86_64`
+    pub arch: BTreeMap<String, Sums>,
+}
+
+/// Split a checksum assignment like `sha256sums_x86_64` into the list and the architecture
+fn split_sums_name(name: &str) -> Option<(&'static str, Option<&str>)> {
+    for list in ["sha256sums", "sha512sums", "b2sums"] {
+        match name.strip_prefix(list) {
+            Some("") => return Some((list, None)),
+            Some(suffix) => match suffix.strip_prefix('_') {
+                Some(arch) if !arch.is_empty() => return Some((list, Some(arch))),
+                _ => (),
+            },
+            None => (),
+        }
+    }
+    None
+}
+
 pub fn parse(bytes: &[u8]) -> Result<Pkgbuild> {
     let script = str::from_utf8(bytes)?;
     let parsed: syntax::List = script
@@ -29,17 +52,24 @@ pub fn parse(bytes: &[u8]) -> Result<Pkgbuild> {
                 let name = name.strip_suffix('+').unwrap_or(name);
                 debug!("Found assignment to {name:?}");
 
-                let target = match name {
-                    "sha256sums" => &mut pkgbuild.sha256sums,
-                    "sha512sums" => &mut pkgbuild.sha512sums,
-                    "b2sums" => &mut pkgbuild.b2sums,
-                    _ => continue,
+                let Some((list, arch)) = split_sums_name(name) else {
+                    continue;
                 };
-
                 let Value::Array(values) = &assign.value else {
                     continue;
                 };
 
+                let sums = match arch {
+                    Some(arch) => pkgbuild.arch.entry(arch.to_string()).or_default(),
+                    None => &mut pkgbuild.sums,
+                };
+                let target = match list {
+                    "sha256sums" => &mut sums.sha256sums,
+                    "sha512sums" => &mut sums.sha512sums,
+                    "b2sums" => &mut sums.b2sums,
+                    _ => continue,
+                };
+
                 for value in values {
                     for unit in &value.units {
                         trace!("Found word unit: {unit:?}");
@@ -91,16 +121,47 @@ b2sums=('cd594be73fcf632544195d09518901b1055ae86dcf463a5d446a83beba66073c70a9dfb
         assert_eq!(
             pkgbuild,
             Pkgbuild {
-                sha256sums: vec![
-                    "7a1258a5dfc48c54cea1092adddb6bcfb1fcf19c7272c0a6a9e1d2d7daee6e12".to_string(),
-                    "f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128".to_string(),
-                ],
-                sha512sums: vec![],
-                b2sums: vec![
-                    "cd594be73fcf632544195d09518901b1055ae86dcf463a5d446a83beba66073c70a9dfb75efd9d826c2ecf7215ab6cd76128a20104d5ef4ea57470061d2e29bf".to_string(),
-                    "f4f89b720bcbe23c5413c6cbc2d0793d8e379fc53861a6fbd83f506e56a86132bb92236498b4357310b09e51fd05aa5ccc941649a4f205fb4e53cb6bc32cdd64".to_string(),
-                ],
+                sums: Sums {
+                    sha256sums: vec![
+                        "7a1258a5dfc48c54cea1092adddb6bcfb1fcf19c7272c0a6a9e1d2d7daee6e12".to_string(),
+                        "f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128".to_string(),
+                    ],
+                    sha512sums: vec![],
+                    b2sums: vec![
+                        "cd594be73fcf632544195d09518901b1055ae86dcf463a5d446a83beba66073c70a9dfb75efd9d826c2ecf7215ab6cd76128a20104d5ef4ea57470061d2e29bf".to_string(),
+                        "f4f89b720bcbe23c5413c6cbc2d0793d8e379fc53861a6fbd83f506e56a86132bb92236498b4357310b09e51fd05aa5ccc941649a4f205fb4e53cb6bc32cdd64".to_string(),
+                    ],
+                },
+                arch: BTreeMap::new(),
             }
         );
     }
+
+    #[test]
+    fn test_parse_arch() {
+        init();
+
+        let script =
+            b"sha256sums=('7a1258a5dfc48c54cea1092adddb6bcfb1fcf19c7272c0a6a9e1d2d7daee6e12')
+sha256sums_x86_64=('f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128')
+sha256sums_aarch64=('2fb6121a6eb4c5e6afaf22212b2ed15022500a4bc34bb3dc0f9782c1d43c3962')
+_pkgname=example
+_commit=('f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128')
+sha256sums_armv7h=SKIP
+md5sums_i686=('d41d8cd98f00b204e9800998ecf8427e')
+";
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(
+            pkgbuild.sums.sha256sums,
+            vec!["7a1258a5dfc48c54cea1092adddb6bcfb1fcf19c7272c0a6a9e1d2d7daee6e12".to_string()]
+        );
+        assert_eq!(
+            pkgbuild.arch.keys().collect::<Vec<_>>(),
+            vec!["aarch64", "x86_64"]
+        );
+        assert_eq!(
+            pkgbuild.arch["x86_64"].sha256sums,
+            vec!["f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128".to_string()]
+        );
+    }
 }
@@ -0,0 +1,539 @@
+use crate::errors::*;
+use std::collections::HashMap;
+use std::str;
+
+/// Prevent macros that reference themselves from recursing forever
+const MAX_EXPANSION_DEPTH: usize = 32;
+/// Prevent macros that reference other macros multiple times from growing exponentially
+const MAX_EXPANSION_LEN: usize = 64 * 1024;
+/// Spec files are plain text, refuse to buffer anything larger than this
+pub const MAX_SPEC_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Sections that contain scripts or text instead of tags
+const BODY_SECTIONS: &[&str] = &[
+    "%description",
+    "%prep",
+    "%generate_buildrequires",
+    "%conf",
+    "%build",
+    "%install",
+    "%check",
+    "%clean",
+    "%files",
+    "%changelog",
+    "%pre",
+    "%post",
+    "%preun",
+    "%postun",
+    "%pretrans",
+    "%posttrans",
+    "%triggerin",
+    "%triggerun",
+    "%triggerpostun",
+    "%verifyscript",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceKind {
+    Source,
+    Patch,
+}
+
+impl SourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceKind::Source => "source",
+            SourceKind::Patch => "patch",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SpecSource {
+    pub kind: SourceKind,
+    pub num: u32,
+    pub value: String,
+}
+
+impl SpecSource {
+    /// The upstream url, without the `#/filename` suffix some packagers use to rename downloads
+    pub fn url(&self) -> Option<&str> {
+        if !self.value.contains("://") {
+            return None;
+        }
+        Some(
+            self.value
+                .split_once('#')
+                .map(|(url, _)| url)
+                .unwrap_or(&self.value),
+        )
+    }
+
+    /// The name of the file inside of the source rpm
+    pub fn filename(&self) -> &str {
+        if let Some((_, name)) = self.value.split_once("#/") {
+            return name;
+        }
+        let path = self.url().unwrap_or(&self.value);
+        let path = path.split_once('?').map(|(path, _)| path).unwrap_or(path);
+        path.rsplit_once('/').map(|(_, name)| name).unwrap_or(path)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Spec {
+    pub macros: HashMap<String, String>,
+    pub sources: Vec<SpecSource>,
+}
+
+impl Spec {
+    pub fn name(&self) -> Option<&str> {
+        self.macros.get("name").map(String::as_str)
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.macros.get("version").map(String::as_str)
+    }
+
+    pub fn release(&self) -> Option<&str> {
+        self.macros.get("release").map(String::as_str)
+    }
+
+    /// Find the source or patch a file in the source rpm originates from
+    pub fn find_source(&self, filename: &str) -> Option<&SpecSource> {
+        self.sources
+            .iter()
+            .find(|source| source.filename() == filename)
+    }
+
+    pub fn expand(&self, text: &str) -> Result<String> {
+        let mut budget = MAX_EXPANSION_LEN;
+        self.expand_depth(text, 0, &mut budget)
+    }
+
+    /// Every call and every byte of text is paid for from `budget`, since the output of an
+    /// expansion is built from the texts it visits this also limits the size of the result
+    fn expand_depth(&self, text: &str, depth: usize, budget: &mut usize) -> Result<String> {
+        *budget = budget
+            .checked_sub(text.len() + 1)
+            .ok_or(Error::RpmMacroExpansionTooLarge)?;
+        if depth > MAX_EXPANSION_DEPTH {
+            return Ok(text.to_string());
+        }
+
+        let mut out = String::new();
+        let mut text = text;
+        while let Some((before, after)) = text.split_once('%') {
+            out.push_str(before);
+
+            if let Some(after) = after.strip_prefix('%') {
+                out.push('%');
+                text = after;
+            } else if let Some(after) = after.strip_prefix('{') {
+                let Some(end) = find_closing_brace(after) else {
+                    // unbalanced, keep as-is
+                    out.push_str("%{");
+                    text = after;
+                    continue;
+                };
+                let inner = &after[..end];
+                text = &after[end + 1..];
+                match self.expand_braced(inner, depth, budget)? {
+                    Some(value) => out.push_str(&value),
+                    None => {
+                        out.push_str("%{");
+                        out.push_str(inner);
+                        out.push('}');
+                    }
+                }
+            } else {
+                let len = after
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(after.len());
+                let name = &after[..len];
+                match self.macros.get(name) {
+                    Some(value) if !name.is_empty() => {
+                        out.push_str(&self.expand_depth(value, depth + 1, budget)?)
+                    }
+                    _ => {
+                        out.push('%');
+                        out.push_str(name);
+                    }
+                }
+                text = &after[len..];
+            }
+        }
+        out.push_str(text);
+        Ok(out)
+    }
+
+    /// Expand the content of `%{...}`, returns None for macros we don't know
+    fn expand_braced(
+        &self,
+        inner: &str,
+        depth: usize,
+        budget: &mut usize,
+    ) -> Result<Option<String>> {
+        let (negate, conditional, inner) = if let Some(inner) = inner.strip_prefix("!?") {
+            (true, true, inner)
+        } else if let Some(inner) = inner.strip_prefix('?') {
+            (false, true, inner)
+        } else {
+            (false, false, inner)
+        };
+
+        if conditional {
+            let (name, text) = inner
+                .split_once(':')
+                .map(|(name, text)| (name, Some(text)))
+                .unwrap_or((inner, None));
+            let value = self.macros.get(name);
+            let expanded = match (value, text, negate) {
+                (Some(_), Some(text), false) => self.expand_depth(text, depth + 1, budget)?,
+                (Some(value), None, false) => self.expand_depth(value, depth + 1, budget)?,
+                (None, Some(text), true) => self.expand_depth(text, depth + 1, budget)?,
+                _ => String::new(),
+            };
+            Ok(Some(expanded))
+        } else {
+            let Some(value) = self.macros.get(inner) else {
+                return Ok(None);
+            };
+            self.expand_depth(value, depth + 1, budget).map(Some)
+        }
+    }
+
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.macros.insert(name.to_string(), value.to_string());
+    }
+
+    /// Evaluate a `%if` expression, returns None if we don't understand it
+    fn evaluate(&self, expr: &str) -> Result<Option<bool>> {
+        let expr = self.expand(expr)?;
+        Ok(evaluate_expanded(expr.trim()))
+    }
+}
+
+fn evaluate_expanded(expr: &str) -> Option<bool> {
+    if let Some(expr) = expr.strip_prefix('!') {
+        return evaluate_expanded(expr.trim_start()).map(|b| !b);
+    }
+    for (op, eq) in [("==", true), ("!=", false)] {
+        if let Some((left, right)) = expr.split_once(op) {
+            let left = left.trim().trim_matches('"');
+            let right = right.trim().trim_matches('"');
+            if left.contains('%') || right.contains('%') {
+                return None;
+            }
+            return Some((left == right) == eq);
+        }
+    }
+    expr.parse::<i64>().ok().map(|n| n != 0)
+}
+
+fn find_closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Split `Source12: value` into its tag and value
+fn split_tag(line: &str) -> Option<(&str, &str)> {
+    let (tag, value) = line.split_once(':')?;
+    if tag.is_empty()
+        || !tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '(' || c == ')')
+    {
+        return None;
+    }
+    Some((tag, value.trim()))
+}
+
+fn parse_numbered(tag: &str, prefix: &str) -> Option<u32> {
+    let tag = tag.to_ascii_lowercase();
+    let num = tag.strip_prefix(prefix)?;
+    if num.is_empty() {
+        Some(0)
+    } else {
+        num.parse().ok()
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Spec> {
+    parse_with_macros(bytes, HashMap::new())
+}
+
+/// Parse a spec file with some macros already known, e.g. from the rpm header
+pub fn parse_with_macros(bytes: &[u8], macros: HashMap<String, String>) -> Result<Spec> {
+    let script = str::from_utf8(bytes)?;
+    let mut spec = Spec {
+        macros,
+        ..Default::default()
+    };
+
+    // each entry tells us if the current branch is active
+    let mut conditions = Vec::<Option<bool>>::new();
+    let mut in_body = false;
+
+    for line in script.lines() {
+        let line = line.trim();
+        let keyword = line.split_whitespace().next().unwrap_or("");
+
+        match keyword {
+            "%if" | "%ifarch" | "%ifnarch" | "%ifos" | "%ifnos" => {
+                let condition = if keyword == "%if" {
+                    spec.evaluate(&line[keyword.len()..])?
+                } else {
+                    None
+                };
+                conditions.push(condition);
+                continue;
+            }
+            "%elif" => {
+                // the previous branch may have been taken, we don't track this
+                if let Some(condition) = conditions.last_mut() {
+                    *condition = None;
+                }
+                continue;
+            }
+            "%else" => {
+                if let Some(condition) = conditions.last_mut() {
+                    *condition = condition.map(|b| !b);
+                }
+                continue;
+            }
+            "%endif" => {
+                conditions.pop();
+                continue;
+            }
+            _ => (),
+        }
+
+        // conditions we couldn't evaluate are considered true
+        if conditions.contains(&Some(false)) {
+            continue;
+        }
+
+        if keyword == "%package" {
+            in_body = false;
+            continue;
+        } else if BODY_SECTIONS.contains(&keyword) {
+            if keyword == "%changelog" {
+                // nothing after this is relevant for us
+                break;
+            }
+            in_body = true;
+            continue;
+        }
+
+        if keyword == "%define" || keyword == "%global" {
+            let mut parts = line[keyword.len()..].trim().splitn(2, char::is_whitespace);
+            let (Some(name), Some(value)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let name = name.split_once('(').map(|(name, _)| name).unwrap_or(name);
+            let value = if keyword == "%global" {
+                spec.expand(value.trim())?
+            } else {
+                value.trim().to_string()
+            };
+            spec.define(name, &value);
+            continue;
+        }
+
+        if in_body {
+            continue;
+        }
+
+        let Some((tag, value)) = split_tag(line) else {
+            continue;
+        };
+        let value = spec.expand(value)?;
+
+        if let Some(num) = parse_numbered(tag, "source") {
+            spec.sources.push(SpecSource {
+                kind: SourceKind::Source,
+                num,
+                value,
+            });
+        } else if let Some(num) = parse_numbered(tag, "patch") {
+            spec.sources.push(SpecSource {
+                kind: SourceKind::Patch,
+                num,
+                value,
+            });
+        } else if let key @ ("name" | "version" | "release" | "epoch" | "url") =
+            tag.to_ascii_lowercase().as_str()
+        {
+            // only the main package sets these
+            if !spec.macros.contains_key(key) {
+                spec.define(key, &value);
+            }
+        }
+    }
+
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let data = br#"%global major 2
+%global minor 45
+%define pkg_version %{major}.%{minor}.2
+
+Name:           git
+Version:        %{pkg_version}
+Release:        1%{?dist}
+Summary:        Fast Version Control System
+License:        GPL-2.0-only
+URL:            https://git-scm.com/
+Source0:        https://www.kernel.org/pub/software/scm/git/%{name}-%{version}.tar.xz
+Source1:        https://www.kernel.org/pub/software/scm/git/%{name}-%{version}.tar.sign
+Source2:        git-gui.desktop
+%if 0%{?fedora} >= 40
+Source3:        %{url}fedora.txt
+%endif
+%if 0
+Source4:        https://example.com/disabled.tar.gz
+%else
+Source5:        %{?with_docs:https://example.com/docs-%{version}.tar.gz}%{!?with_docs:docs.tar.gz}
+%endif
+Patch0:         git-cvsimport-Ignore-cvsps-2.2b1-Branches-output.patch
+Patch12:        https://github.com/git/git/commit/0123abc.patch#/git-fix-build.patch
+
+%package daemon
+Summary:        Git protocol daemon
+Version:        1.0
+
+%description
+Source9: this is not a tag
+
+%prep
+%autosetup -p1
+
+%changelog
+* Thu Jun 06 2024 Someone <someone@example.com> - 2.45.2-1
+- Source0: not a tag either
+"#;
+        let spec = parse(data).unwrap();
+        assert_eq!(spec.name(), Some("git"));
+        assert_eq!(spec.version(), Some("2.45.2"));
+        assert_eq!(spec.macros.get("release").unwrap(), "1");
+        assert_eq!(
+            spec.sources,
+            vec![
+                SpecSource {
+                    kind: SourceKind::Source,
+                    num: 0,
+                    value: "https://www.kernel.org/pub/software/scm/git/git-2.45.2.tar.xz"
+                        .to_string(),
+                },
+                SpecSource {
+                    kind: SourceKind::Source,
+                    num: 1,
+                    value: "https://www.kernel.org/pub/software/scm/git/git-2.45.2.tar.sign"
+                        .to_string(),
+                },
+                SpecSource {
+                    kind: SourceKind::Source,
+                    num: 2,
+                    value: "git-gui.desktop".to_string(),
+                },
+                SpecSource {
+                    kind: SourceKind::Source,
+                    num: 3,
+                    value: "https://git-scm.com/fedora.txt".to_string(),
+                },
+                SpecSource {
+                    kind: SourceKind::Source,
+                    num: 5,
+                    value: "docs.tar.gz".to_string(),
+                },
+                SpecSource {
+                    kind: SourceKind::Patch,
+                    num: 0,
+                    value: "git-cvsimport-Ignore-cvsps-2.2b1-Branches-output.patch".to_string(),
+                },
+                SpecSource {
+                    kind: SourceKind::Patch,
+                    num: 12,
+                    value: "https://github.com/git/git/commit/0123abc.patch#/git-fix-build.patch"
+                        .to_string(),
+                },
+            ]
+        );
+
+        let source = spec.find_source("git-2.45.2.tar.xz").unwrap();
+        assert_eq!(
+            source.url(),
+            Some("https://www.kernel.org/pub/software/scm/git/git-2.45.2.tar.xz")
+        );
+        let source = spec.find_source("git-fix-build.patch").unwrap();
+        assert_eq!(source.kind, SourceKind::Patch);
+        assert_eq!(
+            source.url(),
+            Some("https://github.com/git/git/commit/0123abc.patch")
+        );
+        let source = spec.find_source("git-gui.desktop").unwrap();
+        assert_eq!(source.url(), None);
+        assert!(spec.find_source("git.spec").is_none());
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let mut spec = Spec::default();
+        spec.define("name", "foo");
+        spec.define("version", "1.2.3");
+        spec.define("pyname", "%{name}-py");
+        assert_eq!(spec.expand("%{name}-%{version}").unwrap(), "foo-1.2.3");
+        assert_eq!(
+            spec.expand("%name-%version.tar.gz").unwrap(),
+            "foo-1.2.3.tar.gz"
+        );
+        assert_eq!(spec.expand("%{pyname}").unwrap(), "foo-py");
+        assert_eq!(spec.expand("100%%").unwrap(), "100%");
+        assert_eq!(spec.expand("%{?dist}").unwrap(), "");
+        assert_eq!(spec.expand("%{?name}").unwrap(), "foo");
+        assert_eq!(spec.expand("%{?name:yes}%{!?name:no}").unwrap(), "yes");
+        assert_eq!(spec.expand("%{?missing:yes}%{!?missing:no}").unwrap(), "no");
+        assert_eq!(spec.expand("%{unknown}/%{name}").unwrap(), "%{unknown}/foo");
+        assert_eq!(spec.expand("%(echo hi)").unwrap(), "%(echo hi)");
+
+        spec.define("loop", "%{loop}");
+        assert!(spec.expand("%{loop}").unwrap().starts_with("%{loop}"));
+    }
+
+    #[test]
+    fn test_expand_exponential_global() {
+        let mut script = String::from("Name: foo\n%global a0 xxxxxxxx\n");
+        for i in 1..=40 {
+            script.push_str(&format!("%global a{i} %{{a{}}}%{{a{}}}\n", i - 1, i - 1));
+        }
+        let err = parse(script.as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::RpmMacroExpansionTooLarge));
+    }
+
+    #[test]
+    fn test_expand_exponential_define() {
+        let mut spec = Spec::default();
+        spec.define("a0", "");
+        for i in 1..=32 {
+            spec.define(&format!("a{i}"), &format!("%{{a{}}}%{{a{}}}", i - 1, i - 1));
+        }
+        assert!(spec.expand("%{a4}").is_ok());
+        let err = spec.expand("%{a32}").unwrap_err();
+        assert!(matches!(err, Error::RpmMacroExpansionTooLarge));
+    }
+}
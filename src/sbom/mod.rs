@@ -128,12 +128,16 @@ pub async fn index(db: &db::Client, sbom: &Sbom) -> Result<()> {
                     format!("fetch:{url}"),
                     &db::TaskData::FetchTar {
                         url,
+                        mirrors: Vec::new(),
+                        aliases: Vec::new(),
                         compression: Some("gz".to_string()),
                         success_ref: Some(db::DownloadRef {
                             vendor: cargo::VENDOR.to_string(),
                             package: pkg.name.to_string(),
                             version: pkg.version.to_string(),
                         }),
+                        sha256: None,
+                        signature: None,
                     },
                 )?)
                 .await?;
@@ -179,12 +183,16 @@ pub async fn index(db: &db::Client, sbom: &Sbom) -> Result<()> {
                     format!("fetch:{url}"),
                     &db::TaskData::FetchTar {
                         url,
+                        mirrors: Vec::new(),
+                        aliases: Vec::new(),
                         compression: Some("gz".to_string()),
                         success_ref: Some(db::DownloadRef {
                             vendor: yarn::VENDOR.to_string(),
                             package: pkg.name.to_string(),
                             version: pkg.version.to_string(),
                         }),
+                        sha256: None,
+                        signature: None,
                     },
                 )?)
                 .await?;
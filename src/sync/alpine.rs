@@ -59,7 +59,12 @@ pub async fn run(args: &args::SyncAlpine) -> Result<()> {
     let db = db::Client::create().await?;
     let vendor = &args.vendor;
 
-    let reader = utils::fetch_or_open(&args.file, args.fetch).await?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+    let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+        return Ok(());
+    };
     let mut reader = io::BufReader::new(reader);
     {
         // discard first part of apkv2
@@ -117,6 +122,8 @@ pub async fn run(args: &args::SyncAlpine) -> Result<()> {
         }
     }
 
+    http.mark_processed(&args.file).await?;
+
     Ok(())
 }
 
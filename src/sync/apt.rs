@@ -35,7 +35,9 @@ pub async fn run(args: &args::SyncApt) -> Result<()> {
     let base_url = args.url.strip_suffix('/').unwrap_or(&args.url);
 
     let db = db::Client::create().await?;
-    let http = utils::http_client(None)?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
 
     for release in &args.releases {
         for suite in &args.suites {
@@ -45,7 +47,9 @@ pub async fn run(args: &args::SyncApt) -> Result<()> {
 
             let url = format!("{base_url}/dists/{release}/{filename}");
             info!("Fetching Sources index: url={url:?}");
-            let reader = http.fetch(&url).await?;
+            let Some(reader) = http.fetch_index(&url, true).await? else {
+                continue;
+            };
             let reader = io::BufReader::new(reader);
             let mut reader = match compression {
                 "gz" => Decompressor::gz(reader),
@@ -57,54 +61,95 @@ pub async fn run(args: &args::SyncApt) -> Result<()> {
             reader.read_to_end(&mut buf).await?;
 
             let sources = apt::SourcesIndex::parse(&buf)?;
+            let repo = format!("{release}/{suite}");
 
             for pkg in &sources.pkgs {
                 debug!("pkg={pkg:?}");
-                pkg.version.as_ref().unwrap();
+                let vendor = &args.vendor;
+                let package = &pkg.package;
+                let version = pkg.version.as_ref().unwrap();
                 pkg.directory.as_ref().unwrap();
 
+                // mark all refs known for this package as "last_seen now"
+                db.bump_named_refs(vendor, package, version).await?;
+                // the same version may be part of multiple suites
+                db.insert_package_repo(vendor, package, version, &repo)
+                    .await?;
+
+                if !args.reindex && db.get_package(vendor, package, version).await?.is_some() {
+                    debug!("Package is already imported: vendor={vendor:?} package={package:?} version={version:?} repo={repo:?}");
+                    continue;
+                }
+
                 for entry in &pkg.checksums_sha256 {
                     let name = entry.filename.clone();
-                    if name.ends_with(".orig.tar.xz")
-                        || name.ends_with(".orig.tar.gz")
-                        || name.ends_with(".orig.tar.bz2")
-                    {
-                        let chksum = format!("sha256:{}", entry.hash);
-                        let package = pkg.package.to_string();
-                        let version = pkg.version.clone().unwrap();
-                        info!(
-                            "digest={chksum:?} package={package:?} version={version:?} name={name:?}"
-                        );
-                        let obj = db::Ref {
-                            chksum,
-                            vendor: args.vendor.to_string(),
-                            package,
-                            version,
-                            filename: Some(name.clone()),
-                        };
-                        db.insert_ref(&obj).await?;
-
-                        if name.starts_with("chromium_") {
-                            continue;
-                        }
-
-                        if args.reindex || db.resolve_artifact(&obj.chksum).await?.is_none() {
-                            let directory = pkg.directory.as_ref().unwrap();
-                            let url = format!("{base_url}/{directory}/{name}");
-                            info!("url={url:?}");
-                            db.insert_task(&Task::new(
-                                format!("fetch:{url}"),
-                                &TaskData::FetchTar {
-                                    url,
-                                    compression: None,
-                                    success_ref: None,
-                                },
-                            )?)
+                    let role = apt::SourceRole::from_filename(&name);
+                    let chksum = format!("sha256:{}", entry.hash);
+                    let package = package.to_string();
+                    let version = version.to_string();
+                    info!(
+                        "digest={chksum:?} package={package:?} version={version:?} name={name:?} role={role:?}"
+                    );
+                    let obj = db::Ref {
+                        chksum,
+                        vendor: args.vendor.to_string(),
+                        package,
+                        version,
+                        filename: Some(name.clone()),
+                        role: role.map(|role| role.as_str().to_string()),
+                        repo: None,
+                        arch: None,
+                    };
+                    db.insert_ref(&obj).await?;
+
+                    if !apt::is_source_tarball(&name) || name.starts_with("chromium_") {
+                        continue;
+                    }
+
+                    let aliases = pkg.aliases_for(&name);
+                    let artifact = db.resolve_artifact(&obj.chksum).await?;
+                    if let Some(artifact) = &artifact {
+                        db.register_index_aliases(&aliases, &artifact.chksum)
                             .await?;
-                        }
+                    }
+
+                    if args.reindex || artifact.is_none() {
+                        let directory = pkg.directory.as_ref().unwrap();
+                        let url = format!("{base_url}/{directory}/{name}");
+                        let mirrors = args
+                            .mirrors
+                            .iter()
+                            .map(|mirror| {
+                                let mirror = mirror.strip_suffix('/').unwrap_or(mirror);
+                                format!("{mirror}/{directory}/{name}")
+                            })
+                            .collect();
+                        info!("url={url:?} mirrors={mirrors:?}");
+                        db.insert_task(&Task::new(
+                            format!("fetch:{url}"),
+                            &TaskData::FetchTar {
+                                url,
+                                mirrors,
+                                aliases: aliases.clone(),
+                                compression: None,
+                                success_ref: None,
+                                sha256: Some(obj.chksum.clone()),
+                                signature: None,
+                            },
+                        )?)
+                        .await?;
                     }
                 }
+
+                db.insert_package(&db::Package {
+                    vendor: vendor.to_string(),
+                    package: package.to_string(),
+                    version: version.to_string(),
+                })
+                .await?;
             }
+
+            http.mark_processed(&url).await?;
         }
     }
 
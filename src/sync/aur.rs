@@ -0,0 +1,211 @@
+use crate::args;
+use crate::compression::Decompressor;
+use crate::db;
+use crate::errors::*;
+use crate::ingest::pacman::{self, Snapshot};
+use crate::utils;
+use futures::StreamExt;
+use srcinfo::Srcinfo;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::{self, AsyncRead, AsyncReadExt};
+use tokio::process;
+use tokio_tar::{Archive, EntryType};
+
+/// .SRCINFO files are small, refuse to buffer anything larger than this
+const MAX_SRCINFO_SIZE: u64 = 1024 * 1024;
+
+/// Read a tarball of the AUR metadata, e.g. `<pkgbase>/.SRCINFO`
+async fn read_tarball<R: AsyncRead + Unpin>(reader: R, index: &mut Vec<String>) -> Result<()> {
+    let mut tar = Archive::new(reader);
+    let mut entries = tar.entries()?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let path = entry.path()?;
+        if path.file_name().is_none_or(|name| name != ".SRCINFO") {
+            continue;
+        }
+
+        debug!("Found .SRCINFO in tarball: {path:?}");
+        let mut buf = String::new();
+        entry.read_to_string(&mut buf).await?;
+        index.push(buf);
+    }
+    Ok(())
+}
+
+/// Read a directory of checkouts, e.g. `<dir>/<pkgbase>/.SRCINFO`
+async fn read_directory(path: &Path, index: &mut Vec<String>) -> Result<()> {
+    let files = utils::read_checkouts(path, |_| vec![".SRCINFO".to_string()], MAX_SRCINFO_SIZE);
+    for file in files.await? {
+        index.push(String::from_utf8_lossy(&file.data).into_owned());
+    }
+    Ok(())
+}
+
+async fn git_output(path: &Path, args: &[&str]) -> Result<std::process::Output> {
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()
+        .await?;
+    Ok(output)
+}
+
+/// Read a clone of aur.git, with one branch per pkgbase
+async fn read_git_branches(path: &Path, index: &mut Vec<String>) -> Result<()> {
+    let output = git_output(
+        path,
+        &[
+            "for-each-ref",
+            "--format=%(refname)",
+            "refs/heads",
+            "refs/remotes",
+        ],
+    )
+    .await?;
+    if !output.status.success() {
+        return Err(Error::GitError(output.status));
+    }
+
+    for refname in String::from_utf8_lossy(&output.stdout).lines() {
+        if refname.ends_with("/HEAD") {
+            continue;
+        }
+        let output = git_output(path, &["show", &format!("{refname}:.SRCINFO")]).await?;
+        if !output.status.success() {
+            debug!("Branch has no .SRCINFO, skipping: {refname:?}");
+            continue;
+        }
+        index.push(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    Ok(())
+}
+
+/// Parse all .SRCINFO files, keyed by pkgbase
+fn parse_index(index: Vec<String>) -> BTreeMap<String, (String, Snapshot)> {
+    let mut out = BTreeMap::new();
+    for buf in index {
+        let srcinfo = match Srcinfo::parse_buf(buf.as_bytes()) {
+            Ok(srcinfo) => srcinfo,
+            Err(err) => {
+                warn!("Failed to parse .SRCINFO: {err:#}");
+                continue;
+            }
+        };
+        out.insert(
+            srcinfo.base.pkgbase.clone(),
+            (srcinfo.version(), Snapshot::from_srcinfo(buf)),
+        );
+    }
+    out
+}
+
+pub async fn run(args: &args::SyncAur) -> Result<()> {
+    let db = db::Client::create().await?;
+    let vendor = &args.vendor;
+
+    let mut index = Vec::new();
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+
+    let path = Path::new(&args.file);
+    if !args.fetch && fs::metadata(path).await?.is_dir() {
+        if fs::try_exists(path.join(".git")).await? || fs::try_exists(path.join("HEAD")).await? {
+            read_git_branches(path, &mut index).await?;
+        } else {
+            read_directory(path, &mut index).await?;
+        }
+    } else {
+        let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+            return Ok(());
+        };
+        let reader = Decompressor::detect(io::BufReader::new(reader), &args.file);
+        read_tarball(reader, &mut index).await?;
+    }
+
+    for (pkgbase, (version, snapshot)) in parse_index(index) {
+        // mark all refs known for this package as "last_seen now"
+        db.bump_named_refs(vendor, &pkgbase, &version).await?;
+
+        if db.get_package(vendor, &pkgbase, &version).await?.is_some() {
+            debug!("Package is already imported: vendor={vendor:?} package={pkgbase:?} version={version:?}");
+            continue;
+        }
+
+        info!("package={pkgbase:?} version={version:?}");
+        // anybody can upload to the AUR, only fetch sources from https
+        pacman::insert_source_entries(&db, &snapshot, vendor, &pkgbase, &version, false).await?;
+
+        db.insert_package(&db::Package {
+            vendor: vendor.to_string(),
+            package: pkgbase,
+            version,
+        })
+        .await?;
+    }
+
+    if args.fetch {
+        http.mark_processed(&args.file).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_tarball() {
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+        for (path, data) in [
+            (
+                "aur/yay/.SRCINFO",
+                "pkgbase = yay
+	pkgdesc = Yet another yogurt. Pacman wrapper and AUR helper written in go.
+	pkgver = 12.3.5
+	pkgrel = 1
+	url = https://github.com/Jguer/yay
+	arch = x86_64
+	license = GPL-3.0-or-later
+	source = yay-12.3.5.tar.gz::https://github.com/Jguer/yay/archive/v12.3.5.tar.gz
+	sha256sums = 2fb6121a6eb4c5e6afaf22212b2ed15022500a4bc34bb3dc0f9782c1d43c3962
+
+pkgname = yay
+",
+            ),
+            ("aur/yay/PKGBUILD", "pkgname=yay\n"),
+        ] {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, data.as_bytes())
+                .await
+                .unwrap();
+        }
+        let tar = builder.into_inner().await.unwrap();
+
+        let mut index = Vec::new();
+        read_tarball(&tar[..], &mut index).await.unwrap();
+        assert_eq!(index.len(), 1);
+
+        let index = parse_index(index);
+        let (version, snapshot) = &index["yay"];
+        assert_eq!(version, "12.3.5-1");
+        let entries = snapshot.source_entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].preferred_chksum().as_deref(),
+            Some("sha256:2fb6121a6eb4c5e6afaf22212b2ed15022500a4bc34bb3dc0f9782c1d43c3962")
+        );
+    }
+}
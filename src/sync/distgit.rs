@@ -0,0 +1,319 @@
+use crate::args;
+use crate::compression::Decompressor;
+use crate::db::{self, Task, TaskData};
+use crate::errors::*;
+use crate::rpm_spec::{self, Spec};
+use crate::utils;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio::fs;
+use tokio::io::{self, AsyncRead, AsyncReadExt};
+use tokio_tar::{Archive, EntryType};
+
+#[derive(Debug, PartialEq)]
+pub struct SourcesEntry {
+    pub algo: String,
+    pub filename: String,
+    pub hash: String,
+}
+
+impl SourcesEntry {
+    pub fn chksum(&self) -> String {
+        format!("{}:{}", self.algo, self.hash)
+    }
+
+    /// The content-addressed location of this file in the lookaside cache
+    pub fn lookaside_url(&self, lookaside: &str, package: &str) -> String {
+        let lookaside = lookaside.strip_suffix('/').unwrap_or(lookaside);
+        let Self {
+            algo,
+            filename,
+            hash,
+        } = self;
+        format!("{lookaside}/{package}/{filename}/{algo}/{hash}/{filename}")
+    }
+}
+
+/// Parse a line of a dist-git `sources` file, e.g. `SHA512 (foo-1.0.tar.gz) = ...`
+///
+/// The legacy `<md5>  <filename>` format is not supported since we can't verify md5.
+pub fn parse_sources_line(line: &str) -> Option<SourcesEntry> {
+    let (algo, line) = line.split_once(" (")?;
+    let (filename, hash) = line.rsplit_once(") = ")?;
+    let algo = algo.to_ascii_lowercase();
+    if !matches!(algo.as_str(), "sha256" | "sha512") {
+        return None;
+    }
+    let hash = hash.trim();
+    if hash.is_empty() || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(SourcesEntry {
+        algo,
+        filename: filename.to_string(),
+        hash: hash.to_ascii_lowercase(),
+    })
+}
+
+pub fn parse_sources(data: &str) -> Vec<SourcesEntry> {
+    data.lines().filter_map(parse_sources_line).collect()
+}
+
+#[derive(Debug, Default)]
+pub struct Package {
+    pub sources: Vec<SourcesEntry>,
+    pub spec: Option<Spec>,
+}
+
+impl Package {
+    fn add_file(
+        &mut self,
+        package: &str,
+        filename: &str,
+        data: &[u8],
+        macros: &HashMap<String, String>,
+    ) {
+        if filename == "sources" {
+            self.sources = parse_sources(&String::from_utf8_lossy(data));
+        } else if filename == format!("{package}.spec") {
+            match rpm_spec::parse_with_macros(data, macros.clone()) {
+                Ok(spec) => self.spec = Some(spec),
+                Err(err) => warn!("Failed to parse spec file of {package:?}: {err:#}"),
+            }
+        }
+    }
+}
+
+/// Read a tarball of dist-git repositories, e.g. `rpms/<package>/sources`
+async fn read_tarball<R: AsyncRead + Unpin>(
+    reader: R,
+    index: &mut BTreeMap<String, Package>,
+    macros: &HashMap<String, String>,
+) -> Result<()> {
+    let mut tar = Archive::new(reader);
+    let mut entries = tar.entries()?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+
+        let Some(package) = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|p| p.to_str())
+        else {
+            continue;
+        };
+        let Some(filename) = path.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
+        if filename != "sources" && !filename.ends_with(".spec") {
+            continue;
+        }
+
+        debug!("Found file in dist-git tarball: {path:?}");
+        if entry.header().size()? > rpm_spec::MAX_SPEC_SIZE {
+            warn!("File in dist-git tarball is too large, skipping: {path:?}");
+            continue;
+        }
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await?;
+        index
+            .entry(package.to_string())
+            .or_default()
+            .add_file(package, filename, &buf, macros);
+    }
+    Ok(())
+}
+
+/// Read a directory of dist-git clones, e.g. `<dir>/<package>/sources`
+async fn read_directory(
+    path: &Path,
+    index: &mut BTreeMap<String, Package>,
+    macros: &HashMap<String, String>,
+) -> Result<()> {
+    let files = utils::read_checkouts(
+        path,
+        |package| vec!["sources".to_string(), format!("{package}.spec")],
+        rpm_spec::MAX_SPEC_SIZE,
+    );
+    for file in files.await? {
+        index.entry(file.package.clone()).or_default().add_file(
+            &file.package,
+            &file.filename,
+            &file.data,
+            macros,
+        );
+    }
+    Ok(())
+}
+
+pub async fn run(args: &args::SyncDistgit) -> Result<()> {
+    let db = db::Client::create().await?;
+    let vendor = &args.vendor;
+
+    let mut index = BTreeMap::new();
+    let mut macros = HashMap::new();
+    if let Some(dist) = &args.dist {
+        macros.insert("dist".to_string(), dist.to_string());
+    }
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+
+    let path = Path::new(&args.file);
+    if !args.fetch && fs::metadata(path).await?.is_dir() {
+        read_directory(path, &mut index, &macros).await?;
+    } else {
+        let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+            return Ok(());
+        };
+        let reader = Decompressor::detect(io::BufReader::new(reader), &args.file);
+        read_tarball(reader, &mut index, &macros).await?;
+    }
+
+    for (package, pkg) in &index {
+        let spec = pkg.spec.as_ref();
+        let (Some(version), Some(release)) = (
+            spec.and_then(|spec| spec.version()),
+            spec.and_then(|spec| spec.release()),
+        ) else {
+            warn!("Failed to detect version of dist-git package, skipping: {package:?}");
+            continue;
+        };
+        // same format as sync-rpm, e.g. `%autorelease` can't be expanded without the git history
+        if version.contains('%') || release.contains('%') {
+            warn!("Failed to expand version of dist-git package, skipping: {package:?} (version={version:?}, release={release:?})");
+            continue;
+        }
+        let version = format!("{version}-{release}");
+
+        for entry in &pkg.sources {
+            let chksum = entry.chksum();
+            let lookaside_url = entry.lookaside_url(&args.lookaside, package);
+            let source = pkg
+                .spec
+                .as_ref()
+                .and_then(|spec| spec.find_source(&entry.filename));
+            let upstream_url = source.and_then(|source| source.url());
+
+            let already_imported = db.resolve_artifact(&chksum).await?.is_some();
+
+            let r = db::Ref {
+                chksum: chksum.clone(),
+                vendor: vendor.to_string(),
+                package: package.to_string(),
+                version: version.to_string(),
+                filename: Some(upstream_url.unwrap_or(&lookaside_url).to_string()),
+                role: source.map(|source| source.kind.as_str().to_string()),
+                repo: None,
+                arch: None,
+            };
+            info!("insert: {r:?}");
+            db.insert_ref(&r).await?;
+
+            if already_imported || !utils::is_possible_tar_artifact(&lookaside_url) {
+                continue;
+            }
+
+            // the lookaside cache is content-addressed, prefer it over upstream
+            let mirrors = upstream_url.map(String::from).into_iter().collect();
+            info!("Adding download task: url={lookaside_url:?} mirrors={mirrors:?}");
+            db.insert_task(&Task::new(
+                format!("fetch:{lookaside_url}"),
+                &TaskData::FetchTar {
+                    url: lookaside_url,
+                    mirrors,
+                    aliases: vec![chksum],
+                    compression: None,
+                    success_ref: None,
+                    sha256: None,
+                    signature: None,
+                },
+            )?)
+            .await?;
+        }
+    }
+
+    if args.fetch {
+        http.mark_processed(&args.file).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        let data = "SHA512 (git-2.45.2.tar.xz) = 3f9a0c8e5d6e0b2b8d1b2e8b5bcb1d4b1f4ae5a2c1c7a8f0f4b5e2f7e3c9d5a1b3e7f0c2d4a6b8e0f1a3c5e7b9d0f2a4c6e8b1d3f5a7c9e0b2d4f6a8c1e3b5d7
+SHA512 (git-2.45.2.tar.sign) = 5d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c592
+d41d8cd98f00b204e9800998ecf8427e  legacy-1.0.tar.gz
+";
+        let sources = parse_sources(data);
+        assert_eq!(
+            sources,
+            vec![
+                SourcesEntry {
+                    algo: "sha512".to_string(),
+                    filename: "git-2.45.2.tar.xz".to_string(),
+                    hash: "3f9a0c8e5d6e0b2b8d1b2e8b5bcb1d4b1f4ae5a2c1c7a8f0f4b5e2f7e3c9d5a1b3e7f0c2d4a6b8e0f1a3c5e7b9d0f2a4c6e8b1d3f5a7c9e0b2d4f6a8c1e3b5d7".to_string(),
+                },
+                SourcesEntry {
+                    algo: "sha512".to_string(),
+                    filename: "git-2.45.2.tar.sign".to_string(),
+                    hash: "5d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c592".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            sources[1].lookaside_url("https://src.fedoraproject.org/repo/pkgs/rpms/", "git"),
+            "https://src.fedoraproject.org/repo/pkgs/rpms/git/git-2.45.2.tar.sign/sha512/5d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c5925d41402abc4b2a76b9719d911017c592/git-2.45.2.tar.sign"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_tarball() {
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+        for (path, data) in [
+            (
+                "rpms/hello/sources",
+                "SHA512 (hello-2.12.tar.gz) = 5d41402abc4b2a76\n",
+            ),
+            (
+                "rpms/hello/hello.spec",
+                "Name: hello\nVersion: 2.12\nRelease: 1%{?dist}\nURL: https://www.gnu.org/software/hello/\nSource0: https://ftp.gnu.org/gnu/hello/hello-%{version}.tar.gz\n",
+            ),
+            ("rpms/hello/README.md", "hello\n"),
+        ] {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, data.as_bytes())
+                .await
+                .unwrap();
+        }
+        let tar = builder.into_inner().await.unwrap();
+
+        let mut index = BTreeMap::new();
+        let macros = HashMap::from([("dist".to_string(), ".fc41".to_string())]);
+        read_tarball(&tar[..], &mut index, &macros).await.unwrap();
+        let pkg = &index["hello"];
+        assert_eq!(pkg.sources.len(), 1);
+        let spec = pkg.spec.as_ref().unwrap();
+        assert_eq!(spec.version(), Some("2.12"));
+        assert_eq!(spec.release(), Some("1.fc41"));
+        assert_eq!(
+            spec.find_source(&pkg.sources[0].filename)
+                .and_then(|s| s.url()),
+            Some("https://ftp.gnu.org/gnu/hello/hello-2.12.tar.gz")
+        );
+    }
+}
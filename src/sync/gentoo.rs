@@ -5,6 +5,7 @@ use crate::utils;
 use async_compression::tokio::bufread::GzipDecoder;
 use futures::StreamExt;
 use std::collections::BTreeMap;
+use std::path::Path;
 use tokio::io::{self, AsyncBufReadExt, AsyncReadExt};
 use tokio_tar::{Archive, EntryType};
 
@@ -19,6 +20,14 @@ pub struct ManifestEntry {
 pub struct Package {
     pub artifacts: BTreeMap<String, ManifestEntry>,
     pub metadata: BTreeMap<String, Metadata>,
+    pub git: BTreeMap<String, GitSource>,
+}
+
+/// Distfiles that bundle the dependencies of a package, e.g. `foo-1.0-crates.tar.xz`
+pub const VENDORED_DEPS_ROLE: &str = "vendored-deps";
+
+pub fn is_vendored_deps(filename: &str) -> bool {
+    filename.ends_with("-crates.tar.xz") || filename.ends_with("-deps.tar.xz")
 }
 
 fn parse_manifest_entry(line: &str) -> Result<(String, ManifestEntry)> {
@@ -99,6 +108,140 @@ fn parse_metadata(data: &str) -> Result<Metadata> {
     Ok(metadata)
 }
 
+/// The `profiles/thirdpartymirrors` file, mapping `mirror://<name>/` to real urls
+#[derive(Debug, PartialEq, Default)]
+pub struct ThirdPartyMirrors {
+    pub mirrors: BTreeMap<String, Vec<String>>,
+}
+
+impl ThirdPartyMirrors {
+    pub fn parse(data: &str) -> Self {
+        let mut mirrors = BTreeMap::new();
+        for line in data.lines() {
+            let line = line.split_once('#').map(|(line, _)| line).unwrap_or(line);
+            let mut words = line.split_whitespace();
+            let Some(name) = words.next() else {
+                continue;
+            };
+            let urls = words
+                .map(|url| url.trim_end_matches('/').to_string())
+                .collect::<Vec<_>>();
+            if !urls.is_empty() {
+                mirrors.insert(name.to_string(), urls);
+            }
+        }
+        ThirdPartyMirrors { mirrors }
+    }
+
+    /// Resolve a `SRC_URI` entry into the urls to try, in order of preference
+    pub fn expand(&self, url: &str) -> Vec<String> {
+        if let Some(path) = url.strip_prefix("mirror://") {
+            let Some((name, path)) = path.split_once('/') else {
+                return vec![];
+            };
+            let Some(mirrors) = self.mirrors.get(name) else {
+                return vec![];
+            };
+            mirrors
+                .iter()
+                .map(|mirror| format!("{mirror}/{path}"))
+                .collect()
+        } else {
+            match url.split_once("://") {
+                Some(("http", _)) | Some(("https", _)) => vec![url.to_string()],
+                _ => vec![],
+            }
+        }
+    }
+}
+
+/// A git-r3 ebuild that is pinned to a specific commit
+#[derive(Debug, PartialEq)]
+pub struct GitSource {
+    pub repo: String,
+    pub commit: String,
+}
+
+impl GitSource {
+    pub fn url(&self) -> String {
+        format!("git+{}#commit={}", self.repo, self.commit)
+    }
+
+    pub fn chksum(&self) -> String {
+        format!("git:{}", self.commit)
+    }
+}
+
+fn expand_variables(value: &str, vars: &BTreeMap<String, String>) -> Option<String> {
+    let mut out = String::new();
+    let mut remaining = value;
+    while let Some((before, after)) = remaining.split_once("${") {
+        out.push_str(before);
+        let (name, after) = after.split_once('}')?;
+        out.push_str(vars.get(name)?);
+        remaining = after;
+    }
+    out.push_str(remaining);
+    if out.contains('$') {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+/// Extract `EGIT_REPO_URI` and `EGIT_COMMIT` from an ebuild
+///
+/// Live ebuilds without a pinned commit are ignored.
+pub fn parse_ebuild_git(data: &str, pkgname: &str, version: &str) -> Option<GitSource> {
+    let (pv, pr) = match version.rsplit_once('-') {
+        Some((pv, pr)) if pr.starts_with('r') => (pv, pr),
+        _ => (version, "r0"),
+    };
+
+    let mut vars = BTreeMap::new();
+    vars.insert("PN".to_string(), pkgname.to_string());
+    vars.insert("PV".to_string(), pv.to_string());
+    vars.insert("PR".to_string(), pr.to_string());
+    vars.insert("PVR".to_string(), version.to_string());
+    vars.insert("P".to_string(), format!("{pkgname}-{pv}"));
+    vars.insert("PF".to_string(), format!("{pkgname}-{version}"));
+
+    for line in data.lines() {
+        let Some((name, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+        {
+            continue;
+        }
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+            .unwrap_or(value);
+        if let Some(value) = expand_variables(value, &vars) {
+            vars.insert(name.to_string(), value);
+        }
+    }
+
+    let repo = vars
+        .get("EGIT_REPO_URI")?
+        .split_whitespace()
+        .find(|url| url.starts_with("https://"))?;
+    let commit = vars.get("EGIT_COMMIT")?;
+    if commit.len() != 40 || !commit.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some(GitSource {
+        repo: repo.to_string(),
+        commit: commit.to_ascii_lowercase(),
+    })
+}
+
 pub fn parse_pkgname_version(filename: &str) -> Result<(&str, &str)> {
     let Some(idx) = filename.rfind('-') else {
         return Err(Error::InvalidData);
@@ -122,12 +265,18 @@ pub async fn run(args: &args::SyncGentoo) -> Result<()> {
     let db = db::Client::create().await?;
     let vendor = &args.vendor;
 
-    let reader = utils::fetch_or_open(&args.file, args.fetch).await?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+    let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+        return Ok(());
+    };
     let reader = io::BufReader::new(reader);
     let reader = GzipDecoder::new(reader);
     let mut tar = Archive::new(reader);
 
     let mut index = BTreeMap::<_, Package>::new();
+    let mut thirdpartymirrors = ThirdPartyMirrors::default();
 
     let mut entries = tar.entries()?;
     while let Some(entry) = entries.next().await {
@@ -153,7 +302,15 @@ pub async fn run(args: &args::SyncGentoo) -> Result<()> {
 
         debug!("Found file in git: parent={parent:?} file={filename:?}");
 
-        if filename == "Manifest" {
+        if filename == "thirdpartymirrors" && parent == Path::new("profiles") {
+            let mut buf = String::new();
+            entry.read_to_string(&mut buf).await?;
+            thirdpartymirrors = ThirdPartyMirrors::parse(&buf);
+            debug!(
+                "Found {} third-party mirrors",
+                thirdpartymirrors.mirrors.len()
+            );
+        } else if filename == "Manifest" {
             let Some(parent) = parent.to_str() else {
                 continue;
             };
@@ -184,6 +341,28 @@ pub async fn run(args: &args::SyncGentoo) -> Result<()> {
 
             let metadata = parse_metadata(&buf)?;
             pkg.metadata.insert(version.to_string(), metadata);
+        } else if let Some(stem) = filename.strip_suffix(".ebuild") {
+            let Some(parent) = parent.to_str() else {
+                continue;
+            };
+            let Ok((pkgname, version)) = parse_pkgname_version(stem) else {
+                continue;
+            };
+
+            let mut buf = String::new();
+            entry.read_to_string(&mut buf).await?;
+            if !buf.contains("EGIT_COMMIT") {
+                continue;
+            }
+
+            if let Some(git) = parse_ebuild_git(&buf, pkgname, version) {
+                debug!("Found pinned git source: {parent:?} version={version:?} {git:?}");
+                index
+                    .entry(parent.to_owned())
+                    .or_default()
+                    .git
+                    .insert(version.to_string(), git);
+            }
         }
     }
 
@@ -196,13 +375,12 @@ pub async fn run(args: &args::SyncGentoo) -> Result<()> {
             );
 
             for (filename, url) in &metadata.inputs {
-                match url.split_once("://") {
-                    Some(("http", _)) => (),
-                    Some(("https", _)) => (),
-                    _ => continue,
-                }
+                let mut urls = thirdpartymirrors.expand(url).into_iter();
+                let Some(primary) = urls.next() else {
+                    continue;
+                };
 
-                if !utils::is_possible_tar_artifact(url) {
+                if !utils::is_possible_tar_artifact(&primary) {
                     continue;
                 }
 
@@ -223,6 +401,9 @@ pub async fn run(args: &args::SyncGentoo) -> Result<()> {
                     package: pkg.to_string(),
                     version: version.to_string(),
                     filename: Some(url.to_string()),
+                    role: is_vendored_deps(filename).then(|| VENDORED_DEPS_ROLE.to_string()),
+                    repo: None,
+                    arch: None,
                 };
                 info!("insert: {r:?}");
                 db.insert_ref(&r).await?;
@@ -231,20 +412,54 @@ pub async fn run(args: &args::SyncGentoo) -> Result<()> {
                     continue;
                 };
 
-                info!("Adding download task: url={url:?}");
+                let mirrors = urls.collect::<Vec<_>>();
+                info!("Adding download task: url={primary:?} mirrors={mirrors:?}");
                 db.insert_task(&Task::new(
-                    format!("fetch:{url}"),
+                    format!("fetch:{primary}"),
                     &TaskData::FetchTar {
-                        url: url.to_string(),
+                        url: primary,
+                        mirrors,
+                        aliases: Vec::new(),
                         compression: None,
                         success_ref: None,
+                        sha256: None,
+                        signature: None,
                     },
                 )?)
                 .await?;
             }
         }
+
+        for (version, git) in data.git {
+            let chksum = git.chksum();
+            let url = git.url();
+
+            if db.resolve_artifact(&chksum).await?.is_none() {
+                info!("Adding git snapshot task: url={url:?}");
+                db.insert_task(&Task::new(
+                    format!("git-clone:{url}"),
+                    &TaskData::GitSnapshot { url: url.clone() },
+                )?)
+                .await?;
+            }
+
+            let r = db::Ref {
+                chksum,
+                vendor: vendor.to_string(),
+                package: pkg.to_string(),
+                version,
+                filename: Some(url),
+                role: None,
+                repo: None,
+                arch: None,
+            };
+            info!("insert: {r:?}");
+            db.insert_ref(&r).await?;
+        }
     }
 
+    http.mark_processed(&args.file).await?;
+
     Ok(())
 }
 
@@ -391,6 +606,96 @@ _md5_=b977a58ded16b7cd6e10cde4e52ed083
         );
     }
 
+    #[test]
+    fn test_thirdpartymirrors() {
+        let data = "# Gentoo third-party mirrors
+gnu		https://ftpmirror.gnu.org/gnu https://ftp.gnu.org/gnu/
+sourceforge	https://downloads.sourceforge.net
+";
+        let mirrors = ThirdPartyMirrors::parse(data);
+        assert_eq!(mirrors.mirrors.len(), 2);
+        assert_eq!(
+            mirrors.expand("mirror://gnu/hello/hello-2.12.1.tar.gz"),
+            vec![
+                "https://ftpmirror.gnu.org/gnu/hello/hello-2.12.1.tar.gz".to_string(),
+                "https://ftp.gnu.org/gnu/hello/hello-2.12.1.tar.gz".to_string(),
+            ]
+        );
+        assert_eq!(
+            mirrors.expand("mirror://sourceforge/expat/expat-2.6.2.tar.xz"),
+            vec!["https://downloads.sourceforge.net/expat/expat-2.6.2.tar.xz".to_string()]
+        );
+        assert_eq!(
+            mirrors.expand("mirror://unknown/foo-1.0.tar.gz"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            mirrors.expand("https://example.com/foo-1.0.tar.gz"),
+            vec!["https://example.com/foo-1.0.tar.gz".to_string()]
+        );
+        assert_eq!(
+            mirrors.expand("ftp://example.com/foo-1.0.tar.gz"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_parse_ebuild_git() {
+        let data = r#"# Copyright 2024 Gentoo Authors
+EAPI=8
+
+EGIT_REPO_URI="https://github.com/example/${PN}.git"
+EGIT_COMMIT="4B825DC642CB6EB9A060E54BF8D69288FBEE4904"
+inherit git-r3 go-module
+
+SRC_URI="https://dev.gentoo.org/~dev/distfiles/${P}-deps.tar.xz"
+"#;
+        let git = parse_ebuild_git(data, "tool", "0_pre20240101-r1").unwrap();
+        assert_eq!(
+            git,
+            GitSource {
+                repo: "https://github.com/example/tool.git".to_string(),
+                commit: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string(),
+            }
+        );
+        assert_eq!(
+            git.url(),
+            "git+https://github.com/example/tool.git#commit=4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        );
+        assert_eq!(git.chksum(), "git:4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+    }
+
+    #[test]
+    fn test_parse_ebuild_git_live() {
+        let data = r#"EAPI=8
+if [[ ${PV} == 9999 ]]; then
+	EGIT_REPO_URI="https://github.com/example/${PN}.git"
+	inherit git-r3
+fi
+"#;
+        assert_eq!(parse_ebuild_git(data, "tool", "9999"), None);
+    }
+
+    #[test]
+    fn test_parse_ebuild_git_commit_variable() {
+        let data = r#"EAPI=8
+MY_COMMIT="0123456789abcdef0123456789abcdef01234567"
+EGIT_COMMIT="${MY_COMMIT}"
+EGIT_REPO_URI="git://example.com/tool.git https://example.com/tool.git"
+"#;
+        let git = parse_ebuild_git(data, "tool", "1.0").unwrap();
+        assert_eq!(git.repo, "https://example.com/tool.git");
+        assert_eq!(git.commit, "0123456789abcdef0123456789abcdef01234567");
+    }
+
+    #[test]
+    fn test_is_vendored_deps() {
+        assert!(is_vendored_deps("ripgrep-14.1.0-crates.tar.xz"));
+        assert!(is_vendored_deps("gh-2.49.0-deps.tar.xz"));
+        assert!(!is_vendored_deps("ripgrep-14.1.0.tar.gz"));
+        assert!(!is_vendored_deps("aho-corasick-1.1.2.crate"));
+    }
+
     #[test]
     fn test_parse_pkgname_version() {
         let split = parse_pkgname_version("apparmor-3.0.10").unwrap();
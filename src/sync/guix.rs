@@ -65,12 +65,16 @@ pub struct GitSource {
 
 #[derive(Debug, PartialEq, Deserialize)]
 pub struct HgSource {
+    pub hg_url: String,
+    pub hg_changeset: String,
     #[serde(flatten)]
     pub integrity: Integrity,
 }
 
 #[derive(Debug, PartialEq, Deserialize)]
 pub struct SvnSource {
+    pub svn_url: String,
+    pub svn_revision: u64,
     #[serde(flatten)]
     pub integrity: Integrity,
 }
@@ -78,7 +82,12 @@ pub struct SvnSource {
 pub async fn run(args: &args::SyncGuix) -> Result<()> {
     let db = db::Client::create().await?;
 
-    let reader = utils::fetch_or_open(&args.file, args.fetch).await?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+    let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+        return Ok(());
+    };
     let reader = io::BufReader::new(reader);
     let mut reader = GzipDecoder::new(reader);
 
@@ -93,6 +102,69 @@ pub async fn run(args: &args::SyncGuix) -> Result<()> {
                 continue;
             };
 
+            // the nar hash of a vcs checkout can't be matched, use the revision instead
+            let vcs = match &source {
+                Source::Git(source) => {
+                    let is_commit = source.git_ref.len() == 40
+                        && source.git_ref.bytes().all(|b| b.is_ascii_hexdigit());
+                    let scheme = if source.submodule { "gitsm" } else { "git" };
+                    let (url, chksum) = if is_commit {
+                        (
+                            format!("{scheme}+{}#commit={}", source.git_url, source.git_ref),
+                            Some(format!("{scheme}:{}", source.git_ref)),
+                        )
+                    } else {
+                        (
+                            format!("{scheme}+{}#tag={}", source.git_url, source.git_ref),
+                            None,
+                        )
+                    };
+                    Some((url, chksum))
+                }
+                Source::Hg(source) => {
+                    let url = format!("hg+{}#revision={}", source.hg_url, source.hg_changeset);
+                    let is_node = source.hg_changeset.len() == 40
+                        && source.hg_changeset.bytes().all(|b| b.is_ascii_hexdigit());
+                    let chksum = is_node.then(|| format!("hg:{}", source.hg_changeset));
+                    Some((url, chksum))
+                }
+                Source::Svn(source) => {
+                    let url = format!("svn+{}#revision={}", source.svn_url, source.svn_revision);
+                    let chksum = format!("svn:{}@{}", source.svn_url, source.svn_revision);
+                    Some((url, Some(chksum)))
+                }
+                _ => None,
+            };
+            // TODO: sources pinned by tag don't get a ref, the commit is only known after the
+            // snapshot task resolved the tag (see `git_tags`)
+            if let Some((url, chksum)) = vcs {
+                debug!("chksum={chksum:?} url={url:?}");
+                if let Some(chksum) = &chksum {
+                    let obj = db::Ref {
+                        chksum: chksum.to_string(),
+                        vendor: args.vendor.to_string(),
+                        package: package.name.to_string(),
+                        version: package.version.to_string(),
+                        filename: Some(url.to_string()),
+                        role: None,
+                        repo: None,
+                        arch: None,
+                    };
+                    info!("insert: {obj:?}");
+                    db.insert_ref(&obj).await?;
+
+                    if db.resolve_artifact(chksum).await?.is_some() {
+                        continue;
+                    }
+                }
+
+                if let Some(task) = utils::task_for_url(&url) {
+                    info!("Adding snapshot task: url={url:?}");
+                    db.insert_task(&task).await?;
+                }
+                continue;
+            }
+
             let integrity = source.integrity();
             if integrity.output_hash_mode != "flat" {
                 continue;
@@ -105,7 +177,7 @@ pub async fn run(args: &args::SyncGuix) -> Result<()> {
             let chksum = format!("sha256:{digest}");
 
             if let Source::Url(source) = &source {
-                let Some(url) = source.urls.first() else {
+                let Some((url, mirrors)) = source.urls.split_first() else {
                     continue;
                 };
                 debug!("chksum={chksum:?} url={url:?}");
@@ -120,6 +192,9 @@ pub async fn run(args: &args::SyncGuix) -> Result<()> {
                     package: package.name.to_string(),
                     version: package.version.to_string(),
                     filename: Some(url.to_string()),
+                    role: None,
+                    repo: None,
+                    arch: None,
                 };
                 info!("insert: {obj:?}");
                 db.insert_ref(&obj).await?;
@@ -130,8 +205,12 @@ pub async fn run(args: &args::SyncGuix) -> Result<()> {
                         format!("fetch:{url}"),
                         &TaskData::FetchTar {
                             url: url.to_string(),
+                            mirrors: mirrors.to_vec(),
+                            aliases: Vec::new(),
                             compression: None,
                             success_ref: None,
+                            sha256: None,
+                            signature: None,
                         },
                     )?)
                     .await?;
@@ -140,6 +219,8 @@ pub async fn run(args: &args::SyncGuix) -> Result<()> {
         }
     }
 
+    http.mark_processed(&args.file).await?;
+
     Ok(())
 }
 
@@ -198,6 +279,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_vcs_sources() {
+        let data = r#"
+[
+  {
+    "type": "hg",
+    "hg_url": "https://hg.sr.ht/~olly/yoyo-migrations",
+    "integrity": "sha256-L0HXa+PmxJj0uS/wD1YZIy5Gm0Ad2SHZ3tqZ/7EzmVA=",
+    "outputHashAlgo": "sha256",
+    "outputHashMode": "recursive",
+    "hg_changeset": "v8.2.0"
+  },
+  {
+    "type": "svn",
+    "svn_url": "svn://svn.code.sf.net/p/xmlstar/code/trunk",
+    "integrity": "sha256-lUFPRMYHF6P6JkGTqxmpG/xgfFpbpLROq4+j8Rv7/NU=",
+    "outputHashAlgo": "sha256",
+    "outputHashMode": "recursive",
+    "svn_revision": 120
+  }
+]
+"#;
+        let source = serde_json::from_str::<Vec<Source>>(data).unwrap();
+        assert_eq!(
+            source,
+            vec![
+                Source::Hg(HgSource {
+                    hg_url: "https://hg.sr.ht/~olly/yoyo-migrations".to_string(),
+                    hg_changeset: "v8.2.0".to_string(),
+                    integrity: Integrity {
+                        hash: "sha256-L0HXa+PmxJj0uS/wD1YZIy5Gm0Ad2SHZ3tqZ/7EzmVA=".to_string(),
+                        output_hash_algo: "sha256".to_string(),
+                        output_hash_mode: "recursive".to_string(),
+                    },
+                }),
+                Source::Svn(SvnSource {
+                    svn_url: "svn://svn.code.sf.net/p/xmlstar/code/trunk".to_string(),
+                    svn_revision: 120,
+                    integrity: Integrity {
+                        hash: "sha256-lUFPRMYHF6P6JkGTqxmpG/xgfFpbpLROq4+j8Rv7/NU=".to_string(),
+                        output_hash_algo: "sha256".to_string(),
+                        output_hash_mode: "recursive".to_string(),
+                    },
+                }),
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_url_source() {
         let data = r#"
@@ -35,7 +35,12 @@ pub async fn run(args: &args::SyncHomebrew) -> Result<()> {
     let db = db::Client::create().await?;
     let vendor = &args.vendor;
 
-    let mut reader = utils::fetch_or_open(&args.file, args.fetch).await?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+    let Some(mut reader) = http.fetch_index(&args.file, args.fetch).await? else {
+        return Ok(());
+    };
 
     let mut buf = String::new();
     reader.read_to_string(&mut buf).await?;
@@ -65,8 +70,12 @@ pub async fn run(args: &args::SyncHomebrew) -> Result<()> {
                     format!("fetch:{url}"),
                     &TaskData::FetchTar {
                         url: url.to_string(),
+                        mirrors: Vec::new(),
+                        aliases: Vec::new(),
                         compression: None,
                         success_ref: None,
+                        sha256: None,
+                        signature: None,
                     },
                 )?)
                 .await?;
@@ -89,9 +98,14 @@ pub async fn run(args: &args::SyncHomebrew) -> Result<()> {
             package,
             version,
             filename: Some(url),
+            role: None,
+            repo: None,
+            arch: None,
         };
         db.insert_ref(&obj).await?;
     }
 
+    http.mark_processed(&args.file).await?;
+
     Ok(())
 }
@@ -1,5 +1,7 @@
 pub mod alpine;
 pub mod apt;
+pub mod aur;
+pub mod distgit;
 pub mod gentoo;
 pub mod guix;
 pub mod homebrew;
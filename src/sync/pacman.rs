@@ -24,7 +24,12 @@ pub async fn run(args: &args::SyncPacman) -> Result<()> {
     let db = db::Client::create().await?;
     let vendor = &args.vendor;
 
-    let reader = utils::fetch_or_open(&args.file, args.fetch).await?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+    let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+        return Ok(());
+    };
     let reader = io::BufReader::new(reader);
     let reader = GzipDecoder::new(reader);
     let mut tar = Archive::new(reader);
@@ -77,5 +82,7 @@ pub async fn run(args: &args::SyncPacman) -> Result<()> {
         .await?;
     }
 
+    http.mark_processed(&args.file).await?;
+
     Ok(())
 }
@@ -1,10 +1,12 @@
 use crate::args;
+use crate::chksums::{self, Hasher};
+use crate::compression::Decompressor;
 use crate::db;
 use crate::errors::*;
 use crate::utils;
-use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
 use serde::Deserialize;
-use tokio::io::{self, AsyncRead, AsyncReadExt};
+use std::cmp::Reverse;
+use tokio::io::{self, AsyncReadExt};
 
 #[derive(Debug, PartialEq, Deserialize)]
 pub struct Metadata {
@@ -48,16 +50,11 @@ impl RepoMd {
         Ok(xml)
     }
 
-    pub fn find_primary_location(&self) -> Result<&str> {
-        let href = self
-            .data
+    pub fn find_primary(&self) -> Result<&Data> {
+        self.data
             .iter()
             .find(|e| e.data_type == "primary")
-            .ok_or(Error::RpmMissingPrimary)?
-            .location
-            .href
-            .as_str();
-        Ok(href)
+            .ok_or(Error::RpmMissingPrimary)
     }
 }
 
@@ -65,65 +62,372 @@ impl RepoMd {
 pub struct Data {
     #[serde(rename = "type")]
     data_type: String,
+    checksum: Option<Checksum>,
     location: Location,
 }
 
-pub async fn run(args: &args::SyncRpm) -> Result<()> {
-    let db = db::Client::create().await?;
-    let base_url = args.url.strip_suffix('/').unwrap_or(&args.url);
-    let vendor = &args.vendor;
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Checksum {
+    #[serde(rename = "type")]
+    algo: String,
+    #[serde(rename = "$value")]
+    value: String,
+}
 
-    let http = utils::http_client(None)?;
+impl Checksum {
+    /// The checksum in the format used by `chksums::Checksums`, if it's an algorithm we compute
+    pub fn to_chksum(&self) -> Option<String> {
+        match self.algo.as_str() {
+            "sha256" | "sha512" => Some(format!("{}:{}", self.algo, self.value.trim())),
+            _ => None,
+        }
+    }
+}
 
-    let url = format!("{base_url}/repodata/repomd.xml");
-    info!("Downloading url: {url:?}");
-    let mut reader = http.fetch(&url).await?;
+/// A repository to sync, optionally prefixed with a label e.g. `updates=https://...`
+#[derive(Debug, PartialEq)]
+pub struct RepoArg<'a> {
+    pub label: Option<&'a str>,
+    pub source: RepoSource,
+    pub url: &'a str,
+}
 
-    let mut text = String::new();
-    reader.read_to_string(&mut text).await?;
+impl<'a> RepoArg<'a> {
+    pub fn parse(arg: &'a str) -> Self {
+        let (source, url) = RepoSource::split_prefix(arg);
+        if source != RepoSource::BaseUrl {
+            return RepoArg {
+                label: None,
+                source,
+                url,
+            };
+        }
+        if let Some((label, rest)) = arg.split_once('=') {
+            // metalink and mirrorlist urls contain `=` in their query string
+            if !label.contains('/') && !label.contains(':') && !label.is_empty() {
+                let (source, url) = RepoSource::split_prefix(rest);
+                return RepoArg {
+                    label: Some(label),
+                    source,
+                    url,
+                };
+            }
+        }
+        RepoArg {
+            label: None,
+            source,
+            url: arg,
+        }
+    }
+}
 
-    let repomd = RepoMd::from_xml(&text)?;
-    let url = format!("{base_url}/{}", repomd.find_primary_location()?);
+#[derive(Debug, PartialEq)]
+pub enum RepoSource {
+    BaseUrl,
+    Metalink,
+    Mirrorlist,
+}
+
+impl RepoSource {
+    /// Split off the `metalink=` or `mirrorlist=` prefix, urls without one are base urls
+    pub fn split_prefix(arg: &str) -> (Self, &str) {
+        if let Some(url) = arg.strip_prefix("metalink=") {
+            (RepoSource::Metalink, url)
+        } else if let Some(url) = arg.strip_prefix("mirrorlist=") {
+            (RepoSource::Mirrorlist, url)
+        } else {
+            (RepoSource::BaseUrl, arg)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Metalink {
+    files: MetalinkFiles,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct MetalinkFiles {
+    #[serde(rename = "file", default)]
+    files: Vec<MetalinkFile>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct MetalinkFile {
+    name: String,
+    verification: Option<Verification>,
+    /// Previous versions that are still considered recent enough
+    alternates: Option<Alternates>,
+    resources: Resources,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Verification {
+    #[serde(rename = "hash", default)]
+    hashes: Vec<Checksum>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Alternates {
+    #[serde(rename = "alternate", default)]
+    alternates: Vec<Alternate>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Alternate {
+    verification: Option<Verification>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Resources {
+    #[serde(rename = "url", default)]
+    urls: Vec<MetalinkUrl>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct MetalinkUrl {
+    preference: Option<u32>,
+    #[serde(rename = "$value")]
+    url: String,
+}
+
+impl Metalink {
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        let xml = serde_xml_rs::from_str(xml)?;
+        Ok(xml)
+    }
+}
+
+/// The repository base urls of a mirror source, ordered by preference
+#[derive(Debug, PartialEq, Default)]
+pub struct Mirrors {
+    pub base_urls: Vec<String>,
+    /// Checksums a mirror's repomd.xml may have, empty if not known
+    pub repomd_chksums: Vec<String>,
+}
+
+/// Extract the repository base urls and the expected repomd.xml checksums from a metalink
+pub fn parse_metalink(xml: &str) -> Result<Mirrors> {
+    let metalink = Metalink::from_xml(xml)?;
+    let mut mirrors = Mirrors::default();
+    let Some(file) = metalink
+        .files
+        .files
+        .into_iter()
+        .find(|file| file.name == "repomd.xml")
+    else {
+        return Ok(mirrors);
+    };
+
+    let mut urls = Vec::new();
+    for url in file.resources.urls {
+        let preference = url.preference.unwrap_or(0);
+        let url = url.url.trim();
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            continue;
+        }
+        let Some(base) = url.strip_suffix("/repodata/repomd.xml") else {
+            continue;
+        };
+        urls.push((preference, url.starts_with("https://"), base.to_string()));
+    }
+    // prefer https, then by preference, stable otherwise
+    urls.sort_by_key(|(preference, https, _)| Reverse((*https, *preference)));
+    mirrors.base_urls = urls.into_iter().map(|(_, _, url)| url).collect();
+
+    let alternates = file.alternates.into_iter().flat_map(|a| a.alternates);
+    let verifications = file
+        .verification
+        .into_iter()
+        .chain(alternates.flat_map(|a| a.verification));
+    mirrors.repomd_chksums = verifications
+        .flat_map(|v| v.hashes)
+        .filter(|hash| hash.algo == "sha256")
+        .filter_map(|hash| hash.to_chksum())
+        .collect();
+
+    Ok(mirrors)
+}
+
+/// Extract the repository base urls from a mirrorlist
+pub fn parse_mirrorlist(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("https://") || line.starts_with("http://"))
+        .map(|line| line.strip_suffix('/').unwrap_or(line).to_string())
+        .collect()
+}
+
+async fn resolve_mirrors(http: &utils::HttpClient, repo: &RepoArg<'_>) -> Result<Mirrors> {
+    let RepoArg { source, url, .. } = repo;
+    if *source == RepoSource::BaseUrl {
+        return Ok(Mirrors {
+            base_urls: vec![url.strip_suffix('/').unwrap_or(url).to_string()],
+            repomd_chksums: Vec::new(),
+        });
+    }
 
+    info!("Downloading {source:?}: {url:?}");
+    let mut text = String::new();
+    http.fetch(url).await?.read_to_string(&mut text).await?;
+    let mirrors = match source {
+        RepoSource::Metalink => parse_metalink(&text)?,
+        _ => Mirrors {
+            base_urls: parse_mirrorlist(&text),
+            repomd_chksums: Vec::new(),
+        },
+    };
+    debug!("Found mirrors: {mirrors:?}");
+    Ok(mirrors)
+}
+
+/// Download the primary index and verify it matches the checksum in repomd.xml
+async fn fetch_primary(http: &utils::HttpClient, url: &str, data: &Data) -> Result<Metadata> {
     info!("Downloading url: {url:?}");
-    let reader = http.fetch(&url).await?;
-    let reader = io::BufReader::new(reader);
-    let mut reader: Box<dyn AsyncRead + Unpin> = if url.ends_with(".zst") {
-        Box::new(ZstdDecoder::new(reader))
+    let reader = http.fetch(url).await?;
+    let reader = io::BufReader::new(Hasher::new(reader));
+    let mut reader = if url.ends_with(".zst") {
+        Decompressor::zstd(reader)
     } else {
-        Box::new(GzipDecoder::new(reader))
+        Decompressor::gz(reader)
     };
 
     let mut buf = String::new();
     reader.read_to_string(&mut buf).await?;
 
+    let mut reader = reader.into_inner();
+    io::copy(&mut reader, &mut io::sink()).await?;
+    let (_, digests) = reader.into_inner().digests();
+
+    match data.checksum.as_ref().map(|c| (c, c.to_chksum())) {
+        Some((_, Some(expected))) => {
+            if expected != digests.sha256 && expected != digests.sha512 {
+                return Err(Error::ChecksumMismatch(expected));
+            }
+            debug!("Primary index matches checksum: {expected:?}");
+        }
+        Some((checksum, None)) => {
+            warn!(
+                "Unsupported checksum algorithm in repomd.xml, not verifying: {:?}",
+                checksum.algo
+            );
+        }
+        None => warn!("Missing checksum for primary index in repomd.xml, not verifying"),
+    }
+
     info!("Processing xml");
-    let md = Metadata::from_xml(&buf)?;
-    for pkg in md.packages {
-        let package = pkg.name;
-        let version = format!("{}-{}", pkg.version.ver, pkg.version.rel);
+    Metadata::from_xml(&buf)
+}
 
-        // mark all refs known for this package as "last_seen now"
-        db.bump_named_refs(vendor, &package, &version).await?;
+/// Reject repository metadata of mirrors that are out of date
+fn verify_repomd(text: &str, repomd_chksums: &[String]) -> Result<()> {
+    if repomd_chksums.is_empty() {
+        return Ok(());
+    }
+    let chksum = chksums::sha256(text.as_bytes());
+    if !repomd_chksums.contains(&chksum) {
+        debug!("Repository metadata does not match metalink: {chksum:?}");
+        return Err(Error::ChecksumMismatch(repomd_chksums[0].clone()));
+    }
+    debug!("Repository metadata matches metalink: {chksum:?}");
+    Ok(())
+}
 
-        if db.get_package(vendor, &package, &version).await?.is_some() {
-            debug!("Package is already imported: vendor={vendor:?} package={package:?} version={version:?}");
-            continue;
+/// Download and verify the repository metadata from a mirror, `None` if unchanged since last sync
+async fn fetch_repo(
+    http: &utils::HttpClient,
+    base_url: &str,
+    repomd_chksums: &[String],
+) -> Result<Option<(String, Metadata)>> {
+    let repomd_url = format!("{base_url}/repodata/repomd.xml");
+    info!("Downloading url: {repomd_url:?}");
+    let Some(mut reader) = http.fetch_index(&repomd_url, true).await? else {
+        return Ok(None);
+    };
+
+    let mut text = String::new();
+    reader.read_to_string(&mut text).await?;
+
+    verify_repomd(&text, repomd_chksums)?;
+    let repomd = RepoMd::from_xml(&text)?;
+    let primary = repomd.find_primary()?;
+    let url = format!("{base_url}/{}", primary.location.href);
+    let md = fetch_primary(http, &url, primary).await?;
+    Ok(Some((repomd_url, md)))
+}
+
+async fn sync_repo(
+    db: &db::Client,
+    http: &utils::HttpClient,
+    vendor: &str,
+    repo: &RepoArg<'_>,
+) -> Result<()> {
+    let mirrors = resolve_mirrors(http, repo).await?;
+    // packages are recorded under their label, or the url they're synced from
+    let repo_name = repo.label.unwrap_or(repo.url);
+
+    let mut error = None;
+    for base_url in &mirrors.base_urls {
+        let (repomd_url, md) = match fetch_repo(http, base_url, &mirrors.repomd_chksums).await {
+            Ok(Some(fetched)) => fetched,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                warn!("Failed to fetch repository metadata from {base_url:?}: {err:#}");
+                error = Some(err);
+                continue;
+            }
+        };
+
+        for pkg in md.packages {
+            let package = pkg.name;
+            let version = format!("{}-{}", pkg.version.ver, pkg.version.rel);
+
+            // mark all refs known for this package as "last_seen now"
+            db.bump_named_refs(vendor, &package, &version).await?;
+            // the same version may be part of multiple repositories
+            db.insert_package_repo(vendor, &package, &version, repo_name)
+                .await?;
+
+            if db.get_package(vendor, &package, &version).await?.is_some() {
+                debug!("Package is already imported: vendor={vendor:?} package={package:?} version={version:?}");
+                continue;
+            }
+
+            let url = format!("{base_url}/{}", pkg.location.href);
+
+            info!("package={package:?} version={version:?} url={url:?}");
+            db.insert_task(&db::Task::new(
+                format!("source-rpm:{vendor}:{package}:{version}"),
+                &db::TaskData::SourceRpm {
+                    vendor: vendor.to_string(),
+                    package: package.to_string(),
+                    version: version.to_string(),
+                    url,
+                    repo: repo.label.map(String::from),
+                },
+            )?)
+            .await?;
         }
 
-        let url = format!("{base_url}/{}", pkg.location.href);
+        http.mark_processed(&repomd_url).await?;
+        return Ok(());
+    }
 
-        info!("package={package:?} version={version:?} url={url:?}");
-        db.insert_task(&db::Task::new(
-            format!("source-rpm:{vendor}:{package}:{version}"),
-            &db::TaskData::SourceRpm {
-                vendor: vendor.to_string(),
-                package: package.to_string(),
-                version: version.to_string(),
-                url,
-            },
-        )?)
-        .await?;
+    Err(error.unwrap_or(Error::RpmMissingMirrors))
+}
+
+pub async fn run(args: &args::SyncRpm) -> Result<()> {
+    let db = db::Client::create().await?;
+    let vendor = &args.vendor;
+
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+
+    for repo in &args.repos {
+        let repo = RepoArg::parse(repo);
+        info!("Syncing rpm repository: {repo:?}");
+        sync_repo(&db, &http, vendor, &repo).await?;
     }
 
     Ok(())
@@ -260,12 +564,20 @@ hobbyist game developers, since 2001.</description>
                 data: vec![
                     Data {
                         data_type: "primary".to_string(),
+                        checksum: Some(Checksum {
+                            algo: "sha256".to_string(),
+                            value: "fa72c03d43e9ffe131633347045c0c56fbeacbd3281b2b03a6351f487218a158".to_string(),
+                        }),
                         location: Location {
                             href: "repodata/fa72c03d43e9ffe131633347045c0c56fbeacbd3281b2b03a6351f487218a158-primary.xml.gz".to_string()
                         }
                     },
                     Data {
                         data_type: "filelists".to_string(),
+                        checksum: Some(Checksum {
+                            algo: "sha256".to_string(),
+                            value: "caf9e9202dbd97fcf4da6ca3f228fd459505f0b17d37fb387240b03c8dc0e84a".to_string(),
+                        }),
                         location: Location {
                             href: "repodata/caf9e9202dbd97fcf4da6ca3f228fd459505f0b17d37fb387240b03c8dc0e84a-filelists.xml.gz".to_string()
                         }
@@ -274,4 +586,121 @@ hobbyist game developers, since 2001.</description>
             }
         );
     }
+
+    #[test]
+    fn test_parse_repo_arg() {
+        assert_eq!(
+            RepoArg::parse("updates=https://dl.fedoraproject.org/pub/fedora/linux/updates/40/Everything/source/tree/"),
+            RepoArg {
+                label: Some("updates"),
+                source: RepoSource::BaseUrl,
+                url: "https://dl.fedoraproject.org/pub/fedora/linux/updates/40/Everything/source/tree/",
+            }
+        );
+        assert_eq!(
+            RepoArg::parse(
+                "metalink=https://mirrors.fedoraproject.org/metalink?repo=epel-source-9&arch=x86_64"
+            ),
+            RepoArg {
+                label: None,
+                source: RepoSource::Metalink,
+                url: "https://mirrors.fedoraproject.org/metalink?repo=epel-source-9&arch=x86_64",
+            }
+        );
+        assert_eq!(
+            RepoArg::parse(
+                "epel=mirrorlist=https://mirrors.fedoraproject.org/mirrorlist?repo=epel-source-9&arch=x86_64"
+            ),
+            RepoArg {
+                label: Some("epel"),
+                source: RepoSource::Mirrorlist,
+                url: "https://mirrors.fedoraproject.org/mirrorlist?repo=epel-source-9&arch=x86_64",
+            }
+        );
+        // without a prefix this is a base url, even if it looks like a metalink
+        assert_eq!(
+            RepoArg::parse("https://example.com/metalink/repo?a=b"),
+            RepoArg {
+                label: None,
+                source: RepoSource::BaseUrl,
+                url: "https://example.com/metalink/repo?a=b",
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_metalink() {
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/" type="dynamic" pubdate="Mon, 08 Jul 2024 10:13:32 GMT" generator="mirrormanager" xmlns:mm0="http://fedorahosted.org/mirrormanager">
+ <files>
+  <file name="repomd.xml">
+   <mm0:timestamp>1720426125</mm0:timestamp>
+   <size>4918</size>
+   <verification>
+    <hash type="md5">0123456789abcdef0123456789abcdef</hash>
+    <hash type="sha256">8d3e6b4c9ca2b5c4b8f3d6e4a9b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9</hash>
+   </verification>
+   <mm0:alternates>
+    <mm0:alternate>
+     <mm0:timestamp>1720339725</mm0:timestamp>
+     <size>4918</size>
+     <verification>
+      <hash type="sha256">0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c4b5a69788796a5b4c3d2e1f0</hash>
+     </verification>
+    </mm0:alternate>
+   </mm0:alternates>
+   <resources maxconnections="1">
+    <url protocol="rsync" type="rsync" location="DE" preference="100">rsync://ftp.fau.de/epel/9/Everything/source/tree/repodata/repomd.xml</url>
+    <url protocol="http" type="http" location="DE" preference="99">http://ftp.fau.de/epel/9/Everything/source/tree/repodata/repomd.xml</url>
+    <url protocol="https" type="https" location="DE" preference="97">https://mirror.example.org/epel/9/Everything/source/tree/repodata/repomd.xml</url>
+    <url protocol="https" type="https" location="DE" preference="98">https://ftp.fau.de/epel/9/Everything/source/tree/repodata/repomd.xml</url>
+   </resources>
+  </file>
+ </files>
+</metalink>
+"#;
+        assert_eq!(
+            parse_metalink(data).unwrap(),
+            Mirrors {
+                base_urls: vec![
+                    "https://ftp.fau.de/epel/9/Everything/source/tree".to_string(),
+                    "https://mirror.example.org/epel/9/Everything/source/tree".to_string(),
+                    "http://ftp.fau.de/epel/9/Everything/source/tree".to_string(),
+                ],
+                repomd_chksums: vec![
+                    "sha256:8d3e6b4c9ca2b5c4b8f3d6e4a9b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9"
+                        .to_string(),
+                    "sha256:0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c4b5a69788796a5b4c3d2e1f0"
+                        .to_string(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_repomd() {
+        let text = "<repomd></repomd>\n";
+        let chksum = chksums::sha256(text.as_bytes());
+        verify_repomd(text, &[]).unwrap();
+        verify_repomd(text, &["sha256:00".to_string(), chksum]).unwrap();
+        assert!(matches!(
+            verify_repomd(text, &["sha256:00".to_string()]),
+            Err(Error::ChecksumMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_mirrorlist() {
+        let data = "# repo = epel-source-9 arch = x86_64 country = DE
+https://ftp.fau.de/epel/9/Everything/source/tree/
+http://mirror.example.org/epel/9/Everything/source/tree
+";
+        assert_eq!(
+            parse_mirrorlist(data),
+            vec![
+                "https://ftp.fau.de/epel/9/Everything/source/tree".to_string(),
+                "http://mirror.example.org/epel/9/Everything/source/tree".to_string(),
+            ]
+        );
+    }
 }
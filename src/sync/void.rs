@@ -5,7 +5,8 @@ use crate::utils;
 use async_compression::tokio::bufread::ZstdDecoder;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use tokio::io::{self, AsyncReadExt};
 use tokio_tar::{Archive, EntryType};
@@ -19,15 +20,30 @@ pub struct Package {
     source_revisions: String,
 }
 
+/// Identify the set of packages in a task, so new packages for a known commit get a new task
+fn packages_digest(packages: &[db::VoidPackage]) -> String {
+    let mut sha256 = Sha256::new();
+    for pkg in packages {
+        sha256.update(format!("{}:{}:{}\n", pkg.srcpkg, pkg.package, pkg.version));
+    }
+    hex::encode(&sha256.finalize()[..8])
+}
+
 pub async fn run(args: &args::SyncVoid) -> Result<()> {
     let db = db::Client::create().await?;
     let vendor = &args.vendor;
 
-    let reader = utils::fetch_or_open(&args.file, args.fetch).await?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+    let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+        return Ok(());
+    };
     let reader = io::BufReader::new(reader);
     let reader = ZstdDecoder::new(reader);
     let mut tar = Archive::new(reader);
 
+    let mut commits = BTreeMap::<String, Vec<db::VoidPackage>>::new();
     let mut entries = tar.entries()?;
     while let Some(entry) = entries.next().await {
         let mut entry = entry?;
@@ -65,21 +81,37 @@ pub async fn run(args: &args::SyncVoid) -> Result<()> {
                 continue;
             }
 
-            // queue for import
             info!("srcpkg={srcpkg:?} commit={commit:?} package={pkgname:?} version={version:?}");
-            db.insert_task(&db::Task::new(
-                format!("void-linux-git:{srcpkg}:{commit}:{pkgname}:{version}"),
-                &db::TaskData::VoidLinuxGit {
-                    vendor: vendor.to_string(),
+            commits
+                .entry(commit.to_string())
+                .or_default()
+                .push(db::VoidPackage {
                     srcpkg: srcpkg.to_string(),
-                    commit: commit.to_string(),
                     package: pkgname.to_string(),
                     version: version.to_string(),
-                },
-            )?)
-            .await?;
+                });
         }
     }
 
+    // queue for import, one task per commit so the snapshot is only downloaded once
+    for (commit, mut packages) in commits {
+        packages.sort_by(|a, b| a.package.cmp(&b.package));
+        info!(
+            "Inserting task: commit={commit:?} packages={}",
+            packages.len()
+        );
+        db.insert_task(&db::Task::new(
+            format!("void-linux-commit:{commit}:{}", packages_digest(&packages)),
+            &db::TaskData::VoidLinuxCommit {
+                vendor: vendor.to_string(),
+                commit,
+                packages,
+            },
+        )?)
+        .await?;
+    }
+
+    http.mark_processed(&args.file).await?;
+
     Ok(())
 }
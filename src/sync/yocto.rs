@@ -26,7 +26,12 @@ pub async fn run(args: &args::SyncYocto) -> Result<()> {
     let db = db::Client::create().await?;
     let vendor = &args.vendor;
 
-    let reader = utils::fetch_or_open(&args.file, args.fetch).await?;
+    let http = utils::http_client(None)?
+        .with_cache(&args.http_cache)
+        .with_rewrites(&args.rewrites);
+    let Some(reader) = http.fetch_index(&args.file, args.fetch).await? else {
+        return Ok(());
+    };
     let reader = io::BufReader::new(reader);
     let reader = GzipDecoder::new(reader);
     let mut tar = Archive::new(reader);
@@ -71,6 +76,10 @@ pub async fn run(args: &args::SyncYocto) -> Result<()> {
         for artifact in artifacts {
             let (chksum, url) = match (artifact.sha256, artifact.commit) {
                 (Some(sha256), _) => (format!("sha256:{sha256}"), artifact.src),
+                (_, Some(commit)) if artifact.src.starts_with("gitsm+") => (
+                    format!("gitsm:{commit}"),
+                    format!("{}#commit={commit}", artifact.src),
+                ),
                 (_, Some(commit)) => (
                     format!("git:{commit}"),
                     format!("{}#commit={commit}", artifact.src),
@@ -90,6 +99,9 @@ pub async fn run(args: &args::SyncYocto) -> Result<()> {
                 package: package.to_string(),
                 version: version.to_string(),
                 filename: Some(url),
+                role: None,
+                repo: None,
+                arch: None,
             };
             debug!("insert: {r:?}");
             db.insert_ref(&r).await?;
@@ -105,5 +117,7 @@ pub async fn run(args: &args::SyncYocto) -> Result<()> {
         warn!("Encounted {errors} errors while processing snapshot");
     }
 
+    http.mark_processed(&args.file).await?;
+
     Ok(())
 }
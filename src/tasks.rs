@@ -0,0 +1,93 @@
+use crate::args;
+use crate::db::{self, Task};
+use crate::errors::*;
+use serde_json::json;
+
+impl From<&args::TaskFilter> for db::TaskFilter {
+    fn from(filter: &args::TaskFilter) -> Self {
+        db::TaskFilter {
+            prefix: filter.prefix.clone(),
+            error: filter.error.clone(),
+            min_retries: filter.min_retries,
+            failed: filter.failed,
+        }
+    }
+}
+
+pub fn task_state(task: &Task) -> String {
+    if let Some(failed_at) = task.failed_at {
+        format!("failed since {}", failed_at.to_rfc3339())
+    } else if let Some(next_attempt_at) = task.next_attempt_at {
+        format!("retry after {}", next_attempt_at.to_rfc3339())
+    } else {
+        "pending".to_string()
+    }
+}
+
+async fn find_task(db: &db::Client, task: &str) -> Result<Task> {
+    let found = if let Ok(id) = task.parse::<i64>() {
+        db.get_task_by_id(id).await?
+    } else {
+        db.get_task(task).await?
+    };
+    found.ok_or_else(|| Error::TaskNotFound(task.to_string()))
+}
+
+pub async fn run(args: &args::Tasks) -> Result<()> {
+    let db = db::Client::create().await?;
+
+    match args {
+        args::Tasks::List(args) => {
+            let filter = db::TaskFilter::from(&args.filter);
+            for task in db.list_tasks(&filter, args.limit).await? {
+                println!(
+                    "{}\t{}\tretries={}\t{}\t{}",
+                    task.id,
+                    task.key,
+                    task.retries,
+                    task_state(&task),
+                    task.error.as_deref().unwrap_or("-"),
+                );
+            }
+        }
+        args::Tasks::Show(args) => {
+            let task = find_task(&db, &args.task).await?;
+            let txt = serde_json::to_string_pretty(&json!({
+                "id": task.id,
+                "key": task.key,
+                "state": task_state(&task),
+                "retries": task.retries,
+                "error": task.error,
+                "data": task.data,
+            }))?;
+            println!("{txt}");
+        }
+        args::Tasks::Retry(args) => {
+            let filter = db::TaskFilter::from(&args.filter);
+            let num = db.retry_tasks(&filter).await?;
+            info!("Scheduled {num} tasks for retry");
+        }
+        args::Tasks::Delete(args) => {
+            for task in &args.tasks {
+                let task = find_task(&db, task).await?;
+                info!("Deleting task: {:?}", task.key);
+                if !db.delete_task(&task).await? {
+                    warn!(
+                        "Task has been claimed by a worker in the meantime, not deleting: {:?}",
+                        task.key
+                    );
+                }
+            }
+        }
+        args::Tasks::Purge(args) => {
+            let filter = db::TaskFilter::from(&args.filter);
+            if filter.is_empty() && !args.all {
+                return Err(Error::TaskFilterMissing);
+            }
+            let num = db.delete_tasks(&filter).await?;
+            info!("Deleted {num} tasks");
+        }
+    }
+
+    Ok(())
+}
@@ -1,6 +1,14 @@
+use crate::args;
 use crate::db::{Task, TaskData};
 use crate::errors::*;
 use futures::TryStreamExt;
+use reqwest::header::{self, HeaderMap};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::str::FromStr;
 use std::time::Duration;
 use tokio::fs;
 use tokio::io::{self, AsyncRead};
@@ -21,23 +29,290 @@ pub fn http_client(socks5: Option<&String>) -> Result<HttpClient> {
         .connect_timeout(CONNECT_TIMEOUT)
         .read_timeout(READ_TIMEOUT)
         .build()?;
-    Ok(HttpClient { reqwest: http })
+    Ok(HttpClient {
+        reqwest: http,
+        cache: None,
+        rewrites: Vec::new(),
+    })
+}
+
+/// Replace the prefix of a url, e.g. to download from a local mirror instead
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewriteRule {
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for RewriteRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((from, to)) = s.split_once('=') else {
+            return Err(Error::InvalidRewriteRule(s.to_string()));
+        };
+        if from.is_empty() {
+            return Err(Error::InvalidRewriteRule(s.to_string()));
+        }
+        Ok(RewriteRule {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+pub fn rewrite_url(rules: &[RewriteRule], url: &str) -> String {
+    for rule in rules {
+        if let Some(suffix) = url.strip_prefix(&rule.from) {
+            return format!("{}{suffix}", rule.to);
+        }
+    }
+    url.to_string()
+}
+
+/// Return the filesystem path if a rewrite rule points the url to a local file
+///
+/// Urls from package metadata are never opened from disk, only the operator configured rewrite
+/// rules may point into the local filesystem.
+pub fn rewritten_local_path(rules: &[RewriteRule], url: &str) -> Option<PathBuf> {
+    let rule = rules.iter().find(|rule| url.starts_with(&rule.from))?;
+    local_path(&rewrite_url(slice::from_ref(rule), url))
+}
+
+/// Return the filesystem path if the url points to a local file
+///
+/// Plain absolute paths are accepted so rewrite rules can point directly into a directory.
+fn local_path(url: &str) -> Option<PathBuf> {
+    if let Some(path) = url.strip_prefix("file://") {
+        Some(PathBuf::from(url_escape::decode(path).as_ref()))
+    } else if url.starts_with('/') {
+        Some(PathBuf::from(url))
+    } else {
+        None
+    }
 }
 
 pub struct HttpClient {
     reqwest: reqwest::Client,
+    cache: Option<HttpCache>,
+    rewrites: Vec<RewriteRule>,
 }
 
 impl HttpClient {
-    pub async fn fetch(&self, url: &str) -> Result<Box<dyn AsyncRead + Unpin>> {
+    /// Apply url rewrite rules before downloading anything
+    pub fn with_rewrites(mut self, args: &args::UrlRewrites) -> Self {
+        self.rewrites = args.rules.clone();
+        self
+    }
+
+    /// The url rewrite rules, to apply them to version control remotes too
+    pub fn rewrites(&self) -> &[RewriteRule] {
+        &self.rewrites
+    }
+
+    /// Keep a copy of responses on disk and revalidate them with the server on the next fetch
+    pub fn with_cache(mut self, args: &args::HttpCache) -> Self {
+        self.cache = args.http_cache.clone().map(|path| HttpCache {
+            path,
+            force: args.force,
+        });
+        self
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+        if let Some(path) = rewritten_local_path(&self.rewrites, url) {
+            debug!("Opening local file: {path:?}");
+            let file = fs::File::open(path).await?;
+            return Ok(Box::new(file));
+        }
+        let url = rewrite_url(&self.rewrites, url);
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(Error::UnsupportedUrl(url));
+        }
+
         let resp = self.reqwest.get(url).send().await?.error_for_status()?;
         let stream = resp.bytes_stream();
-        let stream = StreamReader::new(stream.map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+        let stream = StreamReader::new(stream.map_err(io::Error::other));
         Ok(Box::new(stream))
     }
+
+    /// Fetch a url through the cache, if configured
+    ///
+    /// The response is flagged as unchanged if the server confirmed our cached copy is still
+    /// current and it has been successfully processed with [`HttpClient::mark_processed`] before.
+    pub async fn fetch_cached(&self, url: &str) -> Result<Fetched> {
+        let rewritten = rewrite_url(&self.rewrites, url);
+        let Some(cache) = self
+            .cache
+            .as_ref()
+            .filter(|_| rewritten_local_path(&self.rewrites, url).is_none())
+        else {
+            let reader = self.fetch(url).await?;
+            return Ok(Fetched {
+                reader,
+                unchanged: false,
+            });
+        };
+
+        if !rewritten.starts_with("https://") && !rewritten.starts_with("http://") {
+            return Err(Error::UnsupportedUrl(rewritten));
+        }
+
+        let (meta_path, body_path) = cache.paths(url);
+        let entry = cache.read_entry(url).await?;
+
+        let mut req = self.reqwest.get(&rewritten);
+        if let Some(entry) = &entry {
+            if fs::metadata(&body_path).await.is_ok() {
+                if let Some(etag) = &entry.etag {
+                    req = req.header(header::IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &entry.last_modified {
+                    req = req.header(header::IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+        }
+
+        let mut resp = req.send().await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            match (entry, fs::File::open(&body_path).await) {
+                (Some(entry), Ok(file)) => {
+                    info!("Server reports cached copy is still current: {url:?}");
+                    return Ok(Fetched {
+                        reader: Box::new(file),
+                        unchanged: entry.processed && !cache.force,
+                    });
+                }
+                _ => {
+                    warn!("Server reports cached copy is still current, but we have none, fetching again: {url:?}");
+                    resp = self.reqwest.get(&rewritten).send().await?;
+                }
+            }
+        }
+        let resp = resp.error_for_status()?;
+
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag: header_value(resp.headers(), header::ETAG),
+            last_modified: header_value(resp.headers(), header::LAST_MODIFIED),
+            processed: false,
+        };
+
+        // invalidate the old entry before touching the body
+        if let Err(err) = fs::remove_file(&meta_path).await {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+        fs::create_dir_all(&cache.path).await?;
+
+        debug!("Writing response to cache: {body_path:?}");
+        let tmp_path = body_path.with_extension("tmp");
+        let stream = resp.bytes_stream();
+        let mut stream = StreamReader::new(stream.map_err(io::Error::other));
+        let mut file = fs::File::create(&tmp_path).await?;
+        io::copy(&mut stream, &mut file).await?;
+        fs::rename(&tmp_path, &body_path).await?;
+
+        cache.write_entry(&entry).await?;
+
+        let file = fs::File::open(&body_path).await?;
+        Ok(Fetched {
+            reader: Box::new(file),
+            unchanged: false,
+        })
+    }
+
+    /// Fetch an index through the cache or open a local file, `None` if it's unchanged since the last sync
+    ///
+    /// Local files are never considered unchanged.
+    pub async fn fetch_index(
+        &self,
+        path: &str,
+        should_fetch: bool,
+    ) -> Result<Option<Box<dyn AsyncRead + Send + Unpin>>> {
+        if !should_fetch {
+            let file = fs::File::open(path).await?;
+            return Ok(Some(Box::new(file)));
+        }
+        let fetched = self.fetch_cached(path).await?;
+        if fetched.unchanged {
+            info!("Index is unchanged since last sync, skipping: {path:?}");
+            Ok(None)
+        } else {
+            Ok(Some(fetched.reader))
+        }
+    }
+
+    /// Record that the cached response for this url has been fully processed
+    pub async fn mark_processed(&self, url: &str) -> Result<()> {
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
+        let Some(mut entry) = cache.read_entry(url).await? else {
+            return Ok(());
+        };
+        entry.processed = true;
+        cache.write_entry(&entry).await
+    }
+}
+
+pub struct Fetched {
+    pub reader: Box<dyn AsyncRead + Send + Unpin>,
+    /// The content is identical to a previous, successfully processed response
+    pub unchanged: bool,
+}
+
+struct HttpCache {
+    path: PathBuf,
+    force: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    processed: bool,
 }
 
-pub async fn fetch_or_open(path: &str, should_fetch: bool) -> Result<Box<dyn AsyncRead + Unpin>> {
+impl HttpCache {
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = hex::encode(Sha256::digest(url.as_bytes()));
+        (
+            self.path.join(format!("{key}.json")),
+            self.path.join(format!("{key}.body")),
+        )
+    }
+
+    async fn read_entry(&self, url: &str) -> Result<Option<CacheEntry>> {
+        let (meta_path, _) = self.paths(url);
+        let buf = match fs::read(&meta_path).await {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let entry = serde_json::from_slice::<CacheEntry>(&buf)?;
+        Ok(Some(entry).filter(|entry| entry.url == url))
+    }
+
+    async fn write_entry(&self, entry: &CacheEntry) -> Result<()> {
+        let (meta_path, _) = self.paths(&entry.url);
+        let tmp_path = meta_path.with_extension("tmp.json");
+        fs::write(&tmp_path, serde_json::to_vec(entry)?).await?;
+        fs::rename(&tmp_path, &meta_path).await?;
+        Ok(())
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?;
+    Some(value.to_string())
+}
+
+pub async fn fetch_or_open(
+    path: &str,
+    should_fetch: bool,
+) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
     if should_fetch {
         http_client(None)?.fetch(path).await
     } else {
@@ -46,6 +321,54 @@ pub async fn fetch_or_open(path: &str, should_fetch: bool) -> Result<Box<dyn Asy
     }
 }
 
+/// A file in a directory of checkouts, e.g. `<dir>/<package>/<filename>`
+#[derive(Debug, PartialEq)]
+pub struct CheckoutFile {
+    pub package: String,
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+/// Read the `filenames` of each checkout in a directory, skipping files larger than `max_size`
+pub async fn read_checkouts<F>(
+    path: &Path,
+    filenames: F,
+    max_size: u64,
+) -> Result<Vec<CheckoutFile>>
+where
+    F: Fn(&str) -> Vec<String>,
+{
+    let mut files = Vec::new();
+    let mut dir = fs::read_dir(path).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let Ok(package) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        for filename in filenames(&package) {
+            let path = entry.path().join(&filename);
+            if !fs::try_exists(&path).await? {
+                continue;
+            }
+            if fs::metadata(&path).await?.len() > max_size {
+                warn!("File in checkout is too large, skipping: {path:?}");
+                continue;
+            }
+            debug!("Found file in checkout: {path:?}");
+            let data = fs::read(&path).await?;
+            files.push(CheckoutFile {
+                package: package.clone(),
+                filename,
+                data,
+            });
+        }
+    }
+    Ok(files)
+}
+
 pub fn is_possible_tar_artifact(url: &str) -> bool {
     if !url.starts_with("https://") && !url.starts_with("http://") {
         false
@@ -62,8 +385,12 @@ pub fn task_for_url(url: &str) -> Option<Task> {
                     format!("fetch:{url}"),
                     &TaskData::FetchTar {
                         url: url.to_string(),
+                        mirrors: Vec::new(),
+                        aliases: Vec::new(),
                         compression: None,
                         success_ref: None,
+                        sha256: None,
+                        signature: None,
                     },
                 )
                 .ok()
@@ -71,7 +398,7 @@ pub fn task_for_url(url: &str) -> Option<Task> {
                 None
             }
         }
-        Some((schema, _)) if schema.starts_with("git+") => {
+        Some((schema, _)) if schema.starts_with("git+") || schema.starts_with("gitsm+") => {
             debug!("Found git remote: {url:?}");
             Task::new(
                 format!("git-clone:{url}"),
@@ -81,6 +408,291 @@ pub fn task_for_url(url: &str) -> Option<Task> {
             )
             .ok()
         }
+        Some((schema, _)) if schema.starts_with("hg+") => {
+            debug!("Found mercurial remote: {url:?}");
+            Task::new(
+                format!("hg-clone:{url}"),
+                &TaskData::HgSnapshot {
+                    url: url.to_string(),
+                },
+            )
+            .ok()
+        }
+        Some((schema, _)) if schema.starts_with("svn+") => {
+            debug!("Found subversion remote: {url:?}");
+            Task::new(
+                format!("svn-export:{url}"),
+                &TaskData::SvnSnapshot {
+                    url: url.to_string(),
+                },
+            )
+            .ok()
+        }
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncReadExt;
+    use warp::Filter;
+
+    #[test]
+    fn test_rewrite_url() {
+        let rules = [
+            "https://deb.debian.org/=/srv/mirror/debian/"
+                .parse()
+                .unwrap(),
+            "https://geo.mirror.pkgbuild.com/=file:///srv/mirror/arch/"
+                .parse()
+                .unwrap(),
+            "https://ftp.gnu.org/=https://mirror.example.com/gnu/"
+                .parse()
+                .unwrap(),
+        ];
+        let url = "https://deb.debian.org/debian/dists/sid/main/source/Sources.xz";
+        assert_eq!(
+            rewrite_url(&rules, url),
+            "/srv/mirror/debian/debian/dists/sid/main/source/Sources.xz"
+        );
+        assert_eq!(
+            rewritten_local_path(&rules, url),
+            Some(PathBuf::from(
+                "/srv/mirror/debian/debian/dists/sid/main/source/Sources.xz"
+            ))
+        );
+
+        let url = "https://geo.mirror.pkgbuild.com/foo%201.tar.gz";
+        assert_eq!(
+            rewrite_url(&rules, url),
+            "file:///srv/mirror/arch/foo%201.tar.gz"
+        );
+        assert_eq!(
+            rewritten_local_path(&rules, url),
+            Some(PathBuf::from("/srv/mirror/arch/foo 1.tar.gz"))
+        );
+
+        let url = "https://ftp.gnu.org/gnu/hello/hello-2.12.tar.gz";
+        assert_eq!(
+            rewrite_url(&rules, url),
+            "https://mirror.example.com/gnu/gnu/hello/hello-2.12.tar.gz"
+        );
+        assert_eq!(rewritten_local_path(&rules, url), None);
+
+        let url = "https://example.com/foo-1.0.tar.gz";
+        assert_eq!(rewrite_url(&rules, url), url);
+        assert_eq!(rewritten_local_path(&rules, url), None);
+    }
+
+    #[test]
+    fn test_local_urls_without_rewrite() {
+        for url in ["file:///etc/shadow", "/etc/shadow"] {
+            assert_eq!(rewritten_local_path(&[], url), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_refuses_local_urls() {
+        let http = http_client(None).unwrap();
+        for url in ["file:///etc/hostname", "/etc/hostname"] {
+            assert!(matches!(
+                http.fetch(url).await,
+                Err(Error::UnsupportedUrl(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_rewrite_rule_invalid() {
+        assert!("https://deb.debian.org/".parse::<RewriteRule>().is_err());
+        assert!("=/srv/mirror/".parse::<RewriteRule>().is_err());
+    }
+
+    #[test]
+    fn test_task_for_file_url() {
+        assert!(task_for_url("file:///srv/mirror/foo-1.0.tar.gz").is_none());
+        assert!(task_for_url("/srv/mirror/foo-1.0.tar.gz").is_none());
+        assert!(!is_possible_tar_artifact(
+            "file:///srv/mirror/foo-1.0.tar.gz"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_checkouts() {
+        let tmp =
+            std::env::temp_dir().join(format!("whatsrc-checkouts-{:016x}", fastrand::u64(..)));
+        fs::create_dir_all(tmp.join("hello")).await.unwrap();
+        fs::create_dir_all(tmp.join("empty")).await.unwrap();
+        fs::write(
+            tmp.join("hello/sources"),
+            "SHA512 (hello-2.12.tar.gz) = 00\n",
+        )
+        .await
+        .unwrap();
+        fs::write(tmp.join("hello/hello.spec"), "x".repeat(100))
+            .await
+            .unwrap();
+        fs::write(tmp.join("README"), "not a checkout\n")
+            .await
+            .unwrap();
+
+        let files = read_checkouts(
+            &tmp,
+            |package| vec!["sources".to_string(), format!("{package}.spec")],
+            64,
+        )
+        .await;
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        assert_eq!(
+            files.unwrap(),
+            [CheckoutFile {
+                package: "hello".to_string(),
+                filename: "sources".to_string(),
+                data: b"SHA512 (hello-2.12.tar.gz) = 00\n".to_vec(),
+            }]
+        );
+    }
+
+    type Requests = Arc<Mutex<Vec<(String, Option<String>, Option<String>)>>>;
+
+    /// Serve a fixed index that supports revalidation, `/stale` claims to be unchanged on the first request
+    fn serve_index() -> (String, Requests) {
+        let requests = Requests::default();
+        let log = requests.clone();
+        let routes = warp::path::param::<String>()
+            .and(warp::header::optional::<String>("if-none-match"))
+            .and(warp::header::optional::<String>("if-modified-since"))
+            .map(
+                move |name: String, etag: Option<String>, since: Option<String>| {
+                    let mut log = log.lock().unwrap();
+                    log.push((name.clone(), etag.clone(), since));
+                    let first = log.len() == 1;
+                    let res = warp::http::Response::builder();
+                    if etag.as_deref() == Some("\"v1\"") || (name == "stale" && first) {
+                        res.status(304).body(String::new())
+                    } else {
+                        res.header("etag", "\"v1\"")
+                            .header("last-modified", "Tue, 16 Jul 2024 09:00:00 GMT")
+                            .body("hello world\n".to_string())
+                    }
+                },
+            );
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{addr}"), requests)
+    }
+
+    fn cached_client(path: &Path, force: bool) -> HttpClient {
+        let mut http = http_client(None).unwrap();
+        http.cache = Some(HttpCache {
+            path: path.to_path_buf(),
+            force,
+        });
+        http
+    }
+
+    async fn read_fetched(fetched: Fetched) -> (String, bool) {
+        let mut reader = fetched.reader;
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf).await.unwrap();
+        (buf, fetched.unchanged)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_cached_revalidate() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-http-{:016x}", fastrand::u64(..)));
+        let (base, requests) = serve_index();
+        let url = format!("{base}/index");
+        let http = cached_client(&tmp, false);
+
+        // first fetch has nothing to revalidate
+        let fetched = http.fetch_cached(&url).await.unwrap();
+        assert_eq!(
+            read_fetched(fetched).await,
+            ("hello world\n".to_string(), false)
+        );
+
+        // cached copy is current, but hasn't been processed yet
+        let fetched = http.fetch_cached(&url).await.unwrap();
+        assert_eq!(
+            read_fetched(fetched).await,
+            ("hello world\n".to_string(), false)
+        );
+
+        http.mark_processed(&url).await.unwrap();
+        let fetched = http.fetch_cached(&url).await.unwrap();
+        assert_eq!(
+            read_fetched(fetched).await,
+            ("hello world\n".to_string(), true)
+        );
+        assert!(http.fetch_index(&url, true).await.unwrap().is_none());
+
+        // forced syncs process the index again
+        let fetched = cached_client(&tmp, true).fetch_cached(&url).await.unwrap();
+        assert_eq!(
+            read_fetched(fetched).await,
+            ("hello world\n".to_string(), false)
+        );
+
+        // without a cached body there's nothing to revalidate
+        let (_, body_path) = http.cache.as_ref().unwrap().paths(&url);
+        fs::remove_file(&body_path).await.unwrap();
+        let fetched = http.fetch_cached(&url).await.unwrap();
+        assert_eq!(
+            read_fetched(fetched).await,
+            ("hello world\n".to_string(), false)
+        );
+
+        fs::remove_dir_all(&tmp).await.unwrap();
+        let validators = Some((
+            "\"v1\"".to_string(),
+            "Tue, 16 Jul 2024 09:00:00 GMT".to_string(),
+        ));
+        assert_eq!(
+            requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, etag, since)| etag.clone().zip(since.clone()))
+                .collect::<Vec<_>>(),
+            [
+                None,
+                validators.clone(),
+                validators.clone(),
+                validators.clone(),
+                validators,
+                None,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_cached_not_modified_without_copy() {
+        let tmp = std::env::temp_dir().join(format!("whatsrc-http-{:016x}", fastrand::u64(..)));
+        let (base, requests) = serve_index();
+        let url = format!("{base}/stale");
+        let http = cached_client(&tmp, false);
+
+        let fetched = http.fetch_cached(&url).await.unwrap();
+        assert_eq!(
+            read_fetched(fetched).await,
+            ("hello world\n".to_string(), false)
+        );
+        let entry = http.cache.as_ref().unwrap().read_entry(&url).await;
+        fs::remove_dir_all(&tmp).await.unwrap();
+
+        assert_eq!(
+            entry.unwrap(),
+            Some(CacheEntry {
+                url,
+                etag: Some("\"v1\"".to_string()),
+                last_modified: Some("Tue, 16 Jul 2024 09:00:00 GMT".to_string()),
+                processed: false,
+            })
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
@@ -3,6 +3,7 @@ use crate::db;
 use crate::errors::*;
 use crate::ingest;
 use crate::sbom;
+use crate::tasks;
 use data_encoding::BASE64;
 use diffy_fork_filenames as diffy;
 use log::error;
@@ -29,6 +30,7 @@ use warp::{
 };
 
 const SEARCH_LIMIT: usize = 250;
+const TASKS_LIMIT: usize = 250;
 
 #[allow(clippy::declare_interior_mutable_const)]
 const CACHE_CONTROL_DEFAULT: HeaderValue =
@@ -138,7 +140,7 @@ fn cache_control(reply: impl warp::Reply, value: HeaderValue) -> impl warp::Repl
 }
 
 async fn index(hbs: Arc<Handlebars<'_>>) -> result::Result<Box<dyn warp::Reply>, warp::Rejection> {
-    let html = hbs.render("index.html.hbs", &()).map_err(Error::from)?;
+    let html = hbs.render("index.html.hbs", &())?;
     Ok(Box::new(warp::reply::html(html)))
 }
 
@@ -173,10 +175,12 @@ async fn artifact(
     db: Arc<db::Client>,
     chksum: String,
 ) -> result::Result<Box<dyn warp::Reply>, warp::Rejection> {
+    // svn aliases contain the repository url
+    let chksum = url_escape::decode(&chksum);
     let (chksum, json) = chksum
         .strip_suffix(".json")
         .map(|chksum| (chksum, true))
-        .unwrap_or((chksum.as_str(), false));
+        .unwrap_or((chksum.as_ref(), false));
 
     let alias = db.get_artifact_alias(chksum).await?;
 
@@ -190,11 +194,28 @@ async fn artifact(
 
     let files = artifact.get_files()?;
     let sbom_refs = db.get_sbom_refs_for_archive(resolved_chksum).await?;
+    let submodules = db.get_artifact_submodules(resolved_chksum).await?;
+    let signatures = db.get_git_signatures(resolved_chksum).await?;
+    let release_signatures = db
+        .get_artifact_signatures(resolved_chksum)
+        .await?
+        .into_iter()
+        .map(|s| {
+            json!({
+                "display_vendor": db::display_vendor(&s.vendor),
+                "valid": s.status == ingest::git::SignatureStatus::Valid.as_str(),
+                "signature": s,
+            })
+        })
+        .collect::<Vec<_>>();
 
     if json {
         Ok(Box::new(warp::reply::json(&json!({
             "files": files,
             "sbom_refs": sbom_refs,
+            "submodules": submodules,
+            "signatures": signatures,
+            "release_signatures": release_signatures,
         }))))
     } else {
         let suspecting_autotools = detect_autotools(files.as_deref());
@@ -214,26 +235,27 @@ async fn artifact(
             }
         }
 
-        let html = hbs
-            .render(
-                "artifact.html.hbs",
-                &json!({
-                    "artifact": artifact,
-                    "chksum": chksum,
-                    "alias": alias,
-                    "refs": json!([{
-                        "title": "Build input of",
-                        "refs": build_inputs,
-                    }, {
-                        "title": "Found at",
-                        "refs": found_at,
-                    }]),
-                    "sbom_refs": sbom_refs,
-                    "files": files,
-                    "suspecting_autotools": suspecting_autotools,
-                }),
-            )
-            .map_err(Error::from)?;
+        let html = hbs.render(
+            "artifact.html.hbs",
+            &json!({
+                "artifact": artifact,
+                "chksum": chksum,
+                "alias": alias,
+                "refs": json!([{
+                    "title": "Build input of",
+                    "refs": build_inputs,
+                }, {
+                    "title": "Found at",
+                    "refs": found_at,
+                }]),
+                "sbom_refs": sbom_refs,
+                "submodules": submodules,
+                "signatures": signatures,
+                "release_signatures": release_signatures,
+                "files": files,
+                "suspecting_autotools": suspecting_autotools,
+            }),
+        )?;
         Ok(Box::new(warp::reply::html(html)))
     }
 }
@@ -270,17 +292,15 @@ async fn sbom(
             }
         };
 
-        let html = hbs
-            .render(
-                "sbom.html.hbs",
-                &json!({
-                    "sbom": sbom,
-                    "chksum": chksum,
-                    "sbom_refs": sbom_refs,
-                    "packages": packages,
-                }),
-            )
-            .map_err(Error::from)?;
+        let html = hbs.render(
+            "sbom.html.hbs",
+            &json!({
+                "sbom": sbom,
+                "chksum": chksum,
+                "sbom_refs": sbom_refs,
+                "packages": packages,
+            }),
+        )?;
         Ok(Box::new(warp::reply::html(html)))
     }
 }
@@ -307,7 +327,10 @@ fn detect_hash_search(txt: &str) -> Option<Cow<'_, str>> {
     } else if let Some(value) = txt.strip_prefix("blake2b:") {
         // search is in format blake2b:<hex>
         (value.len() == 128).then_some(Cow::Borrowed(txt))
-    } else if let Some(_value) = txt.strip_prefix("git:") {
+    } else if ["git:", "gitsm:", "hg:", "svn:"]
+        .iter()
+        .any(|prefix| txt.starts_with(prefix))
+    {
         Some(Cow::Borrowed(txt))
     } else if let Ok(url) = txt.parse::<Uri>() {
         let path = url.path();
@@ -354,15 +377,13 @@ async fn search(
 
     let refs = db.search(&query, SEARCH_LIMIT).await?;
 
-    let html = hbs
-        .render(
-            "search.html.hbs",
-            &json!({
-                "search": search.q,
-                "refs": refs,
-            }),
-        )
-        .map_err(Error::from)?;
+    let html = hbs.render(
+        "search.html.hbs",
+        &json!({
+            "search": search.q,
+            "refs": refs,
+        }),
+    )?;
     Ok(Box::new(warp::reply::html(html)))
 }
 
@@ -393,6 +414,10 @@ async fn stats(
         let db = db.clone();
         set.spawn(async move { ("pending_tasks", db.stats_pending_tasks().await) });
     }
+    {
+        let db = db.clone();
+        set.spawn(async move { ("failed_tasks", db.stats_failed_tasks().await) });
+    }
     {
         let db = db.clone();
         set.spawn(async move { ("aliases_with_reason", db.stats_aliases_with_reason().await) });
@@ -419,7 +444,50 @@ async fn stats(
         data.insert(key, values);
     }
 
-    let html = hbs.render("stats.html.hbs", &data).map_err(Error::from)?;
+    let html = hbs.render("stats.html.hbs", &data)?;
+    Ok(Box::new(warp::reply::html(html)))
+}
+
+#[derive(Debug, Deserialize)]
+struct TasksQuery {
+    prefix: Option<String>,
+    error: Option<String>,
+}
+
+async fn tasks(
+    hbs: Arc<Handlebars<'_>>,
+    db: Arc<db::Client>,
+    query: TasksQuery,
+) -> result::Result<Box<dyn warp::Reply>, warp::Rejection> {
+    // only list tasks that have failed at least once
+    let filter = db::TaskFilter {
+        prefix: query.prefix.clone().filter(|s| !s.is_empty()),
+        error: query.error.clone().filter(|s| !s.is_empty()),
+        min_retries: Some(1),
+        failed: false,
+    };
+    let tasks = db
+        .list_tasks(&filter, Some(TASKS_LIMIT))
+        .await?
+        .into_iter()
+        .map(|task| {
+            json!({
+                "key": task.key,
+                "retries": task.retries,
+                "state": tasks::task_state(&task),
+                "error": task.error,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let html = hbs.render(
+        "tasks.html.hbs",
+        &json!({
+            "prefix": query.prefix,
+            "error": query.error,
+            "tasks": tasks,
+        }),
+    )?;
     Ok(Box::new(warp::reply::html(html)))
 }
 
@@ -469,9 +537,8 @@ impl FromStr for Diff {
 
         let s = s
             .strip_prefix("-sorted")
-            .map(|s| {
+            .inspect(|_s| {
                 diff.sorted = true;
-                s
             })
             .unwrap_or(s);
 
@@ -510,6 +577,51 @@ impl fmt::Display for Diff {
     }
 }
 
+#[derive(Debug, Serialize)]
+struct MovedTag {
+    remote: String,
+    tag: String,
+    commits: Vec<db::GitTag>,
+}
+
+/// Group the rows of consecutive (remote, tag) pairs
+fn group_moved_tags(rows: Vec<db::GitTag>) -> Vec<MovedTag> {
+    let mut tags = Vec::<MovedTag>::new();
+    for row in rows {
+        match tags.last_mut() {
+            Some(last) if last.remote == row.remote && last.tag == row.tag => {
+                last.commits.push(row)
+            }
+            _ => tags.push(MovedTag {
+                remote: row.remote.clone(),
+                tag: row.tag.clone(),
+                commits: vec![row],
+            }),
+        }
+    }
+    tags
+}
+
+async fn moved_tags(
+    hbs: Arc<Handlebars<'_>>,
+    db: Arc<db::Client>,
+    json: bool,
+) -> result::Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let tags = group_moved_tags(db.get_moved_git_tags().await?);
+
+    if json {
+        Ok(Box::new(warp::reply::json(&tags)))
+    } else {
+        let html = hbs.render(
+            "moved-tags.html.hbs",
+            &json!({
+                "tags": tags,
+            }),
+        )?;
+        Ok(Box::new(warp::reply::html(html)))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct DiffRedirectQuery {
     diff_from: String,
@@ -568,21 +680,19 @@ async fn diff(
     let diff = diff.to_string();
     let diff_lines = diff.split('\n').collect::<Vec<_>>();
 
-    let html = hbs
-        .render(
-            "diff.html.hbs",
-            &json!({
-                "diff": diff_lines,
-                "diff_from": diff_from,
-                "diff_to": diff_to,
-                "options": options,
-                "sorted": options.sorted,
-                "trimmed": options.trim_left || options.trim_right,
-                "trim_left": options.trim_left,
-                "trim_right": options.trim_right,
-            }),
-        )
-        .map_err(Error::from)?;
+    let html = hbs.render(
+        "diff.html.hbs",
+        &json!({
+            "diff": diff_lines,
+            "diff_from": diff_from,
+            "diff_to": diff_to,
+            "options": options,
+            "sorted": options.sorted,
+            "trimmed": options.trim_left || options.trim_right,
+            "trim_left": options.trim_left,
+            "trim_right": options.trim_right,
+        }),
+    )?;
     Ok(Box::new(warp::reply::html(html)))
 }
 
@@ -647,6 +757,26 @@ pub async fn run(args: &args::Web) -> Result<()> {
         .and(warp::query::<StatsQuery>())
         .and_then(stats)
         .map(|r| cache_control(r, CACHE_CONTROL_SHORT));
+    let tasks = warp::get()
+        .and(hbs.clone())
+        .and(db.clone())
+        .and(warp::path("tasks"))
+        .and(warp::path::end())
+        .and(warp::query::<TasksQuery>())
+        .and_then(tasks)
+        .map(|r| cache_control(r, CACHE_CONTROL_SHORT));
+    let moved_tags = warp::get()
+        .and(hbs.clone())
+        .and(db.clone())
+        .and(
+            warp::path("moved-tags")
+                .map(|| false)
+                .or(warp::path("moved-tags.json").map(|| true))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and_then(moved_tags)
+        .map(|r| cache_control(r, CACHE_CONTROL_SHORT));
     let diff_redirect = warp::get()
         .and(warp::path("diff"))
         .and(warp::path::end())
@@ -676,6 +806,8 @@ pub async fn run(args: &args::Web) -> Result<()> {
                 .or(sbom)
                 .or(search)
                 .or(stats)
+                .or(tasks)
+                .or(moved_tags)
                 .or(diff_redirect)
                 .or(diff)
                 .or(style),
@@ -987,6 +1119,20 @@ sha256:56d9fc4585da4f39bbc5c8ec953fb7962188fa5ed70b2dd5a19dc82df997ba5e  foo-1.0
             search.as_deref(),
             Some("git:7747534db4576db43eced4356859ef400351ca28")
         );
+
+        // test hg
+        let search = detect_hash_search("hg:0cd9a7e50bb59a3d7e4ed7d1b3c13fa5c3ac7a8a");
+        assert_eq!(
+            search.as_deref(),
+            Some("hg:0cd9a7e50bb59a3d7e4ed7d1b3c13fa5c3ac7a8a")
+        );
+
+        // test svn
+        let search = detect_hash_search("svn:svn://svn.code.sf.net/p/xmlstar/code/trunk@120");
+        assert_eq!(
+            search.as_deref(),
+            Some("svn:svn://svn.code.sf.net/p/xmlstar/code/trunk@120")
+        );
     }
 
     #[test]
@@ -1034,4 +1180,54 @@ sha256:56d9fc4585da4f39bbc5c8ec953fb7962188fa5ed70b2dd5a19dc82df997ba5e  foo-1.0
         let encoded = url_encode_artifact("sha256:abc/$<>&#xyz");
         assert_eq!(encoded, "sha256:abc%2F%24%3C%3E%26%23xyz");
     }
+
+    #[test]
+    fn test_group_moved_tags() {
+        let row = |remote: &str, tag: &str, commit: &str| db::GitTag {
+            remote: remote.to_string(),
+            tag: tag.to_string(),
+            commit: commit.to_string(),
+            first_seen: "2024-07-10 09:00:00".to_string(),
+            last_seen: "2024-07-10 09:00:00".to_string(),
+        };
+        let tags = group_moved_tags(vec![
+            row("https://github.com/curl/curl.git", "curl-8_7_1", "aaaa"),
+            row("https://github.com/curl/curl.git", "curl-8_7_1", "bbbb"),
+            row("https://github.com/curl/curl.git", "curl-8_8_0", "cccc"),
+            row("https://github.com/curl/curl.git", "curl-8_8_0", "dddd"),
+            row("https://gitlab.com/other/curl.git", "curl-8_8_0", "eeee"),
+            row("https://gitlab.com/other/curl.git", "curl-8_8_0", "ffff"),
+        ]);
+        let tags = tags
+            .iter()
+            .map(|t| {
+                let commits = t.commits.iter().map(|c| c.commit.as_str());
+                (
+                    t.remote.as_str(),
+                    t.tag.as_str(),
+                    commits.collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tags,
+            [
+                (
+                    "https://github.com/curl/curl.git",
+                    "curl-8_7_1",
+                    vec!["aaaa", "bbbb"]
+                ),
+                (
+                    "https://github.com/curl/curl.git",
+                    "curl-8_8_0",
+                    vec!["cccc", "dddd"]
+                ),
+                (
+                    "https://gitlab.com/other/curl.git",
+                    "curl-8_8_0",
+                    vec!["eeee", "ffff"]
+                ),
+            ]
+        );
+    }
 }
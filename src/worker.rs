@@ -1,12 +1,79 @@
 use crate::args;
+use crate::chksums::Checksums;
 use crate::db::{self, Task, TaskData};
 use crate::errors::*;
 use crate::ingest;
+use crate::ingest::git::SignatureStatus;
 use crate::sbom;
 use crate::utils;
+use std::future::Future;
 use std::sync::Arc;
+use tokio::task::JoinSet;
 use tokio::time::{self, Duration};
 
+/// How long a claimed task is reserved for us before other workers may pick it up
+pub const TASK_LEASE: Duration = Duration::from_secs(5 * 60);
+/// How often the lease of a running task is renewed
+pub const TASK_LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Compare the download with the checksums published by the vendor, returns if any was verified
+fn verify_published_checksums(
+    digests: &Checksums,
+    sha256: Option<&str>,
+    aliases: &[String],
+) -> Result<bool> {
+    let mut verified = false;
+    for expected in sha256.into_iter().chain(aliases.iter().map(String::as_str)) {
+        let found = if expected.starts_with("sha256:") {
+            &digests.sha256
+        } else if expected.starts_with("sha512:") {
+            &digests.sha512
+        } else {
+            continue;
+        };
+        if expected != found {
+            warn!("Published checksum does not match download: expected={expected:?}, found={found:?}");
+            return Err(Error::ChecksumMismatch(expected.to_string()));
+        }
+        verified = true;
+    }
+    Ok(verified)
+}
+
+/// Try the primary url first and fall back to the mirrors, returning the url that worked
+async fn fetch_with_mirrors<T, F, Fut>(
+    primary: String,
+    mirrors: Vec<String>,
+    mut fetch: F,
+) -> Result<(String, T)>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut error = match fetch(primary.clone()).await {
+        Ok(value) => return Ok((primary, value)),
+        Err(err) => {
+            warn!("Failed to fetch from {primary:?}: {err:#}");
+            err
+        }
+    };
+
+    for url in mirrors {
+        match fetch(url.clone()).await {
+            Ok(value) => return Ok((url, value)),
+            Err(err) => {
+                warn!("Failed to fetch from {url:?}: {err:#}");
+                // prefer reporting errors that are worth retrying
+                if error.is_permanent() {
+                    error = err;
+                }
+            }
+        }
+    }
+
+    Err(error)
+}
+
 fn normalize_archlinux_gitlab_names(package: &str) -> String {
     if package == "tree" {
         return "unix-tree".to_string();
@@ -30,17 +97,99 @@ pub struct Worker {
     db: Arc<db::Client>,
     http: utils::HttpClient,
     git_tmp: String,
+    git_cache: args::GitCache,
+    git_keyring: args::GitKeyring,
+    release_keyring: args::ReleaseKeyring,
 }
 
 impl Worker {
+    /// Download and ingest a tarball, `check` can reject the download before it's committed
+    async fn fetch_tar<F>(
+        &self,
+        url: &str,
+        compression: Option<&str>,
+        signature: Option<&db::DetachedSignature>,
+        check: F,
+    ) -> Result<ingest::tar::TarSummary>
+    where
+        F: FnOnce(&Checksums) -> Result<()>,
+    {
+        let reader = self.http.fetch(url).await?;
+        let Some(signature) = signature else {
+            return ingest::tar::stream_data_checked(Some(&self.db), reader, compression, check)
+                .await;
+        };
+
+        let (summary, verified) = ingest::signature::verify_detached_stream(
+            &self.http,
+            &self.release_keyring,
+            &self.git_tmp,
+            &signature.signature_url,
+            &signature.keys,
+            reader,
+            |reader| ingest::tar::stream_data_checked(Some(&self.db), reader, compression, check),
+        )
+        .await?;
+
+        // the download itself is fine, only report problems with the signature
+        match verified {
+            Ok((status, fingerprint)) => {
+                let result = self
+                    .record_signature(&summary.outer_digests, signature, url, status, fingerprint)
+                    .await;
+                if let Err(err) = result {
+                    warn!("Failed to record signature of {url:?}: {err:#}");
+                }
+            }
+            Err(err) => warn!("Failed to verify signature of {url:?}: {err:#}"),
+        }
+
+        Ok(summary)
+    }
+
+    async fn record_signature(
+        &self,
+        digests: &Checksums,
+        signature: &db::DetachedSignature,
+        url: &str,
+        status: SignatureStatus,
+        fingerprint: Option<String>,
+    ) -> Result<()> {
+        let chksum = &signature.chksum;
+        if ![&digests.sha256, &digests.sha512, &digests.blake2b].contains(&chksum) {
+            return Err(Error::ChecksumMismatch(chksum.to_string()));
+        }
+
+        let status =
+            ingest::signature::check_allowed_key(status, fingerprint.as_deref(), &signature.keys);
+        info!(
+            "Signature of {url:?} is {} (fingerprint: {fingerprint:?})",
+            status.as_str()
+        );
+        self.db
+            .insert_artifact_signature(&db::ArtifactSignature {
+                chksum: chksum.to_string(),
+                vendor: signature.vendor.to_string(),
+                url: url.to_string(),
+                signature_url: signature.signature_url.to_string(),
+                status: status.as_str().to_string(),
+                fingerprint,
+            })
+            .await
+    }
+
     pub async fn do_task(&self, task: &Task) -> Result<()> {
         let data = task.data()?;
 
         match data {
             TaskData::FetchTar {
                 url,
+                mirrors,
+                aliases,
                 compression,
                 success_ref,
+                sha256,
+                signature,
             } => {
                 // After importing entire distros, this is the only software I struggle with.
                 // This clown browser:
@@ -61,10 +210,8 @@ impl Worker {
                     return Ok(());
                 }
 
-                info!("Fetching tar: {url:?}");
-                let reader = self.http.fetch(&url).await?;
-
                 // TODO: do this stuff on the fly
+                // mirrors may be content-addressed, so always detect from the primary url
                 let compression = if let Some(compression) = &compression {
                     Some(compression.as_str())
                 } else if url.ends_with(".gz") || url.ends_with(".tgz") {
@@ -73,12 +220,61 @@ impl Worker {
                     Some("xz")
                 } else if url.ends_with(".bz2") {
                     Some("bz2")
+                } else if url.ends_with(".zst") {
+                    Some("zst")
+                } else if url.ends_with(".lzma") {
+                    Some("lzma")
                 } else {
                     None
                 };
 
-                // If there's an "on success" hook, insert it
-                let summary = ingest::tar::stream_data(Some(&self.db), reader, compression).await?;
+                let signature = signature.as_ref();
+                let sha256 = sha256.as_deref();
+                let aliases = &aliases;
+                let (url, (summary, verified)) =
+                    fetch_with_mirrors(url, mirrors, |url| async move {
+                        info!("Fetching tar: {url:?}");
+                        // a mismatching download must not end up in the database
+                        let mut verified = false;
+                        let summary = self
+                            .fetch_tar(&url, compression, signature, |digests| {
+                                verified = verify_published_checksums(digests, sha256, aliases)?;
+                                Ok(())
+                            })
+                            .await?;
+                        Ok((summary, verified))
+                    })
+                    .await?;
+
+                // Only trust the published checksums if they agree with the download
+                if verified {
+                    self.db
+                        .register_index_aliases(aliases, &summary.inner_digests.sha256)
+                        .await?;
+                } else if !aliases.is_empty() {
+                    warn!(
+                        "Download has no verified checksum, not registering aliases: {aliases:?}"
+                    );
+                }
+
+                // Anybody can put a commit into the pax header, only trust it if a forge made the archive
+                if let Some(commit) = &summary.git_commit {
+                    let alias = format!("git:{commit}");
+                    let chksum = &summary.inner_digests.sha256;
+                    if ingest::tar::is_forge_archive_url(&url) {
+                        info!("Found git commit in pax header of forge archive: {commit:?}");
+                        self.db
+                            .insert_alias_if_missing(&alias, chksum, "pax-comment")
+                            .await?;
+                    } else {
+                        debug!("Found unverified git commit in pax header: {commit:?}");
+                        self.db
+                            .insert_alias_hint(&alias, chksum, "pax-comment")
+                            .await?;
+                    }
+                }
+
+                // If there's an "on success" hook, insert it with the url that served the download
                 if let Some(pkg) = success_ref {
                     let r = db::Ref {
                         chksum: summary.outer_digests.sha256,
@@ -86,6 +282,9 @@ impl Worker {
                         package: pkg.package,
                         version: pkg.version,
                         filename: Some(url),
+                        role: None,
+                        repo: None,
+                        arch: None,
                     };
                     info!("insert: {r:?}");
                     self.db.insert_ref(&r).await?;
@@ -102,8 +301,16 @@ impl Worker {
 
                 info!("Downloading pacman git snapshot: {url:?}");
                 let reader = self.http.fetch(&url).await?;
-                ingest::pacman::stream_data(&self.db, reader, &vendor, &package, &version, false)
-                    .await?;
+                ingest::pacman::stream_data(
+                    &self.db,
+                    reader,
+                    &vendor,
+                    &package,
+                    &version,
+                    false,
+                    &self.release_keyring,
+                )
+                .await?;
 
                 self.db
                     .insert_package(&db::Package {
@@ -118,16 +325,18 @@ impl Worker {
                 package,
                 version,
                 url,
+                repo,
             } => {
                 info!("Downloading source rpm: {url:?}");
                 let reader = self.http.fetch(&url).await?;
 
                 ingest::rpm::stream_data(
-                    self.db.clone(),
+                    &self.db,
                     reader,
                     vendor.to_string(),
                     package.to_string(),
                     version.to_string(),
+                    repo,
                 )
                 .await?;
 
@@ -181,7 +390,50 @@ impl Worker {
             }
             TaskData::GitSnapshot { url } => {
                 let git = url.parse::<ingest::git::GitUrl>()?;
-                ingest::git::take_snapshot(&self.db, &git, &self.git_tmp).await?;
+                ingest::git::take_snapshot(
+                    &self.db,
+                    &git,
+                    &self.git_tmp,
+                    &self.git_cache,
+                    &self.git_keyring,
+                    self.http.rewrites(),
+                )
+                .await?;
+            }
+            TaskData::HgSnapshot { url } => {
+                let hg = url.parse::<ingest::hg::HgUrl>()?;
+                ingest::hg::take_snapshot(&self.db, &hg, &self.git_tmp, self.http.rewrites())
+                    .await?;
+            }
+            TaskData::SvnSnapshot { url } => {
+                let svn = url.parse::<ingest::svn::SvnUrl>()?;
+                ingest::svn::take_snapshot(&self.db, &svn, &self.git_tmp, self.http.rewrites())
+                    .await?;
+            }
+            TaskData::VerifySignature {
+                vendor,
+                url,
+                signature_url,
+                chksum,
+                keys,
+            } => {
+                let (digests, status, fingerprint) = ingest::signature::verify_detached(
+                    &self.http,
+                    &self.release_keyring,
+                    &self.git_tmp,
+                    &url,
+                    &signature_url,
+                    &keys,
+                )
+                .await?;
+                let signature = db::DetachedSignature {
+                    vendor,
+                    signature_url,
+                    chksum,
+                    keys,
+                };
+                self.record_signature(&digests, &signature, &url, status, fingerprint)
+                    .await?;
             }
             TaskData::IndexSbom { strain, chksum } => {
                 // Support old sbom task format
@@ -221,45 +473,223 @@ impl Worker {
                     })
                     .await?;
             }
+            TaskData::VoidLinuxCommit {
+                vendor,
+                commit,
+                packages,
+            } => {
+                debug!(
+                    "Void Linux: vendor={vendor:?} commit={commit:?} packages={}",
+                    packages.len()
+                );
+                let url =
+                    format!("https://github.com/void-linux/void-packages/archive/{commit}.tar.gz");
+
+                info!("Downloading Void Linux git snapshot: {url:?}");
+                let reader = self.http.fetch(&url).await?;
+                let srcpkgs = packages.iter().map(|p| p.srcpkg.as_str()).collect();
+                let templates = ingest::void::extract_templates(reader, &srcpkgs).await?;
+
+                for pkg in packages {
+                    if let Some(template) = templates.get(&pkg.srcpkg) {
+                        ingest::void::insert_template_refs(
+                            &self.db,
+                            template,
+                            &vendor,
+                            &pkg.package,
+                            &pkg.version,
+                        )
+                        .await?;
+                    } else {
+                        warn!("Template not found in Void Linux snapshot: srcpkg={:?} commit={commit:?}", pkg.srcpkg);
+                    }
+
+                    self.db
+                        .insert_package(&db::Package {
+                            vendor: vendor.clone(),
+                            package: pkg.package,
+                            version: pkg.version,
+                        })
+                        .await?;
+                }
+            }
         }
 
         Ok(())
     }
+
+    async fn keep_alive(&self, task: &Task) -> Error {
+        let mut interval = time::interval(TASK_LEASE_RENEW_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            debug!("Renewing lease of task: {:?}", task.key);
+            if let Err(err) = self.db.renew_task_lease(task, TASK_LEASE).await {
+                return err;
+            }
+        }
+    }
+
+    pub async fn work(&self) -> Result<()> {
+        loop {
+            if let Some(task) = self.db.claim_task(TASK_LEASE).await? {
+                info!("task={task:?}");
+                let result = tokio::select! {
+                    result = self.do_task(&task) => result,
+                    err = self.keep_alive(&task) => Err(err),
+                };
+                if let Err(err) = result {
+                    error!("Failed to process task: {err:#}");
+                    let msg = format!("{err:#}");
+                    if err.is_permanent() {
+                        warn!("Error is permanent, giving up on task: {:?}", task.key);
+                        self.db.fail_task(&task, &msg).await?;
+                    } else {
+                        self.db.bump_task_error_counter(&task, &msg).await?;
+                    }
+                } else if !self.db.delete_task(&task).await? {
+                    warn!(
+                        "Lease on task expired before it was completed: {:?}",
+                        task.key
+                    );
+                }
+            } else {
+                time::sleep(Duration::from_secs(60)).await;
+            }
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    }
 }
 
 pub async fn run(args: &args::Worker) -> Result<()> {
     let db = db::Client::create().await?;
-    let http = utils::http_client(args.socks5.as_ref())?;
+    let http = utils::http_client(args.socks5.as_ref())?.with_rewrites(&args.rewrites);
 
-    let worker = Worker {
+    let worker = Arc::new(Worker {
         db: Arc::new(db),
         http,
         git_tmp: args.git_tmp.to_string(),
-    };
+        git_cache: args.git_cache.clone(),
+        git_keyring: args.git_keyring.clone(),
+        release_keyring: args.release_keyring.clone(),
+    });
 
-    loop {
-        if let Some(task) = worker.db.get_random_task().await? {
-            info!("task={task:?}");
-            if let Err(err) = worker.do_task(&task).await {
-                error!("Failed to process task: {err:#}");
-                worker
-                    .db
-                    .bump_task_error_counter(&task, &format!("{err:#}"))
-                    .await?;
-            } else {
-                worker.db.delete_task(&task).await?;
-            }
-        } else {
-            time::sleep(Duration::from_secs(60)).await;
-        }
-        time::sleep(Duration::from_millis(50)).await;
+    let mut set = JoinSet::new();
+    for _ in 0..args.concurrency.max(1) {
+        let worker = worker.clone();
+        set.spawn(async move { worker.work().await });
+    }
+
+    // workers only return on fatal errors
+    if let Some(result) = set.join_next().await {
+        result??;
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn permanent_error() -> Error {
+        Error::ChecksumMismatch("sha256:00".to_string())
+    }
+
+    fn retryable_error() -> Error {
+        Error::Io(std::io::Error::other("connection reset"))
+    }
+
+    fn digests() -> Checksums {
+        Checksums {
+            sha256: "sha256:aa".to_string(),
+            sha512: "sha512:bb".to_string(),
+            blake2b: "blake2b:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_verify_published_checksums() {
+        let digests = digests();
+        let aliases = vec![
+            digests.sha512.clone(),
+            "md5:d41d8cd98f00b204e9800998ecf8427e".to_string(),
+        ];
+        assert!(verify_published_checksums(&digests, Some(&digests.sha256), &aliases).unwrap());
+        assert!(verify_published_checksums(&digests, None, &aliases).unwrap());
+        assert!(!verify_published_checksums(&digests, None, &aliases[1..]).unwrap());
+    }
+
+    #[test]
+    fn test_verify_published_checksums_mismatch() {
+        let digests = digests();
+        let err = verify_published_checksums(
+            &digests,
+            Some("sha256:00"),
+            std::slice::from_ref(&digests.sha512),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch(_)));
+
+        let err =
+            verify_published_checksums(&digests, Some(&digests.sha256), &["sha512:00".to_string()])
+                .unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch(_)));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_primary() {
+        let (url, value) = fetch_with_mirrors(
+            "https://example.com/a.tar.gz".to_string(),
+            vec!["https://mirror.example.com/a.tar.gz".to_string()],
+            |url| async move { Ok(url.len()) },
+        )
+        .await
+        .unwrap();
+        assert_eq!(url, "https://example.com/a.tar.gz");
+        assert_eq!(value, 28);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_fallback_to_mirror() {
+        let (url, value) = fetch_with_mirrors(
+            "https://example.com/a.tar.gz".to_string(),
+            vec![
+                "https://mirror1.example.com/a.tar.gz".to_string(),
+                "https://mirror2.example.com/a.tar.gz".to_string(),
+            ],
+            |url| async move {
+                if url.starts_with("https://mirror2.") {
+                    Ok("data")
+                } else {
+                    Err(retryable_error())
+                }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(url, "https://mirror2.example.com/a.tar.gz");
+        assert_eq!(value, "data");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_prefer_retryable_error() {
+        let err = fetch_with_mirrors(
+            "https://example.com/a.tar.gz".to_string(),
+            vec!["https://mirror.example.com/a.tar.gz".to_string()],
+            |url| async move {
+                if url.starts_with("https://mirror.") {
+                    Err::<(), _>(retryable_error())
+                } else {
+                    Err(permanent_error())
+                }
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(!err.is_permanent());
+    }
+
     #[test]
     fn test_normalize_gtk2_extra() {
         let repo = normalize_archlinux_gitlab_names("gtk2+extra");
@@ -138,7 +138,7 @@ impl BitBake {
                         sha256,
                     });
                 }
-                Some(("git", tail)) => {
+                Some((scheme @ ("git" | "gitsm"), tail)) => {
                     let commit = self.get_var(if let Some(name) = name {
                         format!("SRCREV_{name}")
                     } else {
@@ -146,7 +146,7 @@ impl BitBake {
                     })?;
 
                     out.push(Artifact {
-                        src: format!("git+https://{tail}"),
+                        src: format!("{scheme}+https://{tail}"),
                         commit,
                         sha256: None,
                     });